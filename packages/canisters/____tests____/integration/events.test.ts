@@ -9,10 +9,12 @@ import {
 	ACTOR_ONE,
 	ACTOR_TWO,
 	ADJUST_AMOUNT,
+	FINALITY_DEPTH,
 	SAMPLE_ADJUST_EVENTS,
 	SAMPLE_ADJUST_EVENTS_NOT_RESOLVES_TO_ZERO,
 	SAMPLE_CANCEL_EVENT,
 	SAMPLE_DEPOSIT_EVENT,
+	SAMPLE_REORGED_DEPOSIT_EVENT,
 	SAMPLE_WITHDRAW_DETAILS,
 	SAMPLE_WITHDRAW_EVENT,
 } from '../utils/constants';
@@ -20,6 +22,7 @@ import {
 	getDCCanister,
 	getPDCCanister,
 	getRemittanceCanister,
+	waitForDeliveries,
 } from '../utils/functions';
 
 (BigInt.prototype as any).toJSON = function () {
@@ -35,27 +38,40 @@ describe('PUB/SUB', () => {
 
 	async function getAvailableBalance(account: string) {
 		// confirm balance
-		const { balance: availableBalance } =
-			await R_CANISTER.get_available_balance(
-				SAMPLE_DEPOSIT_EVENT.token,
-				SAMPLE_DEPOSIT_EVENT.chain,
-				account,
-				Principal.from(SAMPLE_DEPOSIT_EVENT.canister_id),
-			);
+		const availableResponse = await R_CANISTER.get_available_balance(
+			SAMPLE_DEPOSIT_EVENT.token,
+			SAMPLE_DEPOSIT_EVENT.chain,
+			account,
+			Principal.from(SAMPLE_DEPOSIT_EVENT.canister_id),
+		);
+		if (!('Ok' in availableResponse)) throw new Error(Object.keys(availableResponse.Err)[0]);
+
+		return availableResponse.Ok.balance;
+	}
+
+	async function getPendingBalance(account: string) {
+		const pendingResponse = await R_CANISTER.get_pending_balance(
+			SAMPLE_DEPOSIT_EVENT.token,
+			SAMPLE_DEPOSIT_EVENT.chain,
+			account,
+			Principal.from(SAMPLE_DEPOSIT_EVENT.canister_id),
+		);
+		if (!('Ok' in pendingResponse)) throw new Error(Object.keys(pendingResponse.Err)[0]);
 
-		return availableBalance;
+		return pendingResponse.Ok.balance;
 	}
 
 	async function getWitheldBalance() {
 		// confirm balance
-		const { balance: availableBalance } = await R_CANISTER.get_withheld_balance(
+		const withheldResponse = await R_CANISTER.get_withheld_balance(
 			SAMPLE_DEPOSIT_EVENT.token,
 			SAMPLE_DEPOSIT_EVENT.chain,
 			SAMPLE_DEPOSIT_EVENT.account,
 			Principal.from(SAMPLE_DEPOSIT_EVENT.canister_id),
 		);
+		if (!('Ok' in withheldResponse)) throw new Error(Object.keys(withheldResponse.Err)[0]);
 
-		return availableBalance;
+		return withheldResponse.Ok.balance;
 	}
 
 	beforeAll(async () => {
@@ -65,15 +81,19 @@ describe('PUB/SUB', () => {
 		const { canister: rCanister, id: rCanisterId } = getRemittanceCanister();
 
 		// register teh remittance canister in both dc and pdc
-		await dcCanister.set_remittance_canister(Principal.from(rCanisterId));
-		await pdcCanister.set_remittance_canister(Principal.from(rCanisterId));
+		await dcCanister.add_subscriber(Principal.from(rCanisterId));
+		await pdcCanister.add_subscriber(Principal.from(rCanisterId));
 
 		// subscribe to the dc and pdc canister from the remittance canister
-		await rCanister.subscribe_to_dc(Principal.from(dcCanisterId));
-		await rCanister.subscribe_to_pdc(Principal.from(pdcCanisterId));
+		await rCanister.subscribe_to_dc(Principal.from(dcCanisterId), []);
+		await rCanister.subscribe_to_pdc(Principal.from(pdcCanisterId), []);
+		// the sample withdrawal is authorized by a proof which only signs the amount
+		await rCanister.set_allow_legacy_proofs(true);
 
 		// get the public key of the canister
-		const canisterPk = (await rCanister.public_key()).etherum_pk;
+		const publicKeyResponse = await rCanister.public_key([], [], []);
+		if (!('Ok' in publicKeyResponse)) throw new Error(Object.keys(publicKeyResponse.Err)[0]);
+		const canisterPk = publicKeyResponse.Ok.etherum_pk;
 
 		// save all the canisters fro future use
 		DC_CANISTER = dcCanister;
@@ -84,29 +104,91 @@ describe('PUB/SUB', () => {
 
 	it('The PDC Canister can deposit funds to the Remittance Canister', async () => {
 		// simulate a deposit event
-		await PDC_CANISTER.manual_publish(JSON.stringify([SAMPLE_DEPOSIT_EVENT]));
+		await PDC_CANISTER.manual_publish(JSON.stringify([SAMPLE_DEPOSIT_EVENT]), []);
+		await waitForDeliveries(PDC_CANISTER);
+
+		// the deposit can't be spent until its block is final
+		expect((await getAvailableBalance(ACTOR_ONE)).toString()).toEqual('0');
+		expect((await getPendingBalance(ACTOR_ONE)).toString()).toEqual(
+			SAMPLE_DEPOSIT_EVENT.amount.toString(),
+		);
+
+		const confirmResponse = await PDC_CANISTER.confirm_block(
+			SAMPLE_DEPOSIT_EVENT.chain,
+			SAMPLE_DEPOSIT_EVENT.block_hash,
+			BigInt(FINALITY_DEPTH),
+		);
+		if (!('Ok' in confirmResponse)) throw new Error(Object.keys(confirmResponse.Err)[0]);
 		const availableBalance = await getAvailableBalance(ACTOR_ONE);
 		expect(availableBalance.toString()).toEqual(
 			SAMPLE_DEPOSIT_EVENT.amount.toString(),
 		);
+		expect((await getPendingBalance(ACTOR_ONE)).toString()).toEqual('0');
+	});
+
+	it('A deposit whose block is orphaned before it is final is never credited', async () => {
+		const initialAvailableBalance = await getAvailableBalance(ACTOR_ONE);
+
+		await PDC_CANISTER.manual_publish(JSON.stringify([SAMPLE_REORGED_DEPOSIT_EVENT]), []);
+		await waitForDeliveries(PDC_CANISTER);
+		expect((await getPendingBalance(ACTOR_ONE)).toString()).toEqual(
+			SAMPLE_REORGED_DEPOSIT_EVENT.amount.toString(),
+		);
+
+		const orphanResponse = await PDC_CANISTER.orphan_block(
+			SAMPLE_REORGED_DEPOSIT_EVENT.chain,
+			SAMPLE_REORGED_DEPOSIT_EVENT.block_hash,
+		);
+		if (!('Ok' in orphanResponse)) throw new Error(Object.keys(orphanResponse.Err)[0]);
+
+		expect((await getPendingBalance(ACTOR_ONE)).toString()).toEqual('0');
+		expect((await getAvailableBalance(ACTOR_ONE)).toString()).toEqual(
+			initialAvailableBalance.toString(),
+		);
+	});
+
+	it('A deposit which is published again is only credited once', async () => {
+		const initialAvailableBalance = await getAvailableBalance(ACTOR_ONE);
+
+		// replay the deposit which was credited by the previous test
+		await PDC_CANISTER.manual_publish(JSON.stringify([SAMPLE_DEPOSIT_EVENT]), []);
+		await waitForDeliveries(PDC_CANISTER);
+
+		const availableBalance = await getAvailableBalance(ACTOR_ONE);
+		expect(availableBalance.toString()).toEqual(
+			initialAvailableBalance.toString(),
+		);
+		const isProcessed = await R_CANISTER.is_event_processed({
+			chain: {
+				namespace: SAMPLE_DEPOSIT_EVENT.chain.split(':')[0],
+				reference: SAMPLE_DEPOSIT_EVENT.chain.split(':')[1],
+			},
+			transaction_hash: SAMPLE_DEPOSIT_EVENT.transaction_hash,
+			log_index: SAMPLE_DEPOSIT_EVENT.log_index,
+			block_hash: SAMPLE_DEPOSIT_EVENT.block_hash,
+		});
+		expect(isProcessed).toBe(true);
 	});
 
 	it('The remittance canister can generate a correct signature which can be used to withdraw funds', async () => {
 		const initialAvailableBalance = await getAvailableBalance(ACTOR_ONE);
 		// try to generate an event from the address used to as recipient from the deposit event
-		let {
-			signature: canisterSignature,
-			hash: dataHash,
-			nonce,
-			amount: withdrawalAmount,
-		} = await R_CANISTER.remit(
+		const remitResponse = await R_CANISTER.remit(
 			SAMPLE_DEPOSIT_EVENT.token,
 			SAMPLE_DEPOSIT_EVENT.chain,
 			SAMPLE_DEPOSIT_EVENT.account,
 			Principal.from(SAMPLE_DEPOSIT_EVENT.canister_id),
 			BigInt(SAMPLE_WITHDRAW_DETAILS.amount),
 			SAMPLE_WITHDRAW_DETAILS.signature,
+			[],
 		);
+		if (!('Ok' in remitResponse)) throw new Error(Object.keys(remitResponse.Err)[0]);
+		let {
+			signature: canisterSignature,
+			hash: dataHash,
+			nonce,
+			amount: withdrawalAmount,
+		} = remitResponse.Ok;
 
 		NONCE = nonce;
 		// validate the signature produced
@@ -130,6 +212,10 @@ describe('PUB/SUB', () => {
 
 		const preAvailableBalance = await getAvailableBalance(ACTOR_ONE);
 		const preWitheldBalance = await getWitheldBalance();
+		// the remittance is kept as a signed ticket until it is withdrawn
+		const [ticket] = await R_CANISTER.get_withdrawal_ticket(nonce);
+		expect(ticket?.signature).toEqual([canisterSignature]);
+		expect(ticket?.hash).toEqual(dataHash);
 		// validate the hash returned by the canister is the same as the hash calculated based on the values
 		expect(derivedDataHash.toLowerCase()).toEqual(dataHash.toLowerCase());
 		// validate that the address recovered from the signature and message is that of the r-canister
@@ -148,12 +234,18 @@ describe('PUB/SUB', () => {
 		// generate a withdraw confirm payload and send to nthe r canister, then confirm the balance of the user has reduced by the amount withdrawn
 		// simulate a withdraw event
 		// TODO send a response back to the client for if it was successfull or not
-		await PDC_CANISTER.manual_publish(JSON.stringify([SAMPLE_WITHDRAW_EVENT]));
+		// the withdrawal event carries the nonce of the ticket it resolves
+		await PDC_CANISTER.manual_publish(
+			JSON.stringify([{ ...SAMPLE_WITHDRAW_EVENT, nonce }]),
+			[],
+		);
+		await waitForDeliveries(PDC_CANISTER);
 		const postAvailableBalance = await getAvailableBalance(ACTOR_ONE);
 		const postWitheldBalance = await getWitheldBalance();
 
 		// validate that the balances are as they should be
 		expect(postWitheldBalance.toString()).toEqual('0'); //make sure the witheld balance is now 0
+		expect(await R_CANISTER.get_withdrawal_ticket(nonce)).toEqual([]); // the withdrawal resolved the ticket
 		expect(preAvailableBalance.toString()).toEqual(
 			postAvailableBalance.toString(),
 		); // confirm both of the available balances are the same before and after withdrawal confirmation
@@ -167,19 +259,26 @@ describe('PUB/SUB', () => {
 		const initialAvailableBalance = await getAvailableBalance(ACTOR_ONE);
 
 		// try to generate an event from the address used to as recipient from the deposit event
-		await R_CANISTER.remit(
+		const remitResponse = await R_CANISTER.remit(
 			SAMPLE_DEPOSIT_EVENT.token,
 			SAMPLE_DEPOSIT_EVENT.chain,
 			SAMPLE_DEPOSIT_EVENT.account,
 			Principal.from(SAMPLE_DEPOSIT_EVENT.canister_id),
 			BigInt(SAMPLE_WITHDRAW_DETAILS.amount),
 			SAMPLE_WITHDRAW_DETAILS.signature,
+			[],
 		);
+		if (!('Ok' in remitResponse)) throw new Error(Object.keys(remitResponse.Err)[0]);
+		const { nonce } = remitResponse.Ok;
 
 		const preAvailableBalance = await getAvailableBalance(ACTOR_ONE);
 		const preWitheldBalance = await getWitheldBalance();
 		// generate a cancel withdraw event
-		await PDC_CANISTER.manual_publish(JSON.stringify([SAMPLE_CANCEL_EVENT]));
+		await PDC_CANISTER.manual_publish(
+			JSON.stringify([{ ...SAMPLE_CANCEL_EVENT, nonce }]),
+			[],
+		);
+		await waitForDeliveries(PDC_CANISTER);
 		// check balances again
 		const postAvailableBalance = await getAvailableBalance(ACTOR_ONE);
 		const postWitheldBalance = await getWitheldBalance();
@@ -193,10 +292,12 @@ describe('PUB/SUB', () => {
 	});
 
 	it('Can get the reciept of a succesfull withdrawal', async () => {
-		const { token, chain, account, amount } = await R_CANISTER.get_reciept(
+		const [reciept] = await R_CANISTER.get_reciept(
 			Principal.from(SAMPLE_DEPOSIT_EVENT.canister_id),
 			NONCE,
 		);
+		if (!reciept) throw new Error('RECIEPT_NOT_FOUND');
+		const { token, chain, account, amount } = reciept;
 
 		expect(token.toLowerCase()).toEqual(
 			SAMPLE_DEPOSIT_EVENT.token.toLowerCase(),
@@ -212,7 +313,7 @@ describe('PUB/SUB', () => {
 		const availableBalanceActorOnePre = await getAvailableBalance(ACTOR_ONE);
 		const availableBalanceActorTwoPre = await getAvailableBalance(ACTOR_TWO);
 		// simulate a deposit event
-		await DC_CANISTER.manual_publish(JSON.stringify([...SAMPLE_ADJUST_EVENTS]));
+		await DC_CANISTER.manual_publish(JSON.stringify([...SAMPLE_ADJUST_EVENTS]), []);
 		const availableBalanceActorOnePost = await getAvailableBalance(ACTOR_ONE);
 		const availableBalanceActorTwoPost = await getAvailableBalance(ACTOR_TWO);
 
@@ -230,6 +331,7 @@ describe('PUB/SUB', () => {
 		// simulate a deposit event
 		await DC_CANISTER.manual_publish(
 			JSON.stringify([...SAMPLE_ADJUST_EVENTS_NOT_RESOLVES_TO_ZERO]),
+			[],
 		);
 		const availableBalanceActorOnePost = await getAvailableBalance(ACTOR_ONE);
 		const availableBalanceActorTwoPost = await getAvailableBalance(ACTOR_TWO);
@@ -238,4 +340,17 @@ describe('PUB/SUB', () => {
 		expect(availableBalanceActorOnePost.toString()).toEqual(availableBalanceActorOnePre.toString());
 		expect(availableBalanceActorTwoPost.toString()).toEqual(availableBalanceActorTwoPre.toString());
 	});
+
+	it('The balances of the Remittance Canister reconcile once the events are applied', async () => {
+		const report = await R_CANISTER.reconcile();
+
+		expect(report.assets_checked > BigInt(0)).toBe(true);
+		expect(report.discrepancies).toEqual([]);
+		// the journal is replayed a batch of entries per call until its end is reached
+		let verification = await R_CANISTER.verify_ledger(BigInt(1000));
+		while ('Ok' in verification && verification.Ok.consistent.length === 0) {
+			verification = await R_CANISTER.verify_ledger(BigInt(1000));
+		}
+		expect(verification).toEqual({ Ok: { next_id: expect.any(BigInt), consistent: [true] } });
+	});
 });
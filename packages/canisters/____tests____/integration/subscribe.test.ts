@@ -12,8 +12,8 @@ describe('PUB/SUB', () => {
 		const { canister: rCanister, id: rCanisterId } = getRemittanceCanister();
 
 		// use the remittance canister to subscribe to the DC canister
-		await dcCanister.set_remittance_canister(Principal.from(rCanisterId));
-		await rCanister.subscribe_to_dc(Principal.from(dcCanisterId));
+		await dcCanister.add_subscriber(Principal.from(rCanisterId));
+		await rCanister.subscribe_to_dc(Principal.from(dcCanisterId), []);
 		// check from the dc canister if the subscription was succesfull
 		const response = await dcCanister.is_subscribed(
 			Principal.from(rCanisterId)
@@ -27,8 +27,8 @@ describe('PUB/SUB', () => {
 		const { canister: rCanister, id: rCanisterId } = getRemittanceCanister();
 
 		// use the remittance canister to subscribe to the DC canister
-		await pdcCanister.set_remittance_canister(Principal.from(rCanisterId));
-		await rCanister.subscribe_to_pdc(Principal.from(pdcCanisterId));
+		await pdcCanister.add_subscriber(Principal.from(rCanisterId));
+		await rCanister.subscribe_to_pdc(Principal.from(pdcCanisterId), []);
 		// check from the dc canister if the subscription was succesfull
 		const response = await pdcCanister.is_subscribed(
 			Principal.from(rCanisterId)
@@ -36,4 +36,24 @@ describe('PUB/SUB', () => {
 		// confirm from the dc canister if the r-canister is subscribed to it
 		expect(response).toBe(true);
 	});
+
+	it('A subscriber should only be registered for the events matching its filter', async () => {
+		const { canister: dcCanister, id: dcCanisterId } = getDCCanister();
+		const { canister: rCanister, id: rCanisterId } = getRemittanceCanister();
+		const filter = {
+			actions: [{ Deposit: null }],
+			chains: [{ namespace: 'ethereum', reference: '5' }],
+			tokens: [],
+		};
+
+		await dcCanister.add_subscriber(Principal.from(rCanisterId));
+		await rCanister.subscribe_to_dc(Principal.from(dcCanisterId), [filter]);
+
+		const subscription = (await dcCanister.get_subscribers()).find(
+			(subscriber) =>
+				subscriber.canister_principal.toText() === rCanisterId.toString()
+		);
+		expect(subscription?.topic).toEqual(['REMITTANCE']);
+		expect(subscription?.filter).toEqual(filter);
+	});
 });
@@ -25,8 +25,8 @@ describe('Data Collection Canister', () => {
 		const { canister: rCanister, id: rCanisterId } = getRemittanceCanister();
 
 		// register teh remittance canister in both dc and pdc
-		await dcCanister.set_remittance_canister(Principal.from(rCanisterId));
-		await pdcCanister.set_remittance_canister(Principal.from(rCanisterId));
+		await dcCanister.add_subscriber(Principal.from(rCanisterId));
+		await pdcCanister.add_subscriber(Principal.from(rCanisterId));
 
 		// save all the canisters fro future use
 		DC_CANISTER = dcCanister;
@@ -41,12 +41,12 @@ describe('Data Collection Canister', () => {
 		expect(response).toBe(CANISTER_NAME);
 	});
 
-	test('It can set remittance canister', async () => {
-		await DC_CANISTER.set_remittance_canister(Principal.from(R_CANISTER_ID));
+	test('It can whitelist a subscriber', async () => {
+		await DC_CANISTER.add_subscriber(Principal.from(R_CANISTER_ID));
 
-		const rCanisterDetails = await DC_CANISTER.get_remittance_canister();
-		expect(rCanisterDetails.canister_principal).toEqual(
-			Principal.from(R_CANISTER_ID),
-		);
+		const subscribers = await DC_CANISTER.get_subscribers();
+		expect(
+			subscribers.map((subscription) => subscription.canister_principal),
+		).toContainEqual(Principal.from(R_CANISTER_ID));
 	});
 });
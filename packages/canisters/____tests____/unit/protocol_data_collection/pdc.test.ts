@@ -25,8 +25,8 @@ describe('Protocol Data Collection Canister', () => {
 		const { canister: rCanister, id: rCanisterId } = getRemittanceCanister();
 
 		// register teh remittance canister in both dc and pdc
-		await dcCanister.set_remittance_canister(Principal.from(rCanisterId));
-		await pdcCanister.set_remittance_canister(Principal.from(rCanisterId));
+		await dcCanister.add_subscriber(Principal.from(rCanisterId));
+		await pdcCanister.add_subscriber(Principal.from(rCanisterId));
 
 		// save all the canisters fro future use
 		DC_CANISTER = dcCanister;
@@ -40,13 +40,13 @@ describe('Protocol Data Collection Canister', () => {
 		expect(response).toBe(CANISTER_NAME);
 	});
 
-	test('It can set remittance canister', async () => {
-		await PDC_CANISTER.set_remittance_canister(Principal.from(R_CANISTER_ID));
+	test('It can whitelist a subscriber', async () => {
+		await PDC_CANISTER.add_subscriber(Principal.from(R_CANISTER_ID));
 
-		const rCanisterDetails = await PDC_CANISTER.get_remittance_canister();
-		expect(rCanisterDetails.canister_principal).toEqual(
-			Principal.from(R_CANISTER_ID),
-		);
+		const subscribers = await PDC_CANISTER.get_subscribers();
+		expect(
+			subscribers.map((subscription) => subscription.canister_principal),
+		).toContainEqual(Principal.from(R_CANISTER_ID));
 	});
 
 	test('It can set logstore credentials', async () => {
@@ -68,4 +68,30 @@ describe('Protocol Data Collection Canister', () => {
 		expect(gottenQueryURL).toEqual(SAMPLE_QUERY_URL);
 		expect(gottenQueryToken).toEqual(SAMPLE_QUERY_TOKEN);
 	});
+
+	test('It can grant and revoke roles', async () => {
+		const publisherManager = Principal.from(R_CANISTER_ID);
+
+		const granted = await PDC_CANISTER.grant_role(
+			{ PublisherManager: null },
+			publisherManager,
+		);
+		expect('Ok' in granted).toBe(true);
+		expect(await PDC_CANISTER.get_roles(publisherManager)).toEqual([
+			{ PublisherManager: null },
+		]);
+
+		const revoked = await PDC_CANISTER.revoke_role(
+			{ PublisherManager: null },
+			publisherManager,
+		);
+		expect('Ok' in revoked).toBe(true);
+		expect(await PDC_CANISTER.get_roles(publisherManager)).toEqual([]);
+	});
+
+	test('Only the proposed owner can accept the ownership', async () => {
+		const response = await PDC_CANISTER.accept_ownership();
+
+		expect('Err' in response && 'NotPendingOwner' in response.Err).toBe(true);
+	});
 });
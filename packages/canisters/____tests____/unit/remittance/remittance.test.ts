@@ -12,15 +12,42 @@ describe('Remittance Canister', () => {
 	});
 
 	test('It can generate public key', async () => {
-		const response = await canister.public_key();
-		if (!('Ok' in response)) return;
+		const response = await canister.public_key([], [], []);
+		if (!('Ok' in response)) throw new Error(Object.keys(response.Err)[0]);
 
-		const { sec1_pk: sec1PK, etherum_pk: ethereumPK } = response;
+		const { sec1_pk: sec1PK, etherum_pk: ethereumPK } = response.Ok;
 		const computedEthereumPK = utils.computeAddress(`0x${sec1PK}`);
 
 		expect(ethereumPK.toLowerCase()).toEqual(computedEthereumPK.toLowerCase());
 	});
 
+	test('It can pause and unpause remit', async () => {
+		await canister.pause({ Remit: null }, 'test');
+		expect(await canister.get_paused_operations()).toEqual([{ Remit: null }]);
+
+		await canister.unpause({ Remit: null }, 'test');
+		expect(await canister.get_paused_operations()).toEqual([]);
+
+		const history = await canister.get_pause_history();
+		expect(history.slice(-2).map(({ paused }) => paused)).toEqual([true, false]);
+	});
+
+	test('It records privileged calls in the audit log', async () => {
+		await canister.pause({ Remit: null }, 'audit');
+		await canister.unpause({ Remit: null }, 'audit');
+
+		const response = await canister.get_audit_log(
+			[{ caller: [], method: ['unpause'], from: [], to: [] }],
+			BigInt(0),
+			BigInt(100),
+		);
+		if (!('Ok' in response)) throw new Error(Object.keys(response.Err)[0]);
+
+		const [entry] = response.Ok.entries.slice(-1);
+		expect(entry.method).toEqual('unpause');
+		expect('Success' in entry.outcome).toBe(true);
+	});
+
 	test("It", async () => {
 
 	});
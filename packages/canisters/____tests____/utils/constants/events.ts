@@ -10,6 +10,8 @@ export const CHAIN = 'ethereum:5';
 export const DEPOSIT_AMOUNT = 500000;
 export const WITHDRAW_AMOUNT = 100000;
 export const ADJUST_AMOUNT = 10000;
+// the number of confirmations after which a block of the chain is final
+export const FINALITY_DEPTH = 64;
 
 export const SAMPLE_DEPOSIT_EVENT = {
 	event_name: 'FundsDeposited',
@@ -18,6 +20,16 @@ export const SAMPLE_DEPOSIT_EVENT = {
 	amount: DEPOSIT_AMOUNT,
 	chain: CHAIN,
 	token: TOKEN,
+	transaction_hash: '0x1111111111111111111111111111111111111111111111111111111111111111',
+	block_hash: '0xfffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff1',
+	log_index: 0,
+};
+
+// a deposit whose block is orphaned by a reorg before it is final
+export const SAMPLE_REORGED_DEPOSIT_EVENT = {
+	...SAMPLE_DEPOSIT_EVENT,
+	transaction_hash: '0x4444444444444444444444444444444444444444444444444444444444444444',
+	block_hash: '0xfffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff4',
 };
 
 export const SAMPLE_WITHDRAW_DETAILS = {
@@ -27,6 +39,7 @@ export const SAMPLE_WITHDRAW_DETAILS = {
 	amount: WITHDRAW_AMOUNT,
 };
 
+// published along with the nonce of the remittance it resolves
 export const SAMPLE_WITHDRAW_EVENT = {
 	event_name: 'FundsWithdrawn',
 	canister_id: CANISTER_ID,
@@ -34,8 +47,12 @@ export const SAMPLE_WITHDRAW_EVENT = {
 	amount: WITHDRAW_AMOUNT,
 	chain: CHAIN,
 	token: TOKEN,
+	transaction_hash: '0x2222222222222222222222222222222222222222222222222222222222222222',
+	block_hash: '0xfffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff2',
+	log_index: 0,
 };
 
+// published along with the nonce of the remittance it resolves
 export const SAMPLE_CANCEL_EVENT = {
 	event_name: 'WithdrawCanceled',
 	canister_id: CANISTER_ID,
@@ -43,6 +60,9 @@ export const SAMPLE_CANCEL_EVENT = {
 	amount: WITHDRAW_AMOUNT,
 	chain: CHAIN,
 	token: TOKEN,
+	transaction_hash: '0x3333333333333333333333333333333333333333333333333333333333333333',
+	block_hash: '0xfffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff3',
+	log_index: 0,
 };
 
 export const SAMPLE_ADJUST_EVENTS = [
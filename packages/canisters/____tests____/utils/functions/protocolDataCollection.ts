@@ -16,3 +16,18 @@ export function getPDCCanister({ agent } = { agent: getAgent() }) {
 
 	return { canister: PDCCanister, id: effectiveCanisterId };
 }
+
+// the events published by the PDC are delivered from its outbox by a timer
+// wait until every subscriber acknowledged them
+export async function waitForDeliveries(
+	canister: ReturnType<typeof getPDCCanister>['canister'],
+	{ attempts, interval } = { attempts: 20, interval: 500 },
+) {
+	for (let attempt = 0; attempt < attempts; attempt++) {
+		const pending = await canister.get_outbox();
+		if (pending.length === 0) return;
+		await new Promise((resolve) => setTimeout(resolve, interval));
+	}
+
+	throw new Error('OUTBOX_NOT_DELIVERED');
+}
@@ -1,7 +1,12 @@
 use candid::Principal;
-use ic_cdk::storage;
+use ic_cdk::caller;
 use ic_cdk_macros::*;
-use lib::{dc::publish_json_to_remittance, RemittanceSubscriber};
+use lib::{
+    audit::{AuditFilter, AuditPage},
+    dc::{publish_json_to_remittance, Subscription},
+    owner::{AccessControl, Role},
+    validation::{BatchMode, ValidationReport},
+};
 
 // @dev testing command
 #[query]
@@ -19,26 +24,80 @@ fn owner() -> String {
     lib::owner::get_owner()
 }
 
+#[query]
+fn get_access_control() -> AccessControl {
+    lib::owner::get_access_control()
+}
+
+#[query]
+fn get_roles(principal: Principal) -> Vec<Role> {
+    lib::owner::get_roles(principal)
+}
+
+#[update]
+fn grant_role(role: Role, principal: Principal) -> Result<(), lib::CcampError> {
+    lib::owner::grant_role(role, principal)
+}
+
 #[update]
-pub async fn set_remittance_canister(remittance_principal: Principal) {
-    lib::owner::only_owner();
+fn revoke_role(role: Role, principal: Principal) -> Result<(), lib::CcampError> {
+    lib::owner::revoke_role(role, principal)
+}
 
-    lib::dc::set_remittance_canister(remittance_principal);
+#[update]
+fn propose_owner(new_owner: Option<Principal>) -> Result<(), lib::CcampError> {
+    lib::owner::propose_owner(new_owner)
+}
+
+#[update]
+fn accept_ownership() -> Result<(), lib::CcampError> {
+    lib::owner::accept_ownership()
 }
 
+// the privileged calls made to this canister, only readable by auditors
 #[query]
-pub fn get_remittance_canister() -> RemittanceSubscriber {
-    // confirm at least one remittance canister is subscribed to this pdc
-    lib::dc::get_remittance_canister()
+fn get_audit_log(
+    filter: Option<AuditFilter>,
+    start: u64,
+    limit: u64,
+) -> Result<AuditPage, lib::CcampError> {
+    lib::audit::get_page(filter, start, limit)
+}
+
+// whitelist a canister which can then subscribe to the events of this canister
+#[update]
+pub fn add_subscriber(canister_principal: Principal) -> Result<(), lib::CcampError> {
+    let result = lib::owner::ensure_role(Role::Admin).map(|_| {
+        lib::dc::add_subscriber(canister_principal);
+    });
+    lib::audit::record("add_subscriber", &(canister_principal,), &result);
+
+    result
+}
+
+#[update]
+pub fn remove_subscriber(canister_principal: Principal) -> Result<(), lib::CcampError> {
+    let result = lib::owner::ensure_role(Role::Admin).map(|_| {
+        lib::dc::remove_subscriber(canister_principal);
+    });
+    lib::audit::record("remove_subscriber", &(canister_principal,), &result);
+
+    result
+}
+
+// the whitelisted canisters along with their subscription and delivery status
+#[query]
+pub fn get_subscribers() -> Vec<Subscription> {
+    lib::dc::get_subscribers()
 }
 
 // this function is going to be called by the remittance canister
 // so it can recieve "publish" events from this canister
 #[update]
-fn subscribe() {
-    // verify if this remittance canister has been whitelisted
-    // set the subscribed value to true if its the same, otherwise panic
-    lib::dc::subscribe();
+fn subscribe(subscriber: lib::Subscriber) -> Result<(), lib::CcampError> {
+    // verify if this canister has been whitelisted
+    // and save the topic and filter of the events it wants to recieve
+    lib::dc::subscribe(subscriber)
 }
 
 #[query]
@@ -49,26 +108,63 @@ fn is_subscribed(canister_principal: Principal) -> bool {
 // we would use this method to publish data to the subscriber
 // which would be the remittance model
 // so when we have some new data, we would publish it to the remittance model
+// the optional mode decides if a batch containing invalid events is rejected entirely
+// or if only its valid events are applied, by default the whole batch is rejected
 #[update]
-async fn manual_publish(json_data: String) -> Result<(), String> {
+async fn manual_publish(
+    json_data: String,
+    mode: Option<BatchMode>,
+) -> Result<ValidationReport, lib::CcampError> {
     // create a dummy remittance object we can publish until we implement data collection
     // which would then generate the data instead of hardcoding it
-    let response = publish_json_to_remittance(json_data).await;
+    let response = match lib::owner::ensure_role(Role::Operator) {
+        Ok(()) => {
+            publish_json_to_remittance(json_data.clone(), mode.clone().unwrap_or_default()).await
+        }
+        Err(error) => Err(error),
+    };
+    lib::audit::record("manual_publish", &(json_data, mode), &response);
 
     response
 }
 
 // --------------------------- upgrade hooks ------------------------- //
+// the version of the layout saved by `pre_upgrade`
+const STATE_VERSION: u32 = 1;
+// the stores saved across upgrades
+type StableState = (
+    lib::dc::SubscriberStore,
+    AccessControl,
+    lib::audit::AuditLog,
+);
+
 #[pre_upgrade]
 fn pre_upgrade() {
-    let cloned_store = lib::dc::REMITTANCE_CANISTER.with(|rc| rc.borrow().clone());
-    storage::stable_save((cloned_store,)).unwrap()
+    let cloned_store = lib::dc::SUBSCRIBERS.with(|store| store.borrow().clone());
+    let access_control = lib::owner::get_access_control();
+    let audit_log = lib::audit::get_log();
+    lib::upgrade::save(STATE_VERSION, (cloned_store, access_control, audit_log)).unwrap()
 }
 #[post_upgrade]
 async fn post_upgrade() {
-    init().await;
-
-    let (old_store,): (Option<lib::RemittanceSubscriber>,) = storage::stable_restore().unwrap();
-    lib::dc::REMITTANCE_CANISTER.with(|store| *store.borrow_mut() = old_store);
+    let bytes = lib::upgrade::saved_bytes();
+    let restored: Result<StableState, String> = match lib::upgrade::version(&bytes) {
+        Some(_) => lib::upgrade::decode_versioned(&bytes, STATE_VERSION),
+        // the subscriber saved by the first version
+        None => lib::upgrade::decode(&bytes).map(
+            |(subscriber,): (Option<lib::upgrade::RemittanceSubscriber>,)| {
+                (
+                    lib::upgrade::subscriber_store(subscriber),
+                    lib::upgrade::legacy_access_control(caller()),
+                    lib::audit::AuditLog::default(),
+                )
+            },
+        ),
+    };
+    let (old_store, access_control, audit_log) =
+        restored.unwrap_or_else(|error| ic_cdk::trap(&error));
+    lib::dc::SUBSCRIBERS.with(|store| *store.borrow_mut() = old_store);
+    lib::owner::restore_access_control(access_control);
+    lib::audit::restore_log(audit_log);
 }
 // --------------------------- upgrade hooks ------------------------- //
@@ -1 +1 @@
-pub const CHAIN_IDENTIFIER:&str = "ethereum:137";
+pub const CHAIN_IDENTIFIER:&str = "polygon:137";
@@ -1,15 +1,20 @@
 use std::cell::RefCell;
 
-use candid::Principal;
-use ic_cdk::storage;
+use candid::{Nat, Principal};
 use ic_cdk_macros::*;
 use lib::{
-    constants::ZERO_ADDRESS, ethereum::recover_address_from_eth_signature, RemittanceSubscriber,
+    audit::{AuditFilter, AuditPage},
+    constants::ZERO_ADDRESS,
+    dc::Subscription,
+    owner::{AccessControl, Role},
+    pause::{Operation, PauseRecord},
+    validation::{BatchMode, ValidationReport},
+    CcampError,
 };
 
 use utils::{
     burn_tokens_from_caller, generate_burn_payload, generate_mint_payload,
-    get_user_canister_balance, mint_tokens_to_caller,
+    get_user_canister_balance, mint_tokens_to_caller, verify_amount_signature,
 };
 
 pub mod constants;
@@ -21,61 +26,52 @@ thread_local! {
 }
 
 #[update]
-pub async fn mint(account: String, signature: String, amount: u128) {
-    // validate the signature, which is a signature of the amount to be minted
-    let recovered = recover_address_from_eth_signature(signature, amount.to_string()).unwrap();
-    if recovered.to_lowercase() != account.to_lowercase() {
-        panic!(
-            "SIGNATURE_VERIFICATION_FAILED:recovered key: {}; public key:{}",
-            recovered, account
-        );
-    }
+pub async fn mint(account: String, signature: String, amount: Nat) -> Result<(), CcampError> {
+    lib::pause::ensure_not_paused(Operation::Bridge)?;
     // validate the signature, which is a signature of the amount to be minted
+    verify_amount_signature(&account, signature, &amount)?;
 
     let dc_canister: Principal = ic_cdk::id();
     let caller = ic_cdk::caller();
 
-    let user_balance = get_user_canister_balance(account.clone()).await;
+    let user_balance = get_user_canister_balance(account.clone()).await?;
     if user_balance < amount {
-        panic!("INSUFFICIENT_FUNDS")
+        return Err(CcampError::InsufficientBalance {
+            requested: amount,
+            available: user_balance,
+        });
     }
 
     // deduct from the 'amount' user's balance and add it to the canister's balance
-    let remittance_payload = generate_mint_payload(account.clone(), amount).await;
-    lib::dc::update_remittance_canister(&remittance_payload, &dc_canister)
-        .await
-        .unwrap();
+    let remittance_payload = generate_mint_payload(account.clone(), amount.clone()).await?;
+    lib::dc::update_remittance_canister(&remittance_payload, &dc_canister).await?;
 
     // mint them some ccmatic tokens
-    mint_tokens_to_caller(amount, &caller).await;
+    mint_tokens_to_caller(amount, &caller).await?;
+
+    Ok(())
 }
 
 #[update]
-pub async fn burn(account: String, signature: String, amount: u128) {
-    // validate the signature, which is a signature of the amount to be minted
-    let recovered = recover_address_from_eth_signature(signature, amount.to_string()).unwrap();
-    if recovered.to_lowercase() != account.to_lowercase() {
-        panic!(
-            "SIGNATURE_VERIFICATION_FAILED:recovered key: {}; public key:{}",
-            recovered, account
-        );
-    }
-    // validate the signature, which is a signature of the amount to be minted
+pub async fn burn(account: String, signature: String, amount: Nat) -> Result<(), CcampError> {
+    lib::pause::ensure_not_paused(Operation::Bridge)?;
+    // validate the signature, which is a signature of the amount to be burned
+    verify_amount_signature(&account, signature, &amount)?;
 
     let dc_canister: Principal = ic_cdk::id();
     let caller = ic_cdk::caller();
 
     // first try to burn
-    burn_tokens_from_caller(amount, &caller).await.unwrap();
+    burn_tokens_from_caller(amount.clone(), &caller).await?;
 
     // add the 'amount' to the user's balance and add it to the canister's balance
-    let remittance_payload = generate_burn_payload(account.clone(), amount).await;
-    lib::dc::update_remittance_canister(&remittance_payload, &dc_canister)
-        .await
-        .unwrap();
+    let remittance_payload = generate_burn_payload(account.clone(), amount.clone()).await?;
+    lib::dc::update_remittance_canister(&remittance_payload, &dc_canister).await?;
 
     // mint them some ccmatic tokens
-    mint_tokens_to_caller(amount, &caller).await;
+    mint_tokens_to_caller(amount, &caller).await?;
+
+    Ok(())
 }
 
 // @dev testing command
@@ -94,26 +90,101 @@ fn owner() -> String {
     lib::owner::get_owner()
 }
 
+#[query]
+fn get_access_control() -> AccessControl {
+    lib::owner::get_access_control()
+}
+
+#[query]
+fn get_roles(principal: Principal) -> Vec<Role> {
+    lib::owner::get_roles(principal)
+}
+
+#[update]
+fn grant_role(role: Role, principal: Principal) -> Result<(), CcampError> {
+    lib::owner::grant_role(role, principal)
+}
+
+#[update]
+fn revoke_role(role: Role, principal: Principal) -> Result<(), CcampError> {
+    lib::owner::revoke_role(role, principal)
+}
+
+#[update]
+fn propose_owner(new_owner: Option<Principal>) -> Result<(), CcampError> {
+    lib::owner::propose_owner(new_owner)
+}
+
+#[update]
+fn accept_ownership() -> Result<(), CcampError> {
+    lib::owner::accept_ownership()
+}
+
+// the privileged calls made to this canister, only readable by auditors
+#[query]
+fn get_audit_log(
+    filter: Option<AuditFilter>,
+    start: u64,
+    limit: u64,
+) -> Result<AuditPage, CcampError> {
+    lib::audit::get_page(filter, start, limit)
+}
+
+// stop an operation of this canister e.g when a signer or publisher is compromised
+#[update]
+fn pause(operation: Operation, reason: String) -> Result<(), CcampError> {
+    lib::pause::pause(operation, reason)
+}
+
+#[update]
+fn unpause(operation: Operation, reason: String) -> Result<(), CcampError> {
+    lib::pause::unpause(operation, reason)
+}
+
+#[query]
+fn get_paused_operations() -> Vec<Operation> {
+    lib::pause::get_paused()
+}
+
+#[query]
+fn get_pause_history() -> Vec<PauseRecord> {
+    lib::pause::get_history()
+}
+
+// whitelist a canister which can then subscribe to the events of this canister
+#[update]
+pub fn add_subscriber(canister_principal: Principal) -> Result<(), CcampError> {
+    let result = lib::owner::ensure_role(Role::Admin).map(|_| {
+        lib::dc::add_subscriber(canister_principal);
+    });
+    lib::audit::record("add_subscriber", &(canister_principal,), &result);
+
+    result
+}
+
 #[update]
-pub async fn set_remittance_canister(remittance_principal: Principal) {
-    lib::owner::only_owner();
+pub fn remove_subscriber(canister_principal: Principal) -> Result<(), CcampError> {
+    let result = lib::owner::ensure_role(Role::Admin).map(|_| {
+        lib::dc::remove_subscriber(canister_principal);
+    });
+    lib::audit::record("remove_subscriber", &(canister_principal,), &result);
 
-    lib::dc::set_remittance_canister(remittance_principal);
+    result
 }
 
+// the whitelisted canisters along with their subscription and delivery status
 #[query]
-pub fn get_remittance_canister() -> RemittanceSubscriber {
-    // confirm at least one remittance canister is subscribed to this pdc
-    lib::dc::get_remittance_canister()
+pub fn get_subscribers() -> Vec<Subscription> {
+    lib::dc::get_subscribers()
 }
 
 // this function is going to be called by the remittance canister
 // so it can recieve "publish" events from this canister
 #[update]
-fn subscribe() {
-    // verify if this remittance canister has been whitelisted
-    // set the subscribed value to true if its the same, otherwise panic
-    lib::dc::subscribe();
+fn subscribe(subscriber: lib::Subscriber) -> Result<(), CcampError> {
+    // verify if this canister has been whitelisted
+    // and save the topic and filter of the events it wants to recieve
+    lib::dc::subscribe(subscriber)
 }
 
 #[query]
@@ -122,22 +193,27 @@ fn is_subscribed(canister_principal: Principal) -> bool {
 }
 
 #[update]
-fn set_token_principal(token_canister_principal: Principal) {
-    utils::set_token_principal(token_canister_principal)
+fn set_token_principal(token_canister_principal: Principal) -> Result<(), CcampError> {
+    let result = lib::owner::ensure_role(Role::Admin).map(|_| {
+        utils::set_token_principal(token_canister_principal);
+    });
+    lib::audit::record("set_token_principal", &(token_canister_principal,), &result);
+
+    result
 }
 
 #[query]
-fn get_token_principal() -> Principal {
+fn get_token_principal() -> Result<Principal, CcampError> {
     utils::get_token_principal()
 }
 
 #[update]
-async fn get_user_balance(account_address: String) -> u128 {
+async fn get_user_balance(account_address: String) -> Result<Nat, CcampError> {
     get_user_canister_balance(account_address).await
 }
 
 #[update]
-async fn get_canister_balance() -> u128 {
+async fn get_canister_balance() -> Result<Nat, CcampError> {
     get_user_canister_balance(ZERO_ADDRESS.to_string()).await
 }
 
@@ -145,28 +221,82 @@ async fn get_canister_balance() -> u128 {
 // which would be the remittance model
 // so when we have some new data, we would publish it to the remittance model
 #[update]
-async fn manual_publish(json_data: String) {
+async fn manual_publish(
+    json_data: String,
+    mode: Option<BatchMode>,
+) -> Result<ValidationReport, CcampError> {
     // create a dummy remittance object we can publish until we implement data collection
     // which would then generate the data instead of hardcoding it
-    let _ = lib::dc::publish_json_to_remittance(json_data);
+    let response = match lib::owner::ensure_role(Role::Operator) {
+        Ok(()) => {
+            lib::dc::publish_json_to_remittance(json_data.clone(), mode.clone().unwrap_or_default())
+                .await
+        }
+        Err(error) => Err(error),
+    };
+    lib::audit::record("manual_publish", &(json_data, mode), &response);
+
+    response
 }
 
 // --------------------------- upgrade hooks ------------------------- //
+// the version of the layout saved by `pre_upgrade`
+const STATE_VERSION: u32 = 1;
+// the stores saved across upgrades
+type StableState = (
+    lib::dc::SubscriberStore,
+    Option<Principal>,
+    AccessControl,
+    lib::pause::PauseState,
+    lib::audit::AuditLog,
+);
+
 #[pre_upgrade]
 fn pre_upgrade() {
-    let cloned_store = lib::dc::REMITTANCE_CANISTER.with(|rc| rc.borrow().clone());
+    let cloned_store = lib::dc::SUBSCRIBERS.with(|store| store.borrow().clone());
     let cloned_token_principal = TOKEN_PRINCIPAL.with(|rc| rc.borrow().clone());
-    storage::stable_save((cloned_store, cloned_token_principal)).unwrap()
+    let access_control = lib::owner::get_access_control();
+    let pause_state = lib::pause::get_state();
+    let audit_log = lib::audit::get_log();
+    lib::upgrade::save(
+        STATE_VERSION,
+        (
+            cloned_store,
+            cloned_token_principal,
+            access_control,
+            pause_state,
+            audit_log,
+        ),
+    )
+    .unwrap()
 }
 #[post_upgrade]
 async fn post_upgrade() {
-    init().await;
-
-    let (old_store, cloned_token_principal): (
-        Option<lib::RemittanceSubscriber>,
-        Option<Principal>,
-    ) = storage::stable_restore().unwrap();
-    lib::dc::REMITTANCE_CANISTER.with(|store| *store.borrow_mut() = old_store);
+    let bytes = lib::upgrade::saved_bytes();
+    let restored: Result<StableState, String> = match lib::upgrade::version(&bytes) {
+        Some(_) => lib::upgrade::decode_versioned(&bytes, STATE_VERSION),
+        // the subscriber and token saved by the first version
+        None => lib::upgrade::decode(&bytes).map(
+            |(subscriber, token_principal): (
+                Option<lib::upgrade::RemittanceSubscriber>,
+                Option<Principal>,
+            )| {
+                (
+                    lib::upgrade::subscriber_store(subscriber),
+                    token_principal,
+                    lib::upgrade::legacy_access_control(ic_cdk::caller()),
+                    lib::pause::PauseState::default(),
+                    lib::audit::AuditLog::default(),
+                )
+            },
+        ),
+    };
+    let (old_store, cloned_token_principal, access_control, pause_state, audit_log) =
+        restored.unwrap_or_else(|error| ic_cdk::trap(&error));
+    lib::dc::SUBSCRIBERS.with(|store| *store.borrow_mut() = old_store);
     TOKEN_PRINCIPAL.with(|store| *store.borrow_mut() = cloned_token_principal);
+    lib::owner::restore_access_control(access_control);
+    lib::pause::restore_state(pause_state);
+    lib::audit::restore_log(audit_log);
 }
 // --------------------------- upgrade hooks ------------------------- //
@@ -1,19 +1,20 @@
-use candid::Principal;
+use candid::{Nat, Principal};
 use ic_cdk::{api, id};
 use lib::{
     constants::ZERO_ADDRESS,
     dc::{get_remittance_canister, Account},
-    DataModel,
+    ethereum::recover_address_from_eth_signature,
+    CcampError, DataModel,
 };
 
 use crate::constants::CHAIN_IDENTIFIER;
 
 // get the user balance for this canister which handles the matic chain
 // for the matic native token
-pub async fn get_user_canister_balance(account: String) -> u128 {
-    let rc = get_remittance_canister();
-    let (balance,): (Account,) = api::call::call(
-        rc.canister_principal,
+pub async fn get_user_canister_balance(account: String) -> Result<Nat, CcampError> {
+    let remittance_canister = get_remittance_canister()?;
+    let (balance,): (Result<Account, CcampError>,) = api::call::call(
+        remittance_canister,
         "get_available_balance",
         (
             ZERO_ADDRESS.to_string(),
@@ -23,76 +24,133 @@ pub async fn get_user_canister_balance(account: String) -> u128 {
         ),
     )
     .await
-    .unwrap();
+    .map_err(|(code, message)| CcampError::CallFailed {
+        method: String::from("get_available_balance"),
+        reason: format!("{:?}:{}", code, message),
+    })?;
 
-    balance.balance as u128
+    Ok(balance?.balance)
 }
 
 // generate a payload that moves funds from the user's account to the canister's assigned account
-pub async fn generate_mint_payload(account: String, amount: u128) -> Vec<DataModel> {
+pub async fn generate_mint_payload(
+    account: String,
+    amount: Nat,
+) -> Result<Vec<DataModel>, CcampError> {
+    let account = parse_account(account)?;
+    let amount = lib::amount::to_int(&amount);
     let payload = &vec![
         DataModel {
             token: String::from(ZERO_ADDRESS).try_into().unwrap(),
             chain: String::from(CHAIN_IDENTIFIER).try_into().unwrap(),
-            amount: -(amount as i64),
-            account: account.try_into().unwrap(),
+            amount: lib::amount::negate(&amount),
+            account,
             action: lib::Action::Adjust,
+            identity: None,
+            nonce: None,
         },
         DataModel {
             token: String::from(ZERO_ADDRESS).try_into().unwrap(),
             chain: String::from(CHAIN_IDENTIFIER).try_into().unwrap(),
-            amount: amount as i64,
+            amount,
             account: String::from(ZERO_ADDRESS).try_into().unwrap(),
             action: lib::Action::Adjust,
+            identity: None,
+            nonce: None,
         },
     ];
 
-    payload.clone()
+    Ok(payload.clone())
 }
 
 // generate a payload that moves funds from the canister's account to th euser's account
-pub async fn generate_burn_payload(account: String, amount: u128) -> Vec<DataModel> {
+pub async fn generate_burn_payload(
+    account: String,
+    amount: Nat,
+) -> Result<Vec<DataModel>, CcampError> {
+    let account = parse_account(account)?;
+    let amount = lib::amount::to_int(&amount);
     let payload = &vec![
         DataModel {
             token: String::from(ZERO_ADDRESS).try_into().unwrap(),
             chain: String::from(CHAIN_IDENTIFIER).try_into().unwrap(),
-            amount: -(amount as i64),
+            amount: lib::amount::negate(&amount),
             account: String::from(ZERO_ADDRESS).try_into().unwrap(),
             action: lib::Action::Adjust,
+            identity: None,
+            nonce: None,
         },
         DataModel {
             token: String::from(ZERO_ADDRESS).try_into().unwrap(),
             chain: String::from(CHAIN_IDENTIFIER).try_into().unwrap(),
-            amount: amount as i64,
-            account: account.try_into().unwrap(),
+            amount,
+            account,
             action: lib::Action::Adjust,
+            identity: None,
+            nonce: None,
         },
     ];
 
-    payload.clone()
+    Ok(payload.clone())
 }
 
-pub async fn mint_tokens_to_caller(amount: u128, caller: &Principal) -> u128 {
-    let token_canister = get_token_principal();
-    let (minted_tokens,): (u128,) = api::call::call(token_canister, "mint", (caller, amount))
+pub async fn mint_tokens_to_caller(amount: Nat, caller: &Principal) -> Result<Nat, CcampError> {
+    let token_canister = get_token_principal()?;
+    let (minted_tokens,): (Nat,) = api::call::call(token_canister, "mint", (caller, amount))
         .await
-        .unwrap();
+        .map_err(|(code, message)| CcampError::CallFailed {
+            method: String::from("mint"),
+            reason: format!("{:?}:{}", code, message),
+        })?;
 
-    minted_tokens
+    Ok(minted_tokens)
 }
 
-pub async fn burn_tokens_from_caller(amount: u128, caller: &Principal) -> Result<u128, String> {
-    let token_canister = get_token_principal();
-    let (burned_tokens,): (Result<u128, String>,) =
+pub async fn burn_tokens_from_caller(amount: Nat, caller: &Principal) -> Result<Nat, CcampError> {
+    let token_canister = get_token_principal()?;
+    let (burned_tokens,): (Result<Nat, String>,) =
         api::call::call(token_canister, "burn", (caller, amount))
             .await
-            .unwrap();
+            .map_err(|(code, message)| CcampError::CallFailed {
+                method: String::from("burn"),
+                reason: format!("{:?}:{}", code, message),
+            })?;
 
-    burned_tokens
+    burned_tokens.map_err(|reason| CcampError::TokenOperationFailed { reason })
 }
 
-pub fn get_token_principal() -> Principal {
-    crate::TOKEN_PRINCIPAL.with(|t| t.borrow().unwrap())
+fn parse_account(address: String) -> Result<lib::Address, CcampError> {
+    address
+        .clone()
+        .try_into()
+        .map_err(|reason| CcampError::InvalidAddress { address, reason })
+}
+
+// make sure the signature is a signature of the amount by the provided account
+pub fn verify_amount_signature(
+    account: &str,
+    signature: String,
+    amount: &Nat,
+) -> Result<(), CcampError> {
+    let recovered =
+        recover_address_from_eth_signature(signature, lib::amount::to_decimal_string(amount))?;
+    if recovered.to_lowercase() != account.to_lowercase() {
+        return Err(CcampError::SignatureMismatch {
+            recovered,
+            expected: account.to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+// the token canister set by an admin, the tokens can't be minted or burned before it is set
+pub fn get_token_principal() -> Result<Principal, CcampError> {
+    crate::TOKEN_PRINCIPAL.with(|t| {
+        t.borrow().ok_or(CcampError::TokenOperationFailed {
+            reason: String::from("TOKEN_PRINCIPAL_NOT_SET"),
+        })
+    })
 }
 
 pub fn set_token_principal(token_principal: Principal) {
@@ -1,9 +1,13 @@
 use candid::Principal;
 use ic_cdk::api::time;
-use ic_cdk::{caller, storage};
+use ic_cdk::caller;
 
 use config::{DECIMALS, FEE, INITIAL_SUPPLY, TOKEN_NAME, TOKEN_SYMBOL};
 use ic_cdk_macros::{init, post_upgrade, pre_upgrade, query, update};
+use lib::{
+    audit::{AuditFilter, AuditPage},
+    owner::{AccessControl, Role},
+};
 use std::{cell::RefCell, collections::HashMap};
 use types::{
     Account, Allowance, AllowanceArgs, ApprovalType, ApproveArgs, ApproveError, TransferArgs,
@@ -42,6 +46,42 @@ fn owner() -> String {
     lib::owner::get_owner()
 }
 
+#[query]
+fn get_access_control() -> AccessControl {
+    lib::owner::get_access_control()
+}
+
+#[query]
+fn get_roles(principal: Principal) -> Vec<Role> {
+    lib::owner::get_roles(principal)
+}
+
+#[update]
+fn grant_role(role: Role, principal: Principal) -> Result<(), String> {
+    lib::owner::grant_role(role, principal).map_err(String::from)
+}
+
+#[update]
+fn revoke_role(role: Role, principal: Principal) -> Result<(), String> {
+    lib::owner::revoke_role(role, principal).map_err(String::from)
+}
+
+#[update]
+fn propose_owner(new_owner: Option<Principal>) -> Result<(), String> {
+    lib::owner::propose_owner(new_owner).map_err(String::from)
+}
+
+#[update]
+fn accept_ownership() -> Result<(), String> {
+    lib::owner::accept_ownership().map_err(String::from)
+}
+
+// the privileged calls made to this canister, only readable by auditors
+#[query]
+fn get_audit_log(filter: Option<AuditFilter>, start: u64, limit: u64) -> Result<AuditPage, String> {
+    lib::audit::get_page(filter, start, limit).map_err(String::from)
+}
+
 #[query]
 fn icrc1_metadata() -> MetaDataType {
     generate_metadata()
@@ -240,40 +280,82 @@ fn burn(account_principal: Principal, amount: u128) -> Result<u128, String> {
 }
 
 #[update]
-fn set_dc_canister(dc_principal: Principal) {
-    ADMIN_PRINCIPAL.with(|ap| *ap.borrow_mut() = Some(dc_principal))
+fn set_dc_canister(dc_principal: Principal) -> Result<(), String> {
+    let result = lib::owner::ensure_role(Role::Admin).map(|_| {
+        ADMIN_PRINCIPAL.with(|ap| *ap.borrow_mut() = Some(dc_principal));
+    });
+    lib::audit::record("set_dc_canister", &(dc_principal,), &result);
+
+    result.map_err(String::from)
 }
 
 // --------------------------- upgrade hooks ------------------------- //
+// the version of the layout saved by `pre_upgrade`
+const STATE_VERSION: u32 = 1;
+// the stores saved across upgrades
+type StableState = (
+    HashMap<Principal, u128>,
+    u128,
+    Option<Principal>,
+    ApprovalType,
+    AccessControl,
+    lib::audit::AuditLog,
+);
+
 #[pre_upgrade]
 fn pre_upgrade() {
     let cloned_balances = BALANCES.with(|rc| rc.borrow().clone());
     let cloned_supply = TOTAL_SUPPLY.with(|rc| rc.borrow().clone());
     let cloned_admin = ADMIN_PRINCIPAL.with(|rc| rc.borrow().clone());
     let cloned_approvals = APPROVALS.with(|rc| rc.borrow().clone());
-
-    storage::stable_save((
-        cloned_balances,
-        cloned_supply,
-        cloned_admin,
-        cloned_approvals,
-    ))
+    let access_control = lib::owner::get_access_control();
+    let audit_log = lib::audit::get_log();
+
+    lib::upgrade::save(
+        STATE_VERSION,
+        (
+            cloned_balances,
+            cloned_supply,
+            cloned_admin,
+            cloned_approvals,
+            access_control,
+            audit_log,
+        ),
+    )
     .unwrap()
 }
 
 #[post_upgrade]
 async fn post_upgrade() {
-    lib::owner::init_owner();
-
-    let (cloned_balances, cloned_supply, cloned_admin, cloned_approvals): (
-        HashMap<Principal, u128>,
-        u128,
-        Option<Principal>,
-        ApprovalType,
-    ) = storage::stable_restore().unwrap();
+    let bytes = lib::upgrade::saved_bytes();
+    let restored: Result<StableState, String> = match lib::upgrade::version(&bytes) {
+        Some(_) => lib::upgrade::decode_versioned(&bytes, STATE_VERSION),
+        // the first version saved the same stores without its owner
+        None => lib::upgrade::decode(&bytes).map(
+            |(balances, supply, admin, approvals): (
+                HashMap<Principal, u128>,
+                u128,
+                Option<Principal>,
+                ApprovalType,
+            )| {
+                (
+                    balances,
+                    supply,
+                    admin,
+                    approvals,
+                    lib::upgrade::legacy_access_control(caller()),
+                    lib::audit::AuditLog::default(),
+                )
+            },
+        ),
+    };
+    let (cloned_balances, cloned_supply, cloned_admin, cloned_approvals, access_control, audit_log) =
+        restored.unwrap_or_else(|error| ic_cdk::trap(&error));
 
     BALANCES.with(|r| *r.borrow_mut() = cloned_balances);
     TOTAL_SUPPLY.with(|r| *r.borrow_mut() = cloned_supply);
     ADMIN_PRINCIPAL.with(|r| *r.borrow_mut() = cloned_admin);
     APPROVALS.with(|r| *r.borrow_mut() = cloned_approvals);
+    lib::owner::restore_access_control(access_control);
+    lib::audit::restore_log(audit_log);
 }
@@ -3,6 +3,7 @@ use crate::{
     types::{Account, Allowance},
 };
 use candid::{CandidType, Principal};
+use lib::owner::Role;
 use std::collections::HashMap;
 
 pub type MetaDataType = HashMap<String, Variant>;
@@ -56,9 +57,8 @@ pub fn generate_supported_standards() -> Vec<SupportedStandards> {
 pub fn only_admin_canister() {
     let admin_principal = crate::ADMIN_PRINCIPAL.with(|f| f.borrow().clone().unwrap());
     let caller = ic_cdk::caller();
-    let owner = Principal::from_text(lib::owner::get_owner()).unwrap();
 
-    if caller != admin_principal && caller != owner {
+    if caller != admin_principal && !lib::owner::has_role(&caller, Role::Admin) {
         panic!("NOT_AUTHORIZED")
     }
 }
@@ -0,0 +1,369 @@
+use bech32::{FromBase32, Variant};
+use candid::{CandidType, Principal};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::fmt::Display;
+
+use crate::{chain::SignatureScheme, Wallet};
+
+const SOLANA_ADDRESS_LENGTH: usize = 32;
+const TRON_ADDRESS_PREFIX: u8 = 0x41;
+const TRON_ADDRESS_LENGTH: usize = 21;
+// base58 bitcoin addresses are a version byte followed by a 20 byte hash
+const BITCOIN_BASE58_VERSIONS: [u8; 4] = [0x00, 0x05, 0x6f, 0xc4];
+const BITCOIN_BASE58_LENGTH: usize = 21;
+const BITCOIN_SEGWIT_HRPS: [&str; 3] = ["bc", "tb", "bcrt"];
+// an icp account identifier is a crc32 checksum followed by a 28 byte hash
+const ICP_ACCOUNT_IDENTIFIER_LENGTH: usize = 32;
+
+// an account or token address on any of the chains supported by the protocol
+#[derive(Clone, Debug, Deserialize, CandidType, PartialEq, Hash, Eq)]
+pub enum Address {
+    Evm(Wallet),
+    // the 32 byte ed25519 public key
+    Solana(Vec<u8>),
+    // the canonical encoding of a base58 or bech32 address
+    Bitcoin(String),
+    // the 21 byte address including the 0x41 prefix
+    Tron(Vec<u8>),
+    IcpPrincipal(Principal),
+    IcpAccount(Vec<u8>),
+}
+
+impl Address {
+    pub fn parse_evm(address: &str) -> Result<Self, String> {
+        Ok(Self::Evm(Wallet::try_from(address.to_string())?))
+    }
+
+    pub fn parse_solana(address: &str) -> Result<Self, String> {
+        let decoded = bs58::decode(address)
+            .into_vec()
+            .map_err(|_| String::from("INVALID_SOLANA_ADDRESS"))?;
+        if decoded.len() != SOLANA_ADDRESS_LENGTH {
+            return Err(String::from("INVALID_SOLANA_ADDRESS"));
+        }
+
+        Ok(Self::Solana(decoded))
+    }
+
+    pub fn parse_bitcoin(address: &str) -> Result<Self, String> {
+        // segwit addresses are bech32(m) encoded
+        if let Ok((hrp, data, variant)) = bech32::decode(address) {
+            let (witness_version, program) = data
+                .split_first()
+                .ok_or(String::from("INVALID_BITCOIN_ADDRESS"))?;
+            let witness_version = witness_version.to_u8();
+            let program = Vec::<u8>::from_base32(program)
+                .map_err(|_| String::from("INVALID_BITCOIN_ADDRESS"))?;
+
+            let expected_variant = if witness_version == 0 {
+                Variant::Bech32
+            } else {
+                Variant::Bech32m
+            };
+            let is_valid = BITCOIN_SEGWIT_HRPS.contains(&&hrp[..])
+                && variant == expected_variant
+                && witness_version <= 16
+                && (2..=40).contains(&program.len())
+                && (witness_version != 0 || program.len() == 20 || program.len() == 32);
+            if !is_valid {
+                return Err(String::from("INVALID_BITCOIN_ADDRESS"));
+            }
+
+            return Ok(Self::Bitcoin(address.to_lowercase()));
+        }
+
+        // legacy addresses are base58check encoded
+        let decoded =
+            decode_base58_check(address).ok_or(String::from("INVALID_BITCOIN_ADDRESS"))?;
+        if decoded.len() != BITCOIN_BASE58_LENGTH || !BITCOIN_BASE58_VERSIONS.contains(&decoded[0])
+        {
+            return Err(String::from("INVALID_BITCOIN_ADDRESS"));
+        }
+
+        Ok(Self::Bitcoin(address.to_string()))
+    }
+
+    pub fn parse_tron(address: &str) -> Result<Self, String> {
+        let decoded = decode_base58_check(address).ok_or(String::from("INVALID_TRON_ADDRESS"))?;
+        if decoded.len() != TRON_ADDRESS_LENGTH || decoded[0] != TRON_ADDRESS_PREFIX {
+            return Err(String::from("INVALID_TRON_ADDRESS"));
+        }
+
+        Ok(Self::Tron(decoded))
+    }
+
+    pub fn parse_icp_principal(address: &str) -> Result<Self, String> {
+        let principal =
+            Principal::from_text(address).map_err(|_| String::from("INVALID_ICP_PRINCIPAL"))?;
+
+        Ok(Self::IcpPrincipal(principal))
+    }
+
+    pub fn parse_icp_account(address: &str) -> Result<Self, String> {
+        let decoded = hex::decode(address).map_err(|_| String::from("INVALID_ICP_ACCOUNT"))?;
+        if decoded.len() != ICP_ACCOUNT_IDENTIFIER_LENGTH {
+            return Err(String::from("INVALID_ICP_ACCOUNT"));
+        }
+
+        // the first four bytes are the big endian crc32 of the hash that follows
+        let checksum = crc32fast::hash(&decoded[4..]).to_be_bytes();
+        if checksum[..] != decoded[..4] {
+            return Err(String::from("INVALID_ICP_ACCOUNT_CHECKSUM"));
+        }
+
+        Ok(Self::IcpAccount(decoded))
+    }
+
+    // whether a balance of the address can be withdrawn through a locker on a chain of the scheme
+    // ecdsa chains pay out evm accounts and ed25519 chains solana accounts
+    pub fn is_withdrawable(&self, signature_scheme: &SignatureScheme) -> bool {
+        matches!(
+            (self, signature_scheme),
+            (Self::Evm(_), SignatureScheme::EcdsaSecp256k1)
+                | (Self::Solana(_), SignatureScheme::Ed25519)
+        )
+    }
+
+    pub fn as_evm(&self) -> Option<&Wallet> {
+        match self {
+            Self::Evm(wallet) => Some(wallet),
+            _ => None,
+        }
+    }
+}
+
+impl TryFrom<String> for Address {
+    type Error = String;
+
+    // detect the format of the address from its encoding
+    fn try_from(address: String) -> Result<Self, Self::Error> {
+        let is_hex = |value: &str| value.chars().all(|c| c.is_ascii_hexdigit());
+
+        if address.starts_with("0x") || (address.len() == 40 && is_hex(&address)) {
+            return Self::parse_evm(&address);
+        }
+        if address.len() == ICP_ACCOUNT_IDENTIFIER_LENGTH * 2 && is_hex(&address) {
+            return Self::parse_icp_account(&address);
+        }
+        if address.contains('-') {
+            return Self::parse_icp_principal(&address);
+        }
+
+        let lowercase_address = address.to_lowercase();
+        if BITCOIN_SEGWIT_HRPS
+            .iter()
+            .any(|hrp| lowercase_address.starts_with(&format!("{}1", hrp)))
+        {
+            return Self::parse_bitcoin(&address);
+        }
+        if let Some(decoded) = decode_base58_check(&address) {
+            if decoded.first() == Some(&TRON_ADDRESS_PREFIX) {
+                return Self::parse_tron(&address);
+            }
+            return Self::parse_bitcoin(&address);
+        }
+
+        Self::parse_solana(&address).map_err(|_| String::from("INVALID_ADDRESS"))
+    }
+}
+
+impl Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Evm(wallet) => write!(f, "{}", wallet),
+            Self::Solana(public_key) => write!(f, "{}", bs58::encode(public_key).into_string()),
+            Self::Bitcoin(address) => write!(f, "{}", address),
+            Self::Tron(address) => write!(f, "{}", encode_base58_check(address)),
+            Self::IcpPrincipal(principal) => write!(f, "{}", principal.to_text()),
+            Self::IcpAccount(account) => write!(f, "{}", hex::encode(account)),
+        }
+    }
+}
+
+impl From<Wallet> for Address {
+    fn from(wallet: Wallet) -> Self {
+        Self::Evm(wallet)
+    }
+}
+
+// the checksum of a base58check payload is the first four bytes of its double sha256
+fn base58_checksum(payload: &[u8]) -> [u8; 4] {
+    let hash = Sha256::digest(Sha256::digest(payload));
+
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+// decode a base58check string and return the payload without the checksum
+fn decode_base58_check(address: &str) -> Option<Vec<u8>> {
+    let decoded = bs58::decode(address).into_vec().ok()?;
+    if decoded.len() < 5 {
+        return None;
+    }
+
+    let (payload, checksum) = decoded.split_at(decoded.len() - 4);
+    if base58_checksum(payload)[..] != checksum[..] {
+        return None;
+    }
+
+    Some(payload.to_vec())
+}
+
+fn encode_base58_check(payload: &[u8]) -> String {
+    let mut data = payload.to_vec();
+    data.extend_from_slice(&base58_checksum(payload));
+
+    bs58::encode(data).into_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(address: &str) -> Result<Address, String> {
+        Address::try_from(address.to_string())
+    }
+
+    // parsing the canonical form of an address and displaying it gives back the same string
+    fn assert_round_trip(canonical: &str) -> Address {
+        let parsed = address(canonical).unwrap();
+        assert_eq!(parsed.to_string(), canonical);
+
+        parsed
+    }
+
+    #[test]
+    fn evm_addresses() {
+        let parsed = assert_round_trip("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed");
+        assert!(matches!(parsed, Address::Evm(_)));
+
+        assert_eq!(
+            address("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD"),
+            Err(String::from("INVALID_ADDRESS_CHECKSUM"))
+        );
+        assert_eq!(
+            address("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeA"),
+            Err(String::from("INVALID_ADDRESSS_LENGTH"))
+        );
+    }
+
+    #[test]
+    fn solana_addresses() {
+        let public_key = vec![7u8; SOLANA_ADDRESS_LENGTH];
+        let encoded = bs58::encode(&public_key).into_string();
+        assert_eq!(assert_round_trip(&encoded), Address::Solana(public_key));
+
+        // a base58 string which doesn't decode to 32 bytes
+        let short = bs58::encode([7u8; 31]).into_string();
+        assert_eq!(address(&short), Err(String::from("INVALID_ADDRESS")));
+        assert_eq!(
+            Address::parse_solana(&short),
+            Err(String::from("INVALID_SOLANA_ADDRESS"))
+        );
+        // 0, O, I and l aren't part of the base58 alphabet
+        assert_eq!(
+            Address::parse_solana("0OIl"),
+            Err(String::from("INVALID_SOLANA_ADDRESS"))
+        );
+    }
+
+    #[test]
+    fn bitcoin_addresses() {
+        // p2pkh and p2sh are base58check encoded
+        for canonical in [
+            "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa",
+            "3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy",
+        ] {
+            assert!(matches!(assert_round_trip(canonical), Address::Bitcoin(_)));
+        }
+        // segwit v0 is bech32 and taproot bech32m encoded, both are canonically lowercase
+        for canonical in [
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4",
+            "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0",
+        ] {
+            assert!(matches!(assert_round_trip(canonical), Address::Bitcoin(_)));
+        }
+        assert_eq!(
+            address("BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4")
+                .unwrap()
+                .to_string(),
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"
+        );
+
+        let invalid = [
+            // the last character breaks the base58check checksum
+            "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNb",
+            // the last character breaks the bech32 checksum
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t5",
+            // a segwit v1 program encoded with bech32 instead of bech32m
+            "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqh2y7hd",
+        ];
+        for invalid in invalid {
+            assert!(
+                Address::parse_bitcoin(invalid).is_err(),
+                "{} should be rejected",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn tron_addresses() {
+        let parsed = assert_round_trip("TR7NHqjeKQxGTCi8q8ZY4pL8otSzgjLj6t");
+        assert!(matches!(&parsed, Address::Tron(bytes) if bytes[0] == TRON_ADDRESS_PREFIX));
+
+        assert_eq!(
+            Address::parse_tron("TR7NHqjeKQxGTCi8q8ZY4pL8otSzgjLj6u"),
+            Err(String::from("INVALID_TRON_ADDRESS"))
+        );
+        // a valid base58check payload without the tron prefix
+        assert_eq!(
+            Address::parse_tron("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa"),
+            Err(String::from("INVALID_TRON_ADDRESS"))
+        );
+    }
+
+    #[test]
+    fn icp_addresses() {
+        let principal = "bkyz2-fmaaa-aaaaa-qaaaq-cai";
+        assert_eq!(
+            assert_round_trip(principal),
+            Address::IcpPrincipal(Principal::from_text(principal).unwrap())
+        );
+        assert_eq!(
+            address("bkyz2-fmaaa-aaaaa-qaaaq-caa"),
+            Err(String::from("INVALID_ICP_PRINCIPAL"))
+        );
+
+        // the account identifier is the crc32 of its hash followed by the hash
+        let hash = [3u8; ICP_ACCOUNT_IDENTIFIER_LENGTH - 4];
+        let account = [&crc32fast::hash(&hash).to_be_bytes()[..], &hash[..]].concat();
+        assert_eq!(
+            assert_round_trip(&hex::encode(&account)),
+            Address::IcpAccount(account.clone())
+        );
+
+        let mut corrupted = account;
+        corrupted[ICP_ACCOUNT_IDENTIFIER_LENGTH - 1] ^= 1;
+        assert_eq!(
+            address(&hex::encode(corrupted)),
+            Err(String::from("INVALID_ICP_ACCOUNT_CHECKSUM"))
+        );
+        assert_eq!(
+            Address::parse_icp_account("zz"),
+            Err(String::from("INVALID_ICP_ACCOUNT"))
+        );
+    }
+
+    #[test]
+    fn only_locker_payable_addresses_are_withdrawable() {
+        let evm = address("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed").unwrap();
+        let solana = Address::Solana(vec![7u8; SOLANA_ADDRESS_LENGTH]);
+        let tron = address("TR7NHqjeKQxGTCi8q8ZY4pL8otSzgjLj6t").unwrap();
+
+        assert!(evm.is_withdrawable(&SignatureScheme::EcdsaSecp256k1));
+        assert!(!evm.is_withdrawable(&SignatureScheme::Ed25519));
+        assert!(solana.is_withdrawable(&SignatureScheme::Ed25519));
+        assert!(!tron.is_withdrawable(&SignatureScheme::EcdsaSecp256k1));
+    }
+}
@@ -0,0 +1,69 @@
+use candid::{Int, Nat};
+use num_bigint::{BigInt, Sign};
+use num_traits::{CheckedSub, Signed, ToPrimitive};
+
+// token amounts are represented as candid `nat` (balances) and `int` (signed deltas)
+// but they have to fit into a `uint256` to be usable on the evm side
+pub const MAX_AMOUNT_BYTES: usize = 32;
+
+// convert a balance into a signed delta
+pub fn to_int(amount: &Nat) -> Int {
+    Int::from(amount.clone())
+}
+
+// get the magnitude of a signed delta
+pub fn abs(delta: &Int) -> Nat {
+    Nat::from(delta.0.magnitude().clone())
+}
+
+pub fn is_negative(delta: &Int) -> bool {
+    delta.0.is_negative()
+}
+
+pub fn is_positive(delta: &Int) -> bool {
+    delta.0.is_positive()
+}
+
+pub fn negate(delta: &Int) -> Int {
+    Int::from(-delta.0.clone())
+}
+
+pub fn checked_sub(balance: &Nat, amount: &Nat) -> Option<Nat> {
+    balance.0.checked_sub(&amount.0).map(Nat::from)
+}
+
+// apply a signed delta to a balance
+// returns None if the resulting balance would be negative
+pub fn checked_apply(balance: &Nat, delta: &Int) -> Option<Nat> {
+    let result = BigInt::from(balance.0.clone()) + &delta.0;
+    match result.sign() {
+        Sign::Minus => None,
+        _ => Some(Nat::from(result.magnitude().clone())),
+    }
+}
+
+// returns the big endian `uint256` representation of an amount
+// or None if the amount does not fit into 256 bits
+pub fn to_u256_bytes(amount: &Nat) -> Option<[u8; MAX_AMOUNT_BYTES]> {
+    let bytes = amount.0.to_bytes_be();
+    if bytes.len() > MAX_AMOUNT_BYTES {
+        return None;
+    }
+
+    let mut padded = [0u8; MAX_AMOUNT_BYTES];
+    padded[MAX_AMOUNT_BYTES - bytes.len()..].copy_from_slice(&bytes);
+
+    Some(padded)
+}
+
+// returns the amount as a `u64` e.g for spl token amounts
+// or None if it does not fit into 64 bits
+pub fn to_u64(amount: &Nat) -> Option<u64> {
+    amount.0.to_u64()
+}
+
+// the decimal representation of an amount without the `_` separators
+// which the candid `Display` implementation adds
+pub fn to_decimal_string(amount: &Nat) -> String {
+    amount.0.to_str_radix(10)
+}
@@ -0,0 +1,236 @@
+use candid::{CandidType, Principal};
+use ic_cdk::{api::time, caller};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{cell::RefCell, collections::BTreeMap};
+
+use crate::{owner::Role, CcampError};
+
+// the most entries a single page of the log can contain
+pub const MAX_AUDIT_PAGE_SIZE: u64 = 100;
+// the most entries kept, the oldest ones are dropped first once it is reached
+pub const MAX_AUDIT_ENTRIES: usize = 10_000;
+
+thread_local! {
+    pub static AUDIT_LOG: RefCell<AuditLog> = RefCell::default();
+}
+
+#[derive(Clone, Debug, Deserialize, CandidType, PartialEq)]
+pub enum AuditOutcome {
+    Success,
+    Failure { reason: String },
+}
+
+#[derive(Clone, Debug, Deserialize, CandidType, PartialEq)]
+pub struct AuditEntry {
+    // the position of the entry in the log, ids are never reused
+    pub id: u64,
+    pub caller: Principal,
+    pub method: String,
+    // the hex encoded sha256 hash of the candid encoded arguments of the call
+    pub arguments_digest: String,
+    // timestamp in nanoseconds
+    pub timestamp: u64,
+    pub outcome: AuditOutcome,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, CandidType, PartialEq)]
+pub struct AuditLog {
+    // the entries by id in the order they were appended
+    pub entries: BTreeMap<u64, AuditEntry>,
+    pub next_id: u64,
+}
+impl AuditLog {
+    pub fn insert(&mut self, entry: AuditEntry, capacity: usize) {
+        self.next_id = entry.id + 1;
+        self.entries.insert(entry.id, entry);
+
+        while self.entries.len() > capacity {
+            self.entries.pop_first();
+        }
+    }
+}
+
+// every field which is set has to match for an entry to be returned
+#[derive(Clone, Debug, Default, Deserialize, CandidType, PartialEq)]
+pub struct AuditFilter {
+    pub caller: Option<Principal>,
+    pub method: Option<String>,
+    // the inclusive range of timestamps in nanoseconds
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+impl AuditFilter {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        self.caller.is_none_or(|caller| caller == entry.caller)
+            && self
+                .method
+                .as_ref()
+                .is_none_or(|method| *method == entry.method)
+            && self.from.is_none_or(|from| entry.timestamp >= from)
+            && self.to.is_none_or(|to| entry.timestamp <= to)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, CandidType, PartialEq)]
+pub struct AuditPage {
+    pub entries: Vec<AuditEntry>,
+    // the id to start the next page from, none once the end of the log is reached
+    pub next: Option<u64>,
+}
+
+pub fn digest<A: CandidType>(arguments: &A) -> String {
+    let encoded = candid::encode_one(arguments).expect("ARGUMENTS_ENCODING_FAILED");
+
+    hex::encode(Sha256::digest(encoded))
+}
+
+pub fn append(
+    caller: Principal,
+    method: &str,
+    arguments_digest: String,
+    timestamp: u64,
+    outcome: AuditOutcome,
+) -> u64 {
+    AUDIT_LOG.with(|log| {
+        let mut log = log.borrow_mut();
+        let id = log.next_id;
+        let entry = AuditEntry {
+            id,
+            caller,
+            method: method.to_string(),
+            arguments_digest,
+            timestamp,
+            outcome,
+        };
+        log.insert(entry, MAX_AUDIT_ENTRIES);
+
+        id
+    })
+}
+
+// record a privileged call made to the canister
+// calls which trap are rolled back along with their entry so only returned errors are recorded as failures
+pub fn record<A: CandidType, T>(method: &str, arguments: &A, result: &Result<T, CcampError>) {
+    let outcome = match result {
+        Ok(_) => AuditOutcome::Success,
+        Err(error) => AuditOutcome::Failure {
+            reason: error.to_string(),
+        },
+    };
+
+    append(caller(), method, digest(arguments), time(), outcome);
+}
+
+// the entries matching the filter with an id of at least `start`
+pub fn get_entries(filter: &AuditFilter, start: u64, limit: u64) -> AuditPage {
+    let limit = limit.clamp(1, MAX_AUDIT_PAGE_SIZE) as usize;
+
+    AUDIT_LOG.with(|log| {
+        let log = log.borrow();
+        let mut matching = log
+            .entries
+            .range(start..)
+            .map(|(_, entry)| entry)
+            .filter(|entry| filter.matches(entry));
+
+        let entries: Vec<AuditEntry> = matching.by_ref().take(limit).cloned().collect();
+        let next = matching.next().map(|entry| entry.id);
+
+        AuditPage { entries, next }
+    })
+}
+
+// the page of the log returned to auditors
+pub fn get_page(
+    filter: Option<AuditFilter>,
+    start: u64,
+    limit: u64,
+) -> Result<AuditPage, CcampError> {
+    crate::owner::ensure_role(Role::Auditor)?;
+
+    Ok(get_entries(&filter.unwrap_or_default(), start, limit))
+}
+
+pub fn get_log() -> AuditLog {
+    AUDIT_LOG.with(|log| log.borrow().clone())
+}
+
+pub fn restore_log(audit_log: AuditLog) {
+    AUDIT_LOG.with(|log| *log.borrow_mut() = audit_log);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_are_filtered_and_paginated() {
+        let admin = Principal::from_slice(&[1]);
+        let operator = Principal::from_slice(&[2]);
+        for timestamp in 0..5 {
+            append(
+                admin,
+                "add_publisher",
+                digest(&(operator,)),
+                timestamp,
+                AuditOutcome::Success,
+            );
+            append(
+                operator,
+                "manual_publish",
+                digest(&(String::from("[]"),)),
+                timestamp,
+                AuditOutcome::Success,
+            );
+        }
+        let filter = AuditFilter {
+            caller: Some(admin),
+            from: Some(1),
+            ..Default::default()
+        };
+
+        let page = get_entries(&filter, 0, 3);
+        assert_eq!(
+            page.entries
+                .iter()
+                .map(|entry| entry.id)
+                .collect::<Vec<_>>(),
+            vec![2, 4, 6]
+        );
+        assert_eq!(page.next, Some(8));
+
+        let page = get_entries(&filter, page.next.unwrap(), 3);
+        assert_eq!(
+            page.entries
+                .iter()
+                .map(|entry| entry.id)
+                .collect::<Vec<_>>(),
+            vec![8]
+        );
+        assert_eq!(page.next, None);
+    }
+
+    #[test]
+    fn the_oldest_entries_are_dropped() {
+        let mut log = AuditLog::default();
+        for id in 0..5 {
+            let entry = AuditEntry {
+                id,
+                caller: Principal::anonymous(),
+                method: String::from("pause"),
+                arguments_digest: digest(&()),
+                timestamp: id,
+                outcome: AuditOutcome::Success,
+            };
+            log.insert(entry, 3);
+        }
+
+        assert_eq!(
+            log.entries.keys().copied().collect::<Vec<_>>(),
+            vec![2, 3, 4]
+        );
+        // ids keep growing once entries are dropped
+        assert_eq!(log.next_id, 5);
+    }
+}
@@ -0,0 +1,164 @@
+use candid::CandidType;
+use serde::Deserialize;
+use std::{cell::RefCell, collections::HashMap, fmt::Display};
+
+use crate::{
+    constants::{CHAIN_DELIMITER, SOLANA_NAMESPACE},
+    CcampError,
+};
+
+pub type ChainRegistryStore = HashMap<Chain, ChainMetadata>;
+
+thread_local! {
+    pub static CHAIN_REGISTRY: RefCell<ChainRegistryStore> = RefCell::default();
+}
+
+// a CAIP-2 chain identifier i.e `namespace:reference`
+// e.g "ethereum:5" is the namespace "ethereum" and the reference "5"
+#[derive(Clone, Debug, Deserialize, CandidType, PartialEq, Hash, Eq, PartialOrd, Ord)]
+pub struct Chain {
+    pub namespace: String,
+    pub reference: String,
+}
+impl TryFrom<String> for Chain {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let (namespace, reference) = value
+            .split_once(CHAIN_DELIMITER)
+            .ok_or(String::from("INVALID_CHAIN"))?;
+
+        // namespace: [-a-z0-9]{3,8}
+        let is_namespace_valid = (3..=8).contains(&namespace.len())
+            && namespace
+                .chars()
+                .all(|c| c == '-' || c.is_ascii_lowercase() || c.is_ascii_digit());
+        // reference: [-_a-zA-Z0-9]{1,32}
+        let is_reference_valid = (1..=32).contains(&reference.len())
+            && reference
+                .chars()
+                .all(|c| c == '-' || c == '_' || c.is_ascii_alphanumeric());
+
+        if !is_namespace_valid || !is_reference_valid {
+            return Err(String::from("INVALID_CHAIN"));
+        }
+
+        Ok(Self {
+            namespace: namespace.to_string(),
+            reference: reference.to_string(),
+        })
+    }
+}
+impl Chain {
+    // the signature scheme the withdrawals on this chain are authorized with
+    pub fn signature_scheme(&self) -> SignatureScheme {
+        match self.namespace.as_str() {
+            SOLANA_NAMESPACE => SignatureScheme::Ed25519,
+            _ => SignatureScheme::EcdsaSecp256k1,
+        }
+    }
+}
+impl Display for Chain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}{}", self.namespace, CHAIN_DELIMITER, self.reference)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, CandidType, PartialEq)]
+pub enum SignatureScheme {
+    EcdsaSecp256k1,
+    Ed25519,
+}
+
+// details about a chain which are managed by the owner of the canister
+#[derive(Clone, Debug, Deserialize, CandidType, PartialEq)]
+pub struct ChainMetadata {
+    pub name: String,
+    // number of blocks after which an event on this chain is considered final
+    pub finality_depth: u64,
+    // address of the locker contract deployed on this chain if any
+    pub locker_address: Option<String>,
+}
+
+// the chains supported by the protocol out of the box
+pub fn default_chains() -> Vec<(Chain, ChainMetadata)> {
+    vec![
+        (
+            String::from("ethereum:1").try_into().unwrap(),
+            ChainMetadata {
+                name: String::from("Ethereum Mainnet"),
+                finality_depth: 64,
+                locker_address: None,
+            },
+        ),
+        (
+            String::from("ethereum:5").try_into().unwrap(),
+            ChainMetadata {
+                name: String::from("Ethereum Goerli"),
+                finality_depth: 64,
+                locker_address: Some(String::from("0xadBBdcdbF9B77720775103dd126346686C1d9011")),
+            },
+        ),
+        (
+            String::from("polygon:137").try_into().unwrap(),
+            ChainMetadata {
+                name: String::from("Polygon Mainnet"),
+                finality_depth: 256,
+                locker_address: Some(String::from("0xc2f58141440314f32568496FECdFd0CceaBF1627")),
+            },
+        ),
+    ]
+}
+
+pub fn init_default_chains() {
+    for (chain, metadata) in default_chains() {
+        register_chain(chain, metadata);
+    }
+}
+
+// add a chain to the registry or update the metadata of an existing one
+pub fn register_chain(chain: Chain, metadata: ChainMetadata) {
+    CHAIN_REGISTRY.with(|registry| {
+        registry.borrow_mut().insert(chain, metadata);
+    });
+}
+
+pub fn deregister_chain(chain: &Chain) -> Option<ChainMetadata> {
+    CHAIN_REGISTRY.with(|registry| registry.borrow_mut().remove(chain))
+}
+
+pub fn get_chain_metadata(chain: &Chain) -> Option<ChainMetadata> {
+    CHAIN_REGISTRY.with(|registry| registry.borrow().get(chain).cloned())
+}
+
+pub fn is_registered(chain: &Chain) -> bool {
+    CHAIN_REGISTRY.with(|registry| registry.borrow().contains_key(chain))
+}
+
+pub fn get_registered_chains() -> Vec<(Chain, ChainMetadata)> {
+    CHAIN_REGISTRY.with(|registry| {
+        let mut chains: Vec<(Chain, ChainMetadata)> = registry
+            .borrow()
+            .iter()
+            .map(|(chain, metadata)| (chain.clone(), metadata.clone()))
+            .collect();
+        chains.sort_by(|a, b| a.0.cmp(&b.0));
+
+        chains
+    })
+}
+
+// parse a CAIP-2 string and make sure the chain it refers to has been registered
+pub fn resolve_chain(chain: String) -> Result<Chain, CcampError> {
+    let chain: Chain = chain
+        .clone()
+        .try_into()
+        .map_err(|_| CcampError::InvalidChain { chain })?;
+    if !is_registered(&chain) {
+        return Err(CcampError::ChainNotRegistered {
+            chain: chain.to_string(),
+        });
+    }
+
+    Ok(chain)
+}
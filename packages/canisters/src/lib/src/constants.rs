@@ -1,4 +1,16 @@
 #![allow(dead_code)]
 
 pub const CHAIN_DELIMITER: &str = ":";
+// the CAIP-2 namespace of the solana clusters
+pub const SOLANA_NAMESPACE: &str = "solana";
 pub const ZERO_ADDRESS: &str = "0x0000000000000000000000000000000000000000";
+
+// the topic the balance changes are published to the remittance canisters under
+pub const REMITTANCE_TOPIC: &str = "REMITTANCE";
+
+// the EIP-712 domain of the locker contracts which withdrawals are authorized for
+pub const LOCKER_DOMAIN_NAME: &str = "CCAMP Locker";
+pub const LOCKER_DOMAIN_VERSION: &str = "1";
+
+// the prefix of the messages which authorize withdrawals on solana
+pub const SOLANA_WITHDRAWAL_PREFIX: &str = "ccamp:withdrawal";
@@ -1,99 +1,281 @@
-use candid::{CandidType, Principal};
-use ic_cdk::{api::{self}, id};
+use candid::{utils::ArgumentEncoder, CandidType, Principal};
+use ic_cdk::{
+    api::{self, time},
+    id,
+};
 use serde_derive::Deserialize;
-use serde_json::Value;
 use std::{cell::RefCell, collections::BTreeMap};
 
-use crate::Event;
-pub type SubscriberStore = BTreeMap<Principal, crate::Subscriber>;
+use crate::{
+    constants::REMITTANCE_TOPIC,
+    validation::{validate_json_events, BatchMode, ValidationReport},
+    Action, Address, CcampError, Chain, DataModel, EventIdentity,
+};
+
+// the canisters which were whitelisted to subscribe to this canister
+pub type SubscriberStore = BTreeMap<Principal, Subscription>;
 
 thread_local! {
-    pub static REMITTANCE_CANISTER: RefCell<Option<crate::RemittanceSubscriber>> = RefCell::default();
+    pub static SUBSCRIBERS: RefCell<SubscriberStore> = RefCell::default();
 }
 
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct Account {
-    pub balance: u64,
+    pub balance: candid::Nat,
 }
 
-//admin function to directly set a value for the remittance canister
-pub fn set_remittance_canister(remittance_principal: Principal) {
-    REMITTANCE_CANISTER.with(|rc| {
-        let _ = rc.borrow_mut().insert(crate::RemittanceSubscriber {
-            canister_principal: remittance_principal,
-            subscribed: false,
-        });
+// the events of a topic a subscriber receives
+// an empty list matches every value of the field
+#[derive(Clone, Debug, Default, CandidType, Deserialize, PartialEq)]
+pub struct TopicFilter {
+    pub actions: Vec<Action>,
+    pub chains: Vec<Chain>,
+    pub tokens: Vec<Address>,
+}
+impl TopicFilter {
+    pub fn matches(&self, event: &DataModel) -> bool {
+        (self.actions.is_empty() || self.actions.contains(&event.action))
+            && (self.chains.is_empty() || self.chains.contains(&event.chain))
+            && (self.tokens.is_empty() || self.tokens.contains(&event.token))
+    }
+}
+
+// the outcome of the batches of events published to a subscriber
+#[derive(Clone, Debug, Default, CandidType, Deserialize, PartialEq)]
+pub struct DeliveryStatus {
+    pub delivered: u64,
+    pub failed: u64,
+    // timestamp in nanoseconds of the last delivery attempt
+    pub last_attempt: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub struct Subscription {
+    pub canister_principal: Principal,
+    // set once the whitelisted canister subscribed to a topic
+    pub topic: Option<String>,
+    pub filter: TopicFilter,
+    pub delivery: DeliveryStatus,
+}
+impl Subscription {
+    fn is_subscribed_to(&self, topic: &str) -> bool {
+        self.topic.as_deref() == Some(topic)
+    }
+}
+
+//admin function to whitelist a canister which can then subscribe to this canister
+pub fn add_subscriber(canister_principal: Principal) {
+    SUBSCRIBERS.with(|subscribers| {
+        subscribers
+            .borrow_mut()
+            .entry(canister_principal)
+            .or_insert(Subscription {
+                canister_principal,
+                topic: None,
+                filter: TopicFilter::default(),
+                delivery: DeliveryStatus::default(),
+            });
     })
 }
 
-// get the remittance canister
-pub fn get_remittance_canister() -> crate::RemittanceSubscriber {
-    // confirm at least one remittance canister is subscribed to this pdc
-    REMITTANCE_CANISTER
-        .with(|rc| rc.borrow().clone())
-        .expect("REMITTANCE_CANISTER_NOT_INITIALIZED")
+pub fn remove_subscriber(canister_principal: Principal) {
+    SUBSCRIBERS.with(|subscribers| subscribers.borrow_mut().remove(&canister_principal));
 }
 
-// this function is going to be called by the remittance canister which wants to be able to recieve data from this canister
-pub fn subscribe() {
-    // verify if this remittance canister has been whitelisted
-    // set the subscribed value to true if its the same, otherwise panic
+pub fn get_subscribers() -> Vec<Subscription> {
+    SUBSCRIBERS.with(|subscribers| subscribers.borrow().values().cloned().collect())
+}
+
+// the remittance canister the balances of this canister are queried from
+// i.e the first canister subscribed to the remittance topic
+pub fn get_remittance_canister() -> Result<Principal, CcampError> {
+    SUBSCRIBERS.with(|subscribers| {
+        subscribers
+            .borrow()
+            .values()
+            .find(|subscription| subscription.is_subscribed_to(REMITTANCE_TOPIC))
+            .map(|subscription| subscription.canister_principal)
+            .ok_or(CcampError::NoSubscribers {
+                topic: REMITTANCE_TOPIC.to_string(),
+            })
+    })
+}
+
+// this function is going to be called by a whitelisted canister which wants to be able to recieve data from this canister
+pub fn subscribe(subscriber: crate::Subscriber) -> Result<(), CcampError> {
     let subscriber_principal_id = ic_cdk::caller();
-    let whitelisted_remittance_canister = REMITTANCE_CANISTER
-        .with(|rc| rc.borrow().clone())
-        .expect("REMITTANCE_CANISTER_NOT_INITIALIZED");
-
-    if whitelisted_remittance_canister.canister_principal != subscriber_principal_id {
-        panic!("REMITTANCE_CANISTER_NOT_WHITELISTED")
-    };
-
-    REMITTANCE_CANISTER.with(|rc| {
-        let _ = rc.borrow_mut().insert(crate::RemittanceSubscriber {
-            canister_principal: subscriber_principal_id,
-            subscribed: true,
+    if subscriber.topic != REMITTANCE_TOPIC {
+        return Err(CcampError::InvalidTopic {
+            topic: subscriber.topic,
         });
-    });
+    }
+
+    SUBSCRIBERS.with(|subscribers| {
+        let mut subscribers = subscribers.borrow_mut();
+        let subscription = subscribers.get_mut(&subscriber_principal_id).ok_or(
+            CcampError::SubscriberNotWhitelisted {
+                caller: subscriber_principal_id,
+            },
+        )?;
+
+        subscription.topic = Some(subscriber.topic);
+        subscription.filter = subscriber.filter.unwrap_or_default();
+
+        Ok(())
+    })
 }
 
-// pass in a remittance canister principal to confirm if it has been subscribed to this data canister
+// pass in a canister principal to confirm if it has subscribed to this data canister
 pub fn is_subscribed(canister_principal: Principal) -> bool {
-    let whitelisted_remittance_canister = REMITTANCE_CANISTER
-        .with(|rc| rc.borrow().clone())
-        .expect("REMITTANCE_CANISTER_NOT_INITIALIZED");
+    SUBSCRIBERS.with(|subscribers| {
+        subscribers
+            .borrow()
+            .get(&canister_principal)
+            .is_some_and(|subscription| subscription.topic.is_some())
+    })
+}
+
+// the subscribers of the topic along with the events of the batch which match their filter
+pub(crate) fn matching_subscribers(
+    topic: &str,
+    events: &[DataModel],
+) -> Vec<(Principal, Vec<DataModel>)> {
+    SUBSCRIBERS.with(|subscribers| {
+        subscribers
+            .borrow()
+            .values()
+            .filter(|subscription| subscription.is_subscribed_to(topic))
+            .map(|subscription| {
+                let matching_events: Vec<DataModel> = events
+                    .iter()
+                    .filter(|event| subscription.filter.matches(event))
+                    .cloned()
+                    .collect();
+
+                (subscription.canister_principal, matching_events)
+            })
+            .filter(|(_, matching_events)| !matching_events.is_empty())
+            .collect()
+    })
+}
 
-    return whitelisted_remittance_canister.canister_principal == canister_principal
-        && return whitelisted_remittance_canister.canister_principal == canister_principal
-            && whitelisted_remittance_canister.subscribed;
+pub(crate) fn record_delivery(subscriber: &Principal, result: &Result<(), CcampError>) {
+    SUBSCRIBERS.with(|subscribers| {
+        if let Some(subscription) = subscribers.borrow_mut().get_mut(subscriber) {
+            let delivery = &mut subscription.delivery;
+            delivery.last_attempt = Some(time());
+            match result {
+                Ok(()) => delivery.delivered += 1,
+                Err(error) => {
+                    delivery.failed += 1;
+                    delivery.last_error = Some(error.to_string());
+                }
+            }
+        }
+    })
+}
+
+pub(crate) fn ensure_subscribed(topic: &str) -> Result<(), CcampError> {
+    let has_subscribers = SUBSCRIBERS.with(|subscribers| {
+        subscribers
+            .borrow()
+            .values()
+            .any(|subscription| subscription.is_subscribed_to(topic))
+    });
+    if !has_subscribers {
+        return Err(CcampError::NoSubscribers {
+            topic: topic.to_string(),
+        });
+    }
+
+    Ok(())
 }
 
-// we would use this method to publish data to the subscriber
-// which would be the remittance model
-// so when we have some new data, we would publish it to the remittance model
+// we would use this method to publish data to the subscribers
+// which would be the remittance models
+// so when we have some new data, we would publish it to every remittance model interested in it
+// the first error returned by a subscriber is returned once every subscriber was called
 pub async fn update_remittance_canister(
-    events: &Vec<crate::DataModel>,
+    events: &[DataModel],
     dc_canister: &Principal,
-) -> Result<(), String> {
-    let whitelisted_remittance_canister = get_remittance_canister();
+) -> Result<(), CcampError> {
+    ensure_subscribed(REMITTANCE_TOPIC)?;
 
-    if !whitelisted_remittance_canister.subscribed {
-        panic!("REMITTANCE_CANISTER_NOT_INITIALIZED")
+    let mut response = Ok(());
+    for (subscriber, matching_events) in matching_subscribers(REMITTANCE_TOPIC, events) {
+        let result = api::call::call(
+            subscriber,
+            "update_remittance",
+            (&matching_events, dc_canister),
+        )
+        .await
+        .map_err(|(code, message)| CcampError::CallFailed {
+            method: String::from("update_remittance"),
+            reason: format!("{:?}:{}", code, message),
+        })
+        .and_then(|(remittance_response,): (Result<(), CcampError>,)| remittance_response);
+
+        record_delivery(&subscriber, &result);
+        if response.is_ok() {
+            response = result;
+        }
     }
 
-    let (remittance_response,): (Result<(), String>,) = api::call::call(
-        whitelisted_remittance_canister.canister_principal,
-        "update_remittance",
-        (&events, dc_canister),
-    )
-    .await
-    .unwrap();
+    response
+}
 
-    remittance_response
+// forward a block reported by a publisher to the subscribers receiving the events of its chain
+// publishers report a block again as it gets deeper so the reports aren't kept in the outbox
+pub async fn forward_block_report<A: ArgumentEncoder + Clone>(
+    method: &str,
+    chain: &Chain,
+    arguments: A,
+) -> Result<(), CcampError> {
+    ensure_subscribed(REMITTANCE_TOPIC)?;
+    let subscribers: Vec<Principal> = SUBSCRIBERS.with(|subscribers| {
+        subscribers
+            .borrow()
+            .values()
+            .filter(|subscription| subscription.is_subscribed_to(REMITTANCE_TOPIC))
+            .filter(|subscription| {
+                let chains = &subscription.filter.chains;
+                chains.is_empty() || chains.contains(chain)
+            })
+            .map(|subscription| subscription.canister_principal)
+            .collect()
+    });
+
+    let mut response = Ok(());
+    for subscriber in subscribers {
+        let result = api::call::call(subscriber, method, arguments.clone())
+            .await
+            .map_err(|(code, message)| CcampError::CallFailed {
+                method: method.to_string(),
+                reason: format!("{:?}:{}", code, message),
+            })
+            .and_then(
+                |(report_response,): (Result<Vec<EventIdentity>, CcampError>,)| {
+                    report_response.map(|_| ())
+                },
+            );
+
+        if response.is_ok() {
+            response = result;
+        }
+    }
+
+    response
 }
 
-pub async fn publish_json_to_remittance(json_data: String) -> Result<(), String> {
+pub async fn publish_json_to_remittance(
+    json_data: String,
+    mode: BatchMode,
+) -> Result<ValidationReport, CcampError> {
     // the string provided should be an array of events
     // the same format the ccamp uses to fetch events from logstore
     // probably for some reason were missed by the poller or for some other reason we need a manual provision of events
+    // amounts which are too large to be represented as a json number can be passed as a string
     // schema
     // NOTE: a DC canister can only perform `BalanceAdjusted` adjust operations
     // [{
@@ -105,29 +287,78 @@ pub async fn publish_json_to_remittance(json_data: String) -> Result<(), String>
     //     "token": "0xB24a30A3971e4d9bf771BDc81435c25EA69A445c"
     // }]
 
-    // Parse the string of data into serde_json::Value.
-    let json_event: Value =
-        serde_json::from_str(&json_data[..]).expect("JSON_DESERIALIZATION_FAILED");
-    // Make sure the top-level JSON is an array
-    let update_succesful = if let Value::Array(events) = json_event {
-        let mut parsed_events: Vec<crate::DataModel> = Vec::new();
-
-        for event in events {
-            // parse the json object gotten back into an "'Event' struct"
-            let json_event: Event = serde_json::from_value(event).unwrap();
-            // convert each "event" object into a data model and send it to the remittance canister
-            let parsed_event: crate::DataModel = json_event.into();
-            // send this info over to the remittance canister in order to modify the balances
-            parsed_events.push(parsed_event);
-        }
+    // validate every event and keep track of the ones which were rejected
+    let (validated_events, results) = validate_json_events(&json_data)?;
+    let applied = mode.should_apply(&results);
+
+    // send the valid events over to the remittance canister in order to modify the balances
+    if applied {
+        let parsed_events: Vec<crate::DataModel> = validated_events
+            .into_iter()
+            .map(|event| event.data_model)
+            .collect();
+        update_remittance_canister(&parsed_events, &id()).await?;
+    }
+
+    Ok(ValidationReport {
+        events: results,
+        applied,
+    })
+}
 
-        let dc_canister = id();
-        let response = update_remittance_canister(&parsed_events, &dc_canister).await;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        response
-    } else {
-        Err("ERROR_PARSING_EVENT_INTO_DATAMODEL".to_string())
-    };
+    fn event(action: Action, chain: &str) -> DataModel {
+        DataModel {
+            token: String::from("0xB24a30A3971e4d9bf771BDc81435c25EA69A445c")
+                .try_into()
+                .unwrap(),
+            chain: String::from(chain).try_into().unwrap(),
+            amount: candid::Int::from(100),
+            account: String::from("0x9C81E8F60a9B8743678F1b6Ae893Cc72c6Bc6840")
+                .try_into()
+                .unwrap(),
+            action,
+            identity: None,
+            nonce: None,
+        }
+    }
 
-    update_succesful
+    #[test]
+    fn subscribers_only_receive_the_events_matching_their_filter() {
+        let everything = Principal::from_slice(&[1]);
+        let polygon_deposits = Principal::from_slice(&[2]);
+        let unsubscribed = Principal::from_slice(&[3]);
+        for subscriber in [everything, polygon_deposits, unsubscribed] {
+            add_subscriber(subscriber);
+        }
+        SUBSCRIBERS.with(|subscribers| {
+            let mut subscribers = subscribers.borrow_mut();
+            for subscriber in [everything, polygon_deposits] {
+                subscribers.get_mut(&subscriber).unwrap().topic =
+                    Some(REMITTANCE_TOPIC.to_string());
+            }
+            subscribers.get_mut(&polygon_deposits).unwrap().filter = TopicFilter {
+                actions: vec![Action::Deposit],
+                chains: vec![String::from("polygon:137").try_into().unwrap()],
+                tokens: vec![],
+            };
+        });
+        let events = vec![
+            event(Action::Deposit, "ethereum:5"),
+            event(Action::Deposit, "polygon:137"),
+            event(Action::Withdraw, "polygon:137"),
+        ];
+
+        assert_eq!(
+            matching_subscribers(REMITTANCE_TOPIC, &events),
+            vec![
+                (everything, events.clone()),
+                (polygon_deposits, vec![events[1].clone()])
+            ]
+        );
+        assert!(!is_subscribed(unsubscribed));
+    }
 }
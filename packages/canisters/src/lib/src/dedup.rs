@@ -0,0 +1,160 @@
+use candid::CandidType;
+use serde::Deserialize;
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+};
+
+use crate::{CcampError, Chain, DataModel, EventIdentity};
+
+// the most identities kept, the oldest ones are forgotten first once it is reached
+pub const MAX_PROCESSED_EVENTS: usize = 100_000;
+
+thread_local! {
+    pub static PROCESSED_EVENTS: RefCell<ProcessedEvents> = RefCell::default();
+}
+
+pub type EventKey = (Chain, String, u32);
+
+#[derive(Clone, Debug, Default, Deserialize, CandidType, PartialEq)]
+pub struct ProcessedEvents {
+    // the position of every identity in the order they were processed
+    pub positions: BTreeMap<EventKey, u64>,
+    pub identities: BTreeMap<u64, EventIdentity>,
+    pub next_position: u64,
+}
+impl ProcessedEvents {
+    pub fn contains(&self, identity: &EventIdentity) -> bool {
+        self.positions.contains_key(&identity.key())
+    }
+
+    pub fn insert(&mut self, identity: EventIdentity, capacity: usize) {
+        if self.contains(&identity) {
+            return;
+        }
+
+        self.positions.insert(identity.key(), self.next_position);
+        self.identities.insert(self.next_position, identity);
+        self.next_position += 1;
+
+        while self.identities.len() > capacity {
+            if let Some((_, evicted)) = self.identities.pop_first() {
+                self.positions.remove(&evicted.key());
+            }
+        }
+    }
+
+    pub fn remove(&mut self, identity: &EventIdentity) {
+        if let Some(position) = self.positions.remove(&identity.key()) {
+            self.identities.remove(&position);
+        }
+    }
+}
+
+pub fn is_processed(identity: &EventIdentity) -> bool {
+    PROCESSED_EVENTS.with(|processed| processed.borrow().contains(identity))
+}
+
+// reject a batch containing an event which was processed already or which appears twice in it
+pub fn ensure_unique(events: &[DataModel]) -> Result<(), CcampError> {
+    let mut batch_keys: BTreeSet<EventKey> = BTreeSet::new();
+    for identity in events.iter().filter_map(|event| event.identity.as_ref()) {
+        if is_processed(identity) || !batch_keys.insert(identity.key()) {
+            return Err(CcampError::DuplicateEvent {
+                identity: identity.clone(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+// remember the events of a batch which was applied so they can't be applied again
+pub fn record(events: &[DataModel]) {
+    PROCESSED_EVENTS.with(|processed| {
+        let mut processed = processed.borrow_mut();
+        for identity in events.iter().filter_map(|event| event.identity.clone()) {
+            processed.insert(identity, MAX_PROCESSED_EVENTS);
+        }
+    })
+}
+
+// forget an event which was reversed so it can be processed again e.g once it is included in another block
+pub fn forget(identity: &EventIdentity) {
+    PROCESSED_EVENTS.with(|processed| processed.borrow_mut().remove(identity))
+}
+
+pub fn get_state() -> ProcessedEvents {
+    PROCESSED_EVENTS.with(|processed| processed.borrow().clone())
+}
+
+pub fn restore_state(state: ProcessedEvents) {
+    PROCESSED_EVENTS.with(|processed| *processed.borrow_mut() = state);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Action, Event};
+
+    fn deposit(transaction_hash: &str, block_hash: &str) -> DataModel {
+        DataModel::try_from(Event {
+            event_name: String::from("FundsDeposited"),
+            canister_id: String::from("bkyz2-fmaaa-aaaaa-qaaaq-cai"),
+            account: String::from("0x9C81E8F60a9B8743678F1b6Ae893Cc72c6Bc6840"),
+            amount: candid::Int::from(100),
+            chain: String::from("ethereum:5"),
+            token: String::from("0xB24a30A3971e4d9bf771BDc81435c25EA69A445c"),
+            transaction_hash: Some(transaction_hash.to_string()),
+            block_hash: Some(block_hash.to_string()),
+            log_index: Some(0),
+            nonce: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn events_are_only_processed_once() {
+        let event = deposit("0xAB01", "0x01");
+        ensure_unique(std::slice::from_ref(&event)).unwrap();
+        record(std::slice::from_ref(&event));
+
+        // the same log resubmitted with another case or included in another block after a reorg
+        for duplicate in [deposit("0xab01", "0x01"), deposit("0xAB01", "0x02")] {
+            assert_eq!(
+                ensure_unique(std::slice::from_ref(&duplicate)),
+                Err(CcampError::DuplicateEvent {
+                    identity: duplicate.identity.unwrap()
+                })
+            );
+        }
+        let other = deposit("0xab02", "0x01");
+        assert!(ensure_unique(&[other.clone(), other]).is_err());
+
+        // adjustments have no identity and are never considered duplicates
+        let adjustment = DataModel {
+            action: Action::Adjust,
+            identity: None,
+            nonce: None,
+            ..event
+        };
+        assert_eq!(ensure_unique(&[adjustment.clone(), adjustment]), Ok(()));
+    }
+
+    #[test]
+    fn the_oldest_identities_are_evicted() {
+        let mut processed = ProcessedEvents::default();
+        let identities: Vec<EventIdentity> = ["0x01", "0x02", "0x03"]
+            .iter()
+            .map(|hash| deposit(hash, "0x01").identity.unwrap())
+            .collect();
+        for identity in identities.iter().cloned() {
+            processed.insert(identity, 2);
+        }
+
+        assert!(!processed.contains(&identities[0]));
+        assert!(processed.contains(&identities[1]));
+        assert!(processed.contains(&identities[2]));
+        assert_eq!(processed.positions.len(), 2);
+    }
+}
@@ -1,11 +1,19 @@
 #![allow(dead_code)]
 
-use crate::{ecdsa, remittance::Config};
+use crate::{ecdsa, ethereum, remittance::Config};
 use ic_cdk::export::{
     candid::CandidType,
     serde::{Deserialize, Serialize},
     Principal,
 };
+use std::{cell::RefCell, collections::HashMap};
+
+// derived public keys by key name and derivation path
+pub type PublicKeyCacheStore = HashMap<(String, Vec<Vec<u8>>), CachedPublicKey>;
+
+thread_local! {
+    pub static PUBLIC_KEY_CACHE: RefCell<PublicKeyCacheStore> = RefCell::default();
+}
 
 #[derive(CandidType, Serialize, Debug)]
 pub struct PublicKeyReply {
@@ -13,6 +21,21 @@ pub struct PublicKeyReply {
     pub etherum_pk: String,
 }
 
+// a threshold public key along with the ethereum address derived from it
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq)]
+pub struct CachedPublicKey {
+    pub public_key: Vec<u8>,
+    pub address: String,
+}
+impl From<CachedPublicKey> for PublicKeyReply {
+    fn from(cached: CachedPublicKey) -> Self {
+        Self {
+            sec1_pk: hex::encode(cached.public_key),
+            etherum_pk: cached.address,
+        }
+    }
+}
+
 #[derive(CandidType, Serialize, Debug)]
 pub struct SignatureReply {
     pub signature_hex: String,
@@ -86,10 +109,10 @@ impl EcdsaKeyIds {
     }
 }
 
-pub async fn derive_pk(config:&Config) -> Vec<u8> {
+pub async fn derive_pk(config: &Config, derivation_path: Vec<Vec<u8>>) -> Result<Vec<u8>, String> {
     let request = ecdsa::ECDSAPublicKey {
         canister_id: None,
-        derivation_path: vec![],
+        derivation_path,
         key_id: config.key.to_key_id(),
     };
     let (res,): (ecdsa::ECDSAPublicKeyReply,) = ic_cdk::call(
@@ -98,9 +121,49 @@ pub async fn derive_pk(config:&Config) -> Vec<u8> {
         (request,),
     )
     .await
-    .map_err(|e| format!("ECDSA_PUBLIC_KEY_FAILED {}", e.1))
-    .unwrap();
+    .map_err(|e| format!("ECDSA_PUBLIC_KEY_FAILED {}", e.1))?;
 
-    res.public_key
+    Ok(res.public_key)
 }
 
+pub fn get_cached_public_key(
+    config: &Config,
+    derivation_path: &[Vec<u8>],
+) -> Option<CachedPublicKey> {
+    let cache_key = (config.key.to_key_id().name, derivation_path.to_vec());
+
+    PUBLIC_KEY_CACHE.with(|cache| cache.borrow().get(&cache_key).cloned())
+}
+
+// get the public key from the cache or derive it and cache it along with its address
+pub async fn cached_public_key(
+    config: &Config,
+    derivation_path: Vec<Vec<u8>>,
+) -> Result<CachedPublicKey, String> {
+    if let Some(cached) = get_cached_public_key(config, &derivation_path) {
+        return Ok(cached);
+    }
+
+    let public_key = derive_pk(config, derivation_path.clone()).await?;
+    let cached = CachedPublicKey {
+        address: ethereum::get_address_from_public_key(public_key.clone())?,
+        public_key,
+    };
+    PUBLIC_KEY_CACHE.with(|cache| {
+        cache.borrow_mut().insert(
+            (config.key.to_key_id().name, derivation_path),
+            cached.clone(),
+        )
+    });
+
+    Ok(cached)
+}
+
+// remove the cached keys whose derivation path matches the predicate
+pub fn evict_cached_public_keys<F: Fn(&[Vec<u8>]) -> bool>(predicate: F) {
+    PUBLIC_KEY_CACHE.with(|cache| {
+        cache
+            .borrow_mut()
+            .retain(|(_, derivation_path), _| !predicate(derivation_path))
+    });
+}
@@ -0,0 +1,176 @@
+use candid::{CandidType, Int, Nat, Principal};
+use serde::Deserialize;
+use std::fmt::Display;
+
+use crate::{ethereum::SignatureError, owner::Role, pause::Operation, Asset, EventIdentity};
+
+// the errors returned to clients by the canisters of the protocol
+// every variant carries the details needed to act on it without parsing a reject message
+#[derive(Clone, Debug, Deserialize, CandidType, PartialEq)]
+pub enum CcampError {
+    NotAllowed { caller: Principal },
+    MissingRole { caller: Principal, role: Role },
+    NotPendingOwner { caller: Principal },
+    OperationPaused { operation: Operation },
+    PublisherNotWhitelisted { caller: Principal },
+    SubscriberNotWhitelisted { caller: Principal },
+    NoSubscribers { topic: String },
+    InvalidTopic { topic: String },
+    SequenceGap { expected: u64, received: u64 },
+    DeliveryNotPending { sequence: u64 },
+    DeliveryNotSkipped { sequence: u64 },
+    UnsupportedStateVersion { version: u32 },
+    InvalidSignature { reason: String },
+    SignatureMismatch { recovered: String, expected: String },
+    ProofExpired { expiry: u64, now: u64 },
+    InvalidProofNonce { expected: u64, received: u64 },
+    LegacyProofDisabled,
+    InvalidChain { chain: String },
+    ChainNotRegistered { chain: String },
+    LockerNotRegistered { chain: String },
+    InvalidAddress { address: String, reason: String },
+    InvalidAction { action: String },
+    InvalidAmount { amount: Int },
+    InsufficientBalance { requested: Nat, available: Nat },
+    InsufficientCanisterBalance { requested: Nat, available: Nat },
+    RemittanceInProgress { amount: Nat },
+    UnmatchedWithdrawalTicket { nonce: u64 },
+    UnbalancedAdjustment { asset: Asset, delta: Int },
+    UnbalancedEntry { asset: Asset, delta: Int },
+    InvalidEvent { reason: String },
+    DuplicateEvent { identity: EventIdentity },
+    BlockOrphaned { chain: String, block_hash: String },
+    BlockFinalized { chain: String, block_hash: String },
+    InvalidJson { reason: String },
+    NotEnoughValidations { required: u64, received: u64 },
+    InvalidMessageContent,
+    SigningFailed { reason: String },
+    UnknownKeyVersion { version: u32 },
+    KeyScopeRequired { version: u32 },
+    KeyRotationPending { version: u32 },
+    KeyVersionNotPending { version: u32 },
+    PublicKeyNotCached { version: u32 },
+    CallFailed { method: String, reason: String },
+    TokenOperationFailed { reason: String },
+}
+
+// the display representation mirrors the upper case codes used in reject messages
+impl Display for CcampError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotAllowed { caller } => write!(f, "NOT_ALLOWED:{}", caller),
+            Self::MissingRole { caller, role } => write!(f, "MISSING_ROLE:{}:{:?}", caller, role),
+            Self::NotPendingOwner { caller } => write!(f, "NOT_PENDING_OWNER:{}", caller),
+            Self::OperationPaused { operation } => write!(f, "OPERATION_PAUSED:{:?}", operation),
+            Self::PublisherNotWhitelisted { caller } => {
+                write!(f, "PUBLISHER_NOT_WHITELISTED:{}", caller)
+            }
+            Self::SubscriberNotWhitelisted { caller } => {
+                write!(f, "SUBSCRIBER_NOT_WHITELISTED:{}", caller)
+            }
+            Self::NoSubscribers { topic } => write!(f, "NO_SUBSCRIBERS:{}", topic),
+            Self::InvalidTopic { topic } => write!(f, "INVALID_TOPIC:{}", topic),
+            Self::SequenceGap { expected, received } => write!(
+                f,
+                "SEQUENCE_GAP:expected {}; received {}",
+                expected, received
+            ),
+            Self::DeliveryNotPending { sequence } => {
+                write!(f, "DELIVERY_NOT_PENDING:{}", sequence)
+            }
+            Self::DeliveryNotSkipped { sequence } => {
+                write!(f, "DELIVERY_NOT_SKIPPED:{}", sequence)
+            }
+            Self::UnsupportedStateVersion { version } => {
+                write!(f, "UNSUPPORTED_STATE_VERSION:{}", version)
+            }
+            Self::InvalidSignature { reason } => write!(f, "INVALID_SIGNATURE:{}", reason),
+            Self::SignatureMismatch {
+                recovered,
+                expected,
+            } => write!(
+                f,
+                "SIGNATURE_VERIFICATION_FAILED:recovered key: {}; public key:{}",
+                recovered, expected
+            ),
+            Self::ProofExpired { expiry, now } => write!(f, "PROOF_EXPIRED:{} <= {}", expiry, now),
+            Self::InvalidProofNonce { expected, received } => write!(
+                f,
+                "INVALID_PROOF_NONCE:expected {}; received {}",
+                expected, received
+            ),
+            Self::LegacyProofDisabled => write!(f, "LEGACY_PROOF_DISABLED"),
+            Self::InvalidChain { chain } => write!(f, "INVALID_CHAIN:{}", chain),
+            Self::ChainNotRegistered { chain } => write!(f, "CHAIN_NOT_REGISTERED:{}", chain),
+            Self::LockerNotRegistered { chain } => write!(f, "LOCKER_NOT_REGISTERED:{}", chain),
+            Self::InvalidAddress { address, reason } => {
+                write!(f, "INVALID_ADDRESS:{}:{}", address, reason)
+            }
+            Self::InvalidAction { action } => write!(f, "INVALID_ACTION:{}", action),
+            Self::InvalidAmount { amount } => write!(f, "INVALID_AMOUNT:{}", amount),
+            Self::InsufficientBalance {
+                requested,
+                available,
+            } => write!(f, "INSUFFICIENT_USER_BALANCE:{} > {}", requested, available),
+            Self::InsufficientCanisterBalance {
+                requested,
+                available,
+            } => write!(
+                f,
+                "INSUFFICIENT_CANISTER_BALANCE:{} > {}",
+                requested, available
+            ),
+            Self::RemittanceInProgress { amount } => {
+                write!(f, "REMITTANCE_IN_PROGRESS:{}", amount)
+            }
+            Self::UnmatchedWithdrawalTicket { nonce } => {
+                write!(f, "UNMATCHED_WITHDRAWAL_TICKET:{}", nonce)
+            }
+            Self::UnbalancedAdjustment { asset, delta } => {
+                write!(f, "SUM_ADJUST_AMOUNTS:{}:{} != 0", asset, delta)
+            }
+            Self::UnbalancedEntry { asset, delta } => {
+                write!(f, "UNBALANCED_ENTRY:{}:{} != 0", asset, delta)
+            }
+            Self::InvalidEvent { reason } => write!(f, "INVALID_EVENT:{}", reason),
+            Self::DuplicateEvent { identity } => write!(f, "DUPLICATE_EVENT:{}", identity),
+            Self::BlockOrphaned { chain, block_hash } => {
+                write!(f, "BLOCK_ORPHANED:{}:{}", chain, block_hash)
+            }
+            Self::BlockFinalized { chain, block_hash } => {
+                write!(f, "BLOCK_FINALIZED:{}:{}", chain, block_hash)
+            }
+            Self::InvalidJson { reason } => write!(f, "JSON_DESERIALIZATION_FAILED:{}", reason),
+            Self::NotEnoughValidations { required, received } => {
+                write!(f, "NOT_ENOUGH_VALIDATIONS:{} < {}", received, required)
+            }
+            Self::InvalidMessageContent => write!(f, "INVALID_MESSAGE_CONTENT"),
+            Self::SigningFailed { reason } => write!(f, "ERROR_SIGNING_MESSAGE:{}", reason),
+            Self::UnknownKeyVersion { version } => write!(f, "UNKNOWN_KEY_VERSION:{}", version),
+            Self::KeyScopeRequired { version } => write!(f, "KEY_SCOPE_REQUIRED:{}", version),
+            Self::KeyRotationPending { version } => write!(f, "KEY_ROTATION_PENDING:{}", version),
+            Self::KeyVersionNotPending { version } => {
+                write!(f, "KEY_VERSION_NOT_PENDING:{}", version)
+            }
+            Self::PublicKeyNotCached { version } => {
+                write!(f, "PUBLIC_KEY_NOT_CACHED:{}", version)
+            }
+            Self::CallFailed { method, reason } => write!(f, "CALL_FAILED:{}:{}", method, reason),
+            Self::TokenOperationFailed { reason } => write!(f, "TOKEN_OPERATION_FAILED:{}", reason),
+        }
+    }
+}
+
+impl From<SignatureError> for CcampError {
+    fn from(error: SignatureError) -> Self {
+        Self::InvalidSignature {
+            reason: error.to_string(),
+        }
+    }
+}
+
+impl From<CcampError> for String {
+    fn from(error: CcampError) -> Self {
+        error.to_string()
+    }
+}
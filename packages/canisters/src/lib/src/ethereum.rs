@@ -1,10 +1,11 @@
-use crate::{
-    ecdsa::{self, derive_pk},
-    ethereum,
-    utils::{self, string_to_vec_u8}, remittance::Config,
-};
-use candid::Principal;
+use crate::{ecdsa, ethereum, signer::Signer, utils};
+use candid::CandidType;
 use easy_hasher::easy_hasher;
+use libsecp256k1::{Message, RecoveryId, Signature};
+use serde::Deserialize;
+use std::fmt::Display;
+
+pub mod eip712;
 
 pub fn hash_eth_message<T: AsRef<[u8]>>(message: T) -> Vec<u8> {
     const PREFIX: &str = "\x19Ethereum Signed Message:\n";
@@ -24,86 +25,164 @@ pub fn hash_eth_message<T: AsRef<[u8]>>(message: T) -> Vec<u8> {
         .unwrap()
 }
 
+#[derive(Clone, Debug, Deserialize, CandidType, PartialEq)]
+pub enum SignatureError {
+    InvalidHex,
+    InvalidLength { length: u64 },
+    InvalidRecoveryId { v: u64 },
+    InvalidSignature,
+    // the s value is in the upper half of the curve order which makes the signature malleable
+    HighS,
+    InvalidMessage,
+    InvalidPublicKey,
+    RecoveryFailed,
+    DiscriminatorNotFound,
+}
+impl Display for SignatureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidHex => write!(f, "INVALID_SIGNATURE_HEX"),
+            Self::InvalidLength { length } => write!(f, "INVALID_SIGNATURE_LENGTH:{}", length),
+            Self::InvalidRecoveryId { v } => write!(f, "INVALID_RECOVERY_ID:{}", v),
+            Self::InvalidSignature => write!(f, "INVALID_SIGNATURE"),
+            Self::HighS => write!(f, "SIGNATURE_S_TOO_HIGH"),
+            Self::InvalidMessage => write!(f, "INVALID_MESSAGE"),
+            Self::InvalidPublicKey => write!(f, "INVALID_PUBLIC_KEY"),
+            Self::RecoveryFailed => write!(f, "PUBLIC_KEY_RECOVERY_FAILED"),
+            Self::DiscriminatorNotFound => write!(f, "DISCRIMINATOR_NOT_FOUND"),
+        }
+    }
+}
 
-pub fn recover_address_from_eth_signature(
-    metamask_signature: String,
-    message: String,
-) -> Result<String, String> {
-    let metamask_signature = string_to_vec_u8(&metamask_signature);
-    if metamask_signature.len() != 65 {
-        return Err("INVALID_ETH_SIGNATURE".to_string());
+// parse a hex encoded signature produced by an ethereum wallet into its signature and recovery id
+// accepts `r || s || v` with v as 0/1, 27/28 or an EIP-155 value and EIP-2098 compact `r || vs` signatures
+pub fn parse_eth_signature(signature: &str) -> Result<(Signature, RecoveryId), SignatureError> {
+    let signature_bytes = hex::decode(signature.strip_prefix("0x").unwrap_or(signature))
+        .map_err(|_| SignatureError::InvalidHex)?;
+
+    let (rs, recovery_id): ([u8; 64], u8) = match signature_bytes.len() {
+        // the parity of the compact signature is stored in the top bit of s
+        64 => {
+            let mut rs: [u8; 64] = signature_bytes[..].try_into().unwrap();
+            let y_parity = rs[32] >> 7;
+            rs[32] &= 0x7f;
+
+            (rs, y_parity)
+        }
+        // v can take up to eight bytes when it includes an EIP-155 chain id
+        65..=72 => (
+            signature_bytes[..64].try_into().unwrap(),
+            normalize_v(&signature_bytes[64..])?,
+        ),
+        length => {
+            return Err(SignatureError::InvalidLength {
+                length: length as u64,
+            })
+        }
+    };
+
+    let signature = Signature::parse_standard(&rs).map_err(|_| SignatureError::InvalidSignature)?;
+    if signature.s.is_high() {
+        return Err(SignatureError::HighS);
     }
+    let recovery_id =
+        RecoveryId::parse(recovery_id).map_err(|_| SignatureError::InvalidRecoveryId {
+            v: recovery_id as u64,
+        })?;
 
-    let signature_bytes: [u8; 64] = metamask_signature[0..64].try_into().unwrap();
-    let signature_bytes_64 = libsecp256k1::Signature::parse_standard(&signature_bytes).unwrap();
+    Ok((signature, recovery_id))
+}
 
-    let recovery_id = metamask_signature[64];
-    let recovery_id_byte = libsecp256k1::RecoveryId::parse_rpc(recovery_id).unwrap();
+// convert the v value of a signature into a recovery id of 0 or 1
+fn normalize_v(v_bytes: &[u8]) -> Result<u8, SignatureError> {
+    let v = v_bytes.iter().fold(0u64, |v, byte| (v << 8) | *byte as u64);
 
-    let message_bytes: [u8; 32] = hash_eth_message(message)
-        .try_into()
-        .unwrap();
-    let message_bytes_32 = libsecp256k1::Message::parse(&message_bytes);
+    match v {
+        0 | 1 => Ok(v as u8),
+        27 | 28 => Ok((v - 27) as u8),
+        // EIP-155: v = chain_id * 2 + 35 + recovery_id
+        v if v >= 35 => Ok(((v - 35) % 2) as u8),
+        v => Err(SignatureError::InvalidRecoveryId { v }),
+    }
+}
+
+pub fn recover_address_from_eth_signature(
+    metamask_signature: String,
+    message: String,
+) -> Result<String, SignatureError> {
+    let (signature, recovery_id) = parse_eth_signature(&metamask_signature)?;
 
-    let public_key =
-        libsecp256k1::recover(&message_bytes_32, &signature_bytes_64, &recovery_id_byte).unwrap();
+    let message_bytes: [u8; 32] = hash_eth_message(message).try_into().unwrap();
+    let message = Message::parse(&message_bytes);
 
-    let address = get_address_from_public_key(public_key.serialize_compressed().to_vec()).unwrap();
+    let public_key = libsecp256k1::recover(&message, &signature, &recovery_id)
+        .map_err(|_| SignatureError::RecoveryFailed)?;
 
-    Ok(address)
+    get_address_from_public_key(public_key.serialize_compressed().to_vec())
+        .map_err(|_| SignatureError::InvalidPublicKey)
 }
 
 // append an extra discriminator byte to the ecdsa signature
-pub fn get_signature(signature: &Vec<u8>, message: &Vec<u8>, public_key: &Vec<u8>) -> Vec<u8> {
-    let r = utils::remove_leading(&signature[..32].to_vec(), 0);
-    let s = utils::remove_leading(&signature[32..].to_vec(), 0);
-    let recovery_id = get_recovery_id(message, signature, public_key).unwrap();
-
-    let v = if recovery_id == 0 {
-        hex::decode(format!("{:X}", 27)).unwrap()
-    } else {
-        hex::decode(format!("{:X}", 28)).unwrap()
-    };
+pub fn get_signature(
+    signature: &[u8],
+    message: &[u8],
+    public_key: &[u8],
+) -> Result<Vec<u8>, SignatureError> {
+    let signature_bytes: [u8; 64] =
+        signature[..]
+            .try_into()
+            .map_err(|_| SignatureError::InvalidLength {
+                length: signature.len() as u64,
+            })?;
+    let mut signature = Signature::parse_standard(&signature_bytes)
+        .map_err(|_| SignatureError::InvalidSignature)?;
+    // the evm rejects malleable signatures so the signature is normalized before deriving v
+    signature.normalize_s();
 
-    let eth_sig = [&r[..], &s[..], &v[..]].concat();
+    let signature = signature.serialize().to_vec();
+    let recovery_id = get_recovery_id(message, &signature, public_key)?;
 
-    eth_sig
+    Ok([&signature[..], &[27 + recovery_id]].concat())
 }
 
 // use this function to derive a discriminator "v"
 // the ecdsa signatures produces by the icp only consists of 64bytes
 // so we use this function to add the additional byte needed by the evm
 pub fn get_recovery_id(
-    message: &Vec<u8>,
-    signature: &Vec<u8>,
-    public_key: &Vec<u8>,
-) -> Result<u8, String> {
-    if signature.len() != 64 {
-        return Err("INVALID_SIGNATURE".to_string());
-    }
-    if message.len() != 32 {
-        return Err("INVALID_MESSAGE".to_string());
-    }
+    message: &[u8],
+    signature: &[u8],
+    public_key: &[u8],
+) -> Result<u8, SignatureError> {
+    let signature_bytes: [u8; 64] =
+        signature[..]
+            .try_into()
+            .map_err(|_| SignatureError::InvalidLength {
+                length: signature.len() as u64,
+            })?;
+    let message_bytes: [u8; 32] = message[..]
+        .try_into()
+        .map_err(|_| SignatureError::InvalidMessage)?;
     if public_key.len() != 33 {
-        return Err("INVALID_PUBLIC_KEY".to_string());
+        return Err(SignatureError::InvalidPublicKey);
     }
 
-    for i in 0..3 {
-        let recovery_id = libsecp256k1::RecoveryId::parse_rpc(27 + i).unwrap();
-
-        let signature_bytes: [u8; 64] = signature[..].try_into().unwrap();
-        let signature_bytes_64 = libsecp256k1::Signature::parse_standard(&signature_bytes).unwrap();
+    let signature = Signature::parse_standard(&signature_bytes)
+        .map_err(|_| SignatureError::InvalidSignature)?;
+    let message = Message::parse(&message_bytes);
 
-        let message_bytes: [u8; 32] = message[..].try_into().unwrap();
-        let message_bytes_32 = libsecp256k1::Message::parse(&message_bytes);
+    // only the recovery ids 0 and 1 can be expressed as a v value on the evm
+    for i in 0..2 {
+        let recovery_id =
+            RecoveryId::parse(i).map_err(|_| SignatureError::InvalidRecoveryId { v: i as u64 })?;
 
-        let key =
-            libsecp256k1::recover(&message_bytes_32, &signature_bytes_64, &recovery_id).unwrap();
-        if key.serialize_compressed() == public_key[..] {
-            return Ok(i as u8);
+        if let Ok(key) = libsecp256k1::recover(&message, &signature, &recovery_id) {
+            if key.serialize_compressed() == public_key[..] {
+                return Ok(i);
+            }
         }
     }
-    return Err("DISCRIMINATOR_NOT_FOUND".to_string());
+
+    Err(SignatureError::DiscriminatorNotFound)
 }
 
 // convert a compressed SEC1 address(it is 33bytes instead of 65bytes)
@@ -125,29 +204,169 @@ pub fn get_address_from_public_key(public_key: Vec<u8>) -> Result<String, String
     Ok(address)
 }
 
-pub async fn sign_message(message: &Vec<u8>, config: &Config) -> Result<ecdsa::SignatureReply, String> {
+pub async fn sign_message<S: Signer>(
+    message: &[u8],
+    signer: &S,
+) -> Result<ecdsa::SignatureReply, String> {
     // hash the message to be signed
-    let message_hash = ethereum::hash_eth_message(&message);
+    let message_hash = ethereum::hash_eth_message(message);
 
-    // sign the message
-    let public_key = derive_pk(config).await;
-    let request = ecdsa::SignWithECDSA {
-        message_hash: message_hash.clone(),
-        derivation_path: vec![],
-        key_id: config.key.to_key_id(),
-    };
+    sign_digest(message_hash, signer).await
+}
 
-    let (response,): (ecdsa::SignWithECDSAReply,) = ic_cdk::api::call::call_with_payment(
-        Principal::management_canister(),
-        "sign_with_ecdsa",
-        (request,),
-        config.sign_cycles,
-    )
-    .await
-    .map_err(|e| format!("SIGN_WITH_ECDSA_FAILED {}", e.1))?;
+// sign a 32 byte digest as is e.g an EIP-712 typed data hash
+pub async fn sign_digest<S: Signer>(
+    message_hash: Vec<u8>,
+    signer: &S,
+) -> Result<ecdsa::SignatureReply, String> {
+    // sign the message
+    let public_key = signer.public_key().await?;
+    let signature = signer.sign_digest(&message_hash).await?;
 
-    let full_signature = ethereum::get_signature(&response.signature, &message_hash, &public_key);
+    let full_signature = ethereum::get_signature(&signature, &message_hash, &public_key)
+        .map_err(|e| e.to_string())?;
     Ok(ecdsa::SignatureReply {
         signature_hex: utils::vec_u8_to_string(&full_signature),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signer::LocalSigner;
+    use futures::executor::block_on;
+
+    const MESSAGE: &str = "100000";
+
+    // a signature of the message as `r || s` with its recovery id and the address which made it
+    fn signed() -> ([u8; 64], u8, String) {
+        let signer = LocalSigner::from_seed(b"seed");
+        let address = get_address_from_public_key(block_on(signer.public_key()).unwrap()).unwrap();
+        let signature = block_on(sign_message(MESSAGE.as_bytes(), &signer)).unwrap();
+        let signature = hex::decode(signature.signature_hex).unwrap();
+
+        (
+            signature[..64].try_into().unwrap(),
+            signature[64] - 27,
+            address,
+        )
+    }
+
+    #[test]
+    fn every_encoding_of_v_is_accepted() {
+        let (rs, recovery_id, address) = signed();
+        // v as a recovery id, as 27/28 and with the EIP-155 chain ids of ethereum:1 and polygon:137
+        let encodings: Vec<(Vec<u8>, u8)> = vec![
+            (vec![0], 0),
+            (vec![1], 1),
+            (vec![27], 0),
+            (vec![28], 1),
+            (vec![37], 0),
+            (vec![38], 1),
+            (vec![0x01, 0x35], 0),
+            (vec![0x01, 0x36], 1),
+        ];
+
+        for (v, expected) in encodings {
+            let signature = hex::encode([&rs[..], &v[..]].concat());
+            let (_, parsed) = parse_eth_signature(&signature).unwrap();
+            assert_eq!(parsed.serialize(), expected, "v = {:?}", v);
+
+            // only the encodings of the right recovery id recover the signer
+            let recovered = recover_address_from_eth_signature(signature, MESSAGE.into());
+            assert_eq!(recovered == Ok(address.clone()), expected == recovery_id);
+        }
+    }
+
+    #[test]
+    fn compact_signatures_are_accepted() {
+        let (rs, recovery_id, address) = signed();
+        // EIP-2098 stores the parity in the top bit of s which is never set for a low s
+        let mut compact = rs;
+        compact[32] |= recovery_id << 7;
+        let signature = format!("0x{}", hex::encode(compact));
+
+        let (_, parsed) = parse_eth_signature(&signature).unwrap();
+        assert_eq!(parsed.serialize(), recovery_id);
+        assert_eq!(
+            recover_address_from_eth_signature(signature, MESSAGE.into()),
+            Ok(address)
+        );
+    }
+
+    #[test]
+    fn invalid_signatures_are_rejected_with_their_reason() {
+        let (rs, recovery_id, _) = signed();
+        // the same signature with s mirrored into the upper half of the curve order
+        let mut malleable = Signature::parse_standard(&rs).unwrap();
+        malleable.s = -malleable.s;
+        let malleable = [&malleable.serialize()[..], &[28 - recovery_id]].concat();
+
+        let cases: Vec<(String, SignatureError)> = vec![
+            (String::from("0x123"), SignatureError::InvalidHex),
+            ("zz".repeat(65), SignatureError::InvalidHex),
+            (
+                hex::encode(&rs[..63]),
+                SignatureError::InvalidLength { length: 63 },
+            ),
+            (
+                hex::encode([0u8; 73]),
+                SignatureError::InvalidLength { length: 73 },
+            ),
+            (
+                hex::encode([&rs[..], &[2]].concat()),
+                SignatureError::InvalidRecoveryId { v: 2 },
+            ),
+            (
+                hex::encode([&rs[..], &[29]].concat()),
+                SignatureError::InvalidRecoveryId { v: 29 },
+            ),
+            (
+                hex::encode(
+                    [0xffu8; 64]
+                        .iter()
+                        .chain(&[27])
+                        .copied()
+                        .collect::<Vec<u8>>(),
+                ),
+                SignatureError::InvalidSignature,
+            ),
+            (hex::encode(malleable), SignatureError::HighS),
+        ];
+
+        for (signature, error) in cases {
+            assert_eq!(
+                parse_eth_signature(&signature).map(|_| ()),
+                Err(error),
+                "{}",
+                signature
+            );
+        }
+    }
+
+    #[test]
+    fn recovery_id_is_derived_from_the_public_key() {
+        let (rs, recovery_id, _) = signed();
+        let digest = hash_eth_message(MESSAGE);
+        let public_key = block_on(LocalSigner::from_seed(b"seed").public_key()).unwrap();
+        let other_key = block_on(LocalSigner::from_seed(b"other").public_key()).unwrap();
+
+        assert_eq!(get_recovery_id(&digest, &rs, &public_key), Ok(recovery_id));
+        assert_eq!(
+            get_recovery_id(&digest, &rs, &other_key),
+            Err(SignatureError::DiscriminatorNotFound)
+        );
+        assert_eq!(
+            get_recovery_id(&digest, &rs[..63], &public_key),
+            Err(SignatureError::InvalidLength { length: 63 })
+        );
+        assert_eq!(
+            get_recovery_id(&digest[..31], &rs, &public_key),
+            Err(SignatureError::InvalidMessage)
+        );
+        assert_eq!(
+            get_recovery_id(&digest, &rs, &public_key[..32]),
+            Err(SignatureError::InvalidPublicKey)
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use candid::CandidType;
+use easy_hasher::easy_hasher;
+use serde::Deserialize;
+
+use crate::{Chain, Wallet};
+
+// the fields of the domain are fixed so every domain separator uses the same type
+pub const DOMAIN_TYPE: &str =
+    "EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)";
+
+// the domain a typed data signature is bound to
+// a signature produced for one domain can't be replayed against a contract in another domain
+#[derive(Clone, Debug, Deserialize, CandidType, PartialEq)]
+pub struct Domain {
+    pub name: String,
+    pub version: String,
+    pub chain_id: u64,
+    pub verifying_contract: Wallet,
+}
+impl Domain {
+    pub fn separator(&self) -> [u8; 32] {
+        hash_struct(
+            DOMAIN_TYPE,
+            &[
+                encode_string(&self.name),
+                encode_string(&self.version),
+                encode_u64(self.chain_id),
+                encode_address(&self.verifying_contract),
+            ],
+        )
+    }
+}
+
+pub fn keccak256<T: AsRef<[u8]>>(data: T) -> [u8; 32] {
+    easy_hasher::raw_keccak256(data.as_ref().to_vec())
+        .to_vec()
+        .try_into()
+        .unwrap()
+}
+
+// hashStruct(s) = keccak256(typeHash ‖ encodeData(s))
+pub fn hash_struct(encoded_type: &str, encoded_fields: &[[u8; 32]]) -> [u8; 32] {
+    let mut data = Vec::with_capacity(32 * (encoded_fields.len() + 1));
+    data.extend_from_slice(&keccak256(encoded_type));
+    for field in encoded_fields {
+        data.extend_from_slice(field);
+    }
+
+    keccak256(data)
+}
+
+// the digest which is signed: keccak256("\x19\x01" ‖ domainSeparator ‖ hashStruct(message))
+pub fn hash_typed_data(domain: &Domain, struct_hash: &[u8; 32]) -> [u8; 32] {
+    let mut data = Vec::with_capacity(2 + 32 + 32);
+    data.extend_from_slice(&[0x19, 0x01]);
+    data.extend_from_slice(&domain.separator());
+    data.extend_from_slice(struct_hash);
+
+    keccak256(data)
+}
+
+// dynamic values are encoded as the hash of their contents
+pub fn encode_string(value: &str) -> [u8; 32] {
+    keccak256(value)
+}
+
+pub fn encode_u64(value: u64) -> [u8; 32] {
+    let mut encoded = [0u8; 32];
+    encoded[24..].copy_from_slice(&value.to_be_bytes());
+
+    encoded
+}
+
+// addresses are left padded to 32 bytes
+pub fn encode_address(wallet: &Wallet) -> [u8; 32] {
+    let mut encoded = [0u8; 32];
+    encoded[32 - wallet.address.len()..].copy_from_slice(&wallet.address);
+
+    encoded
+}
+
+// the EIP-155 chain id of an evm chain is the reference of its CAIP-2 identifier
+pub fn chain_id(chain: &Chain) -> Result<u64, String> {
+    chain
+        .reference
+        .parse::<u64>()
+        .map_err(|_| format!("INVALID_EVM_CHAIN_ID:{}", chain))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wallet(address: &str) -> Wallet {
+        Wallet::try_from(address.to_string()).unwrap()
+    }
+
+    // the "Mail" example of the EIP-712 specification
+    #[test]
+    fn mail_example_of_the_specification() {
+        const MAIL_TYPE: &str =
+            "Mail(Person from,Person to,string contents)Person(string name,address wallet)";
+        const PERSON_TYPE: &str = "Person(string name,address wallet)";
+        let person = |name: &str, address: &str| {
+            hash_struct(
+                PERSON_TYPE,
+                &[encode_string(name), encode_address(&wallet(address))],
+            )
+        };
+        let domain = Domain {
+            name: String::from("Ether Mail"),
+            version: String::from("1"),
+            chain_id: 1,
+            verifying_contract: wallet("0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC"),
+        };
+
+        let mail = hash_struct(
+            MAIL_TYPE,
+            &[
+                person("Cow", "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826"),
+                person("Bob", "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB"),
+                encode_string("Hello, Bob!"),
+            ],
+        );
+
+        assert_eq!(
+            hex::encode(domain.separator()),
+            "f2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f"
+        );
+        assert_eq!(
+            hex::encode(mail),
+            "c52c0ee5d84264471806290a3f2c4cecfc5490626bf912d01f240d7a274b371e"
+        );
+        let digest = hash_typed_data(&domain, &mail);
+        assert_eq!(
+            hex::encode(digest),
+            "be609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2"
+        );
+
+        // the signature of the specification recovers to the address of cow
+        let signature = crate::ethereum::parse_eth_signature(
+            "4355c47d63924e8a72e509b65029052eb6c299d53a04e167c5775fd466751c9d\
+             07299936d304c153f6443dfa05f40ff007d72911b6f72307f996231605b91562\
+             1c",
+        )
+        .unwrap();
+        let public_key = libsecp256k1::recover(
+            &libsecp256k1::Message::parse(&digest),
+            &signature.0,
+            &signature.1,
+        )
+        .unwrap();
+        assert_eq!(
+            crate::ethereum::get_address_from_public_key(
+                public_key.serialize_compressed().to_vec()
+            ),
+            Ok(String::from("0xcd2a3d9f938e13cd947ec05abc7fe734df8dd826"))
+        );
+    }
+
+    #[test]
+    fn chain_id_is_the_reference_of_the_chain() {
+        let chain: Chain = String::from("polygon:137").try_into().unwrap();
+        assert_eq!(chain_id(&chain), Ok(137));
+
+        let chain: Chain = String::from("solana:mainnet").try_into().unwrap();
+        assert_eq!(
+            chain_id(&chain),
+            Err(String::from("INVALID_EVM_CHAIN_ID:solana:mainnet"))
+        );
+    }
+}
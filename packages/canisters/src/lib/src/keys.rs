@@ -0,0 +1,225 @@
+use candid::{CandidType, Principal};
+use serde::Deserialize;
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashSet},
+};
+
+use crate::{ecdsa, CcampError, Chain};
+
+// the version of the key canisters deployed before key rotation sign with
+// it is the root key of the canister and has the same derivation path for every scope
+pub const LEGACY_KEY_VERSION: u32 = 0;
+
+thread_local! {
+    pub static KEY_REGISTRY: RefCell<KeyRegistry> = RefCell::default();
+}
+
+#[derive(Clone, Debug, Deserialize, CandidType, PartialEq)]
+pub struct KeyVersion {
+    // whether a key is derived for every data collection canister of a chain
+    // or shared by all of them
+    pub dc_scoped: bool,
+    // timestamp in nanoseconds of the rotation which created this version
+    pub created_at: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, CandidType, PartialEq)]
+pub struct KeyRegistry {
+    // the version new signatures are made with
+    pub current_version: u32,
+    // a rotated version which only becomes current once it is activated
+    // so its addresses can be registered with the Locker contracts before they sign anything
+    pub pending_version: Option<u32>,
+    // the current version and the previous ones which still secure outstanding signatures
+    pub versions: BTreeMap<u32, KeyVersion>,
+}
+impl Default for KeyRegistry {
+    fn default() -> Self {
+        Self {
+            current_version: LEGACY_KEY_VERSION,
+            pending_version: None,
+            versions: BTreeMap::from([(
+                LEGACY_KEY_VERSION,
+                KeyVersion {
+                    dc_scoped: false,
+                    created_at: 0,
+                },
+            )]),
+        }
+    }
+}
+
+// identifies the threshold key used for a version and scope
+// the scope is only set to the parts the version derives its keys for
+#[derive(Clone, Debug, Deserialize, CandidType, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct KeyId {
+    pub version: u32,
+    pub chain: Option<Chain>,
+    pub dc_canister: Option<Principal>,
+}
+impl KeyId {
+    // the derivation path of the key i.e [version, chain, dc_canister?]
+    pub fn derivation_path(&self) -> Vec<Vec<u8>> {
+        let mut derivation_path = vec![];
+        if self.version == LEGACY_KEY_VERSION {
+            return derivation_path;
+        }
+
+        derivation_path.push(self.version.to_be_bytes().to_vec());
+        if let Some(chain) = &self.chain {
+            derivation_path.push(chain.to_string().into_bytes());
+        }
+        if let Some(dc_canister) = &self.dc_canister {
+            derivation_path.push(dc_canister.as_slice().to_vec());
+        }
+
+        derivation_path
+    }
+}
+
+pub fn current_version() -> u32 {
+    KEY_REGISTRY.with(|registry| registry.borrow().current_version)
+}
+
+pub fn get_registry() -> KeyRegistry {
+    KEY_REGISTRY.with(|registry| registry.borrow().clone())
+}
+
+// resolve the key a version uses for the provided scope
+pub fn key_id(
+    version: u32,
+    chain: Option<&Chain>,
+    dc_canister: Option<&Principal>,
+) -> Result<KeyId, CcampError> {
+    let key_version = KEY_REGISTRY
+        .with(|registry| registry.borrow().versions.get(&version).cloned())
+        .ok_or(CcampError::UnknownKeyVersion { version })?;
+
+    if version == LEGACY_KEY_VERSION {
+        return Ok(KeyId {
+            version,
+            chain: None,
+            dc_canister: None,
+        });
+    }
+
+    let chain = chain.ok_or(CcampError::KeyScopeRequired { version })?;
+    let dc_canister = match key_version.dc_scoped {
+        true => Some(*dc_canister.ok_or(CcampError::KeyScopeRequired { version })?),
+        false => None,
+    };
+
+    Ok(KeyId {
+        version,
+        chain: Some(chain.clone()),
+        dc_canister,
+    })
+}
+
+// create a new key version which is pending until it is activated
+// its public keys can be derived in the meantime but nothing is signed with it
+pub fn rotate(dc_scoped: bool, now: u64) -> Result<u32, CcampError> {
+    KEY_REGISTRY.with(|registry| {
+        let mut registry = registry.borrow_mut();
+        if let Some(version) = registry.pending_version {
+            return Err(CcampError::KeyRotationPending { version });
+        }
+
+        let version = registry.current_version + 1;
+        registry.versions.insert(
+            version,
+            KeyVersion {
+                dc_scoped,
+                created_at: now,
+            },
+        );
+        registry.pending_version = Some(version);
+
+        Ok(version)
+    })
+}
+
+// make the pending version the one every new signature is made with
+// the previous versions are kept until the signatures they secure are settled
+pub fn activate(version: u32) -> Result<(), CcampError> {
+    KEY_REGISTRY.with(|registry| {
+        let mut registry = registry.borrow_mut();
+        if registry.pending_version != Some(version) {
+            return Err(CcampError::KeyVersionNotPending { version });
+        }
+
+        registry.pending_version = None;
+        registry.current_version = version;
+
+        Ok(())
+    })
+}
+
+// drop the previous versions which no longer secure an outstanding signature
+// returns the versions which were retired
+pub fn retire_unused(versions_in_use: &HashSet<u32>) -> Vec<u32> {
+    KEY_REGISTRY.with(|registry| {
+        let mut registry = registry.borrow_mut();
+        let current_version = registry.current_version;
+        let pending_version = registry.pending_version;
+        let retired: Vec<u32> = registry
+            .versions
+            .keys()
+            .filter(|version| {
+                **version != current_version
+                    && Some(**version) != pending_version
+                    && !versions_in_use.contains(version)
+            })
+            .cloned()
+            .collect();
+
+        for version in &retired {
+            registry.versions.remove(version);
+        }
+        // the public keys of retired versions won't be used anymore
+        ecdsa::evict_cached_public_keys(|derivation_path| {
+            retired.contains(&derivation_path_version(derivation_path))
+        });
+
+        retired
+    })
+}
+
+// the key version a derivation path was built for
+fn derivation_path_version(derivation_path: &[Vec<u8>]) -> u32 {
+    derivation_path
+        .first()
+        .and_then(|segment| segment.as_slice().try_into().ok())
+        .map(u32::from_be_bytes)
+        .unwrap_or(LEGACY_KEY_VERSION)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotated_versions_sign_once_activated() {
+        let version = rotate(true, 0).unwrap();
+
+        // the pending version can't be rotated again, and isn't retired while pending
+        assert_eq!(current_version(), LEGACY_KEY_VERSION);
+        assert_eq!(
+            rotate(false, 0),
+            Err(CcampError::KeyRotationPending { version })
+        );
+        assert!(retire_unused(&HashSet::new()).is_empty());
+
+        assert_eq!(
+            activate(version + 1),
+            Err(CcampError::KeyVersionNotPending {
+                version: version + 1
+            })
+        );
+        activate(version).unwrap();
+        assert_eq!(current_version(), version);
+        assert_eq!(get_registry().pending_version, None);
+        assert_eq!(retire_unused(&HashSet::new()), vec![LEGACY_KEY_VERSION]);
+    }
+}
@@ -1,54 +1,98 @@
 #![allow(dead_code)]
 
-use candid::{CandidType, Principal};
+use candid::CandidType;
 use serde::Deserialize;
-use std::{collections::BTreeMap, fmt::Display};
+use std::fmt::Display;
 
+pub mod address;
+pub mod amount;
+pub mod audit;
+pub mod chain;
 pub mod constants;
+pub mod dedup;
 pub mod ethereum;
 pub mod ecdsa;
 pub mod dc;
+pub mod error;
+pub mod keys;
+pub mod outbox;
 pub mod owner;
+pub mod pause;
 pub mod utils;
 pub mod remittance;
+pub mod schnorr;
+pub mod signer;
+pub mod upgrade;
+pub mod validation;
+
+pub use address::Address;
+pub use chain::Chain;
+pub use error::CcampError;
+pub use validation::FieldError;
 
 #[derive(Clone, Debug, Deserialize, CandidType, PartialEq, Hash, Eq)]
 pub struct Wallet {
     pub address: Vec<u8>,
 }
+impl Wallet {
+    // the EIP-55 mixed-case checksum encoding of the address without the `0x` prefix
+    pub fn to_checksum_hex(&self) -> String {
+        let lowercase_address = hex::encode(&self.address);
+        let address_hash =
+            easy_hasher::easy_hasher::raw_keccak256(lowercase_address.as_bytes().to_vec()).to_vec();
+
+        lowercase_address
+            .chars()
+            .enumerate()
+            .map(|(i, c)| {
+                // each character is capitalised if the corresponding nibble of the hash is >= 8
+                let hash_byte = address_hash[i / 2];
+                let nibble = if i % 2 == 0 {
+                    hash_byte >> 4
+                } else {
+                    hash_byte & 0x0f
+                };
+
+                if nibble >= 8 {
+                    c.to_ascii_uppercase()
+                } else {
+                    c
+                }
+            })
+            .collect()
+    }
+}
 impl TryFrom<String> for Wallet {
     type Error = String;
     fn try_from(address: String) -> Result<Self, Self::Error> {
-        let starts_from: usize;
-        if address.starts_with("0x") {
-            starts_from = 2;
-        } else {
-            starts_from = 0;
+        let hex_address = address.strip_prefix("0x").unwrap_or(&address);
+
+        if hex_address.len() != 40 {
+            return Err(String::from("INVALID_ADDRESSS_LENGTH"));
+        }
+        if !hex_address.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(String::from("INVALID_ADDRESS_CHARACTERS"));
         }
 
-        let result = (starts_from..address.len())
-            .step_by(2)
-            .map(|i| u8::from_str_radix(&address[i..i + 2], 16).unwrap())
-            .collect::<Vec<u8>>();
+        let wallet = Self {
+            address: hex::decode(hex_address)
+                .map_err(|_| String::from("INVALID_ADDRESS_CHARACTERS"))?,
+        };
 
-        if result.len() != 20 {
-            Err(String::from("INVALID_ADDRESSS_LENGTH"))
-        } else {
-            Ok(Self { address: result })
+        // an all lowercase or all uppercase address carries no checksum
+        // but a mixed-case address has to be a valid EIP-55 checksum
+        let is_mixed_case = hex_address.chars().any(|c| c.is_ascii_lowercase())
+            && hex_address.chars().any(|c| c.is_ascii_uppercase());
+        if is_mixed_case && wallet.to_checksum_hex() != hex_address {
+            return Err(String::from("INVALID_ADDRESS_CHECKSUM"));
         }
+
+        Ok(wallet)
     }
 }
 impl Display for Wallet {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let string_address = self
-            .address
-            .iter()
-            .map(|r| format!("{:02x}", r))
-            .collect::<Vec<String>>()
-            .join("")
-            .to_string();
-
-        write!(f, "0x{}", string_address)
+        write!(f, "0x{}", self.to_checksum_hex())
     }
 }
 
@@ -74,75 +118,103 @@ impl TryFrom<String> for Action {
     }
 }
 
+// the position of an event on its chain which makes it unique
 #[derive(Clone, Debug, Deserialize, CandidType, PartialEq, Hash, Eq)]
-pub struct DataModel {
-    pub token: Wallet,
+pub struct EventIdentity {
     pub chain: Chain,
-    pub amount: i64,
-    pub account: Wallet,
-    pub action: Action,
+    pub transaction_hash: String,
+    pub log_index: u32,
+    pub block_hash: String,
 }
-impl Display for DataModel {
+impl EventIdentity {
+    // what two occurences of the same event have in common
+    // the block hash isn't part of it as a reorg can include the same transaction in another block
+    pub fn key(&self) -> (Chain, String, u32) {
+        (
+            self.chain.clone(),
+            self.transaction_hash.clone(),
+            self.log_index,
+        )
+    }
+}
+impl Display for EventIdentity {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "token:{}; chain:{}; amount:{};account:{};action:{:?}",
-            self.token, self.chain, self.amount, self.account, self.action
+            "{}:{}:{}",
+            self.chain, self.transaction_hash, self.log_index
         )
     }
 }
 
-#[derive(Clone, Debug, CandidType, Deserialize)]
-pub struct Subscriber {
-    pub topic: String,
+fn required<T>(value: Option<T>) -> Result<T, String> {
+    value.ok_or_else(|| String::from("MISSING_FIELD"))
+}
+
+// hex encoded hashes are compared without their case
+pub fn normalize_hash(hash: String) -> Result<String, String> {
+    let hash = hash.trim();
+    if hash.is_empty() {
+        return Err(String::from("EMPTY_HASH"));
+    }
+    if let Some(hex_hash) = hash.strip_prefix("0x") {
+        if !hex_hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(String::from("INVALID_HASH_CHARACTERS"));
+        }
+        return Ok(hash.to_lowercase());
+    }
+
+    Ok(hash.to_string())
 }
 
+// a token on a chain, amounts of different assets are never added together
 #[derive(Clone, Debug, Deserialize, CandidType, PartialEq, Hash, Eq)]
-pub enum Chain {
-    Ethereum1,
-    Ethereum5,
-    Polygon137,
-    Icp,
+pub struct Asset {
+    pub token: Address,
+    pub chain: Chain,
+}
+impl Display for Asset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.chain, self.token)
+    }
 }
-impl TryFrom<String> for Chain {
-    type Error = String;
 
-    fn try_from(value: String) -> Result<Self, Self::Error> {
-        let details: Vec<&str> = value.split(':').collect();
-
-        let chain_name = details[0];
-        let chain_id = details[1];
-
-        let lowercase_chain_name = &chain_name.to_lowercase()[..];
-        match (lowercase_chain_name, chain_id) {
-            ("ethereum", "1") => Ok(Chain::Ethereum1),
-            ("ethereum", "5") => Ok(Chain::Ethereum5),
-            ("ethereum", "137") => Ok(Chain::Polygon137),
-            ("icp", _) => Ok(Chain::Icp),
-            _ => Err(String::from("INVALID CHAIN")),
+#[derive(Clone, Debug, Deserialize, CandidType, PartialEq, Hash, Eq)]
+pub struct DataModel {
+    pub token: Address,
+    pub chain: Chain,
+    pub amount: candid::Int,
+    pub account: Address,
+    pub action: Action,
+    // set for the events which happened on chain, adjustments made by a data collection canister have none
+    pub identity: Option<EventIdentity>,
+    // the nonce of the withdrawal ticket a withdrawal or cancellation resolves
+    pub nonce: Option<u64>,
+}
+impl DataModel {
+    pub fn asset(&self) -> Asset {
+        Asset {
+            token: self.token.clone(),
+            chain: self.chain.clone(),
         }
     }
 }
-impl Display for Chain {
+impl Display for DataModel {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Ethereum1 => write!(f, "ethereum:1"),
-            Self::Ethereum5 => write!(f, "ethereum:5"),
-            Self::Polygon137 => write!(f, "polygon:137"),
-            Self::Icp => write!(f, "icp"),
-        }
+        write!(
+            f,
+            "token:{}; chain:{}; amount:{};account:{};action:{:?}",
+            self.token, self.chain, self.amount, self.account, self.action
+        )
     }
 }
-impl Chain {
-    fn get_chain_details(&self) -> (String, String) {
-        let chain_string = self.to_string();
-        let chain_details: Vec<&str> = chain_string.split(":").collect();
 
-        (
-            String::from(chain_details[0]),
-            String::from(*chain_details.get(1).unwrap_or(&"")),
-        )
-    }
+// the subscription a canister requests from a data collection canister
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Subscriber {
+    pub topic: String,
+    // every event of the topic is received when no filter is set
+    pub filter: Option<dc::TopicFilter>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -150,26 +222,177 @@ pub struct Event {
     pub event_name: String,
     pub canister_id: String,
     pub account: String,
-    pub amount: i64,
+    pub amount: candid::Int,
     pub chain: String,
     pub token: String,
+    // the position of the log the event was emitted by, required for every event but adjustments
+    pub transaction_hash: Option<String>,
+    pub block_hash: Option<String>,
+    pub log_index: Option<u32>,
+    // the nonce the withdrawal was signed with, required for withdrawals and cancellations
+    // a number or a decimal string as it doesn't always fit in the numbers of the publisher
+    pub nonce: Option<candid::Nat>,
 }
 
-impl Into<DataModel> for Event {
-    fn into(self) -> DataModel {
-        DataModel {
-            token: self.token.try_into().unwrap(),
-            chain: self.chain.try_into().unwrap(),
-            amount: self.amount as i64,
-            account: self.account.try_into().unwrap(),
-            action: self.event_name.try_into().unwrap(),
+// every field is parsed so that all the problems with an event are reported at once
+impl TryFrom<Event> for DataModel {
+    type Error = Vec<FieldError>;
+
+    fn try_from(event: Event) -> Result<Self, Self::Error> {
+        let mut errors: Vec<FieldError> = Vec::new();
+
+        let token = FieldError::collect(&mut errors, "token", event.token.try_into());
+        let chain: Option<Chain> =
+            FieldError::collect(&mut errors, "chain", event.chain.try_into());
+        let account = FieldError::collect(&mut errors, "account", event.account.try_into());
+        let action = FieldError::collect(&mut errors, "event_name", event.event_name.try_into());
+
+        // the position of the log has to be complete when any part of it is given
+        let identity = match (event.transaction_hash, event.block_hash, event.log_index) {
+            (None, None, None) => Some(None),
+            (transaction_hash, block_hash, log_index) => {
+                let transaction_hash = FieldError::collect(
+                    &mut errors,
+                    "transaction_hash",
+                    required(transaction_hash).and_then(normalize_hash),
+                );
+                let block_hash = FieldError::collect(
+                    &mut errors,
+                    "block_hash",
+                    required(block_hash).and_then(normalize_hash),
+                );
+                let log_index = FieldError::collect(&mut errors, "log_index", required(log_index));
+
+                match (transaction_hash, block_hash, log_index) {
+                    (Some(transaction_hash), Some(block_hash), Some(log_index)) => {
+                        Some(Some((transaction_hash, block_hash, log_index)))
+                    }
+                    _ => None,
+                }
+            }
+        };
+        // the events which happened on chain are only credited once so they have to carry their position
+        if identity == Some(None)
+            && action
+                .as_ref()
+                .is_some_and(|action| *action != Action::Adjust)
+        {
+            errors.push(FieldError::new(
+                "transaction_hash",
+                String::from("REQUIRED_FOR_ONCHAIN_EVENTS"),
+            ));
+        }
+        // a withdrawal or cancellation resolves the ticket of the remittance it was signed for
+        if event.nonce.is_none()
+            && action
+                .as_ref()
+                .is_some_and(|action| matches!(action, Action::Withdraw | Action::CancelWithdraw))
+        {
+            errors.push(FieldError::new(
+                "nonce",
+                String::from("REQUIRED_FOR_WITHDRAWALS"),
+            ));
+        }
+        let nonce = event.nonce.and_then(|nonce| {
+            FieldError::collect(
+                &mut errors,
+                "nonce",
+                amount::to_u64(&nonce).ok_or_else(|| String::from("NONCE_OUT_OF_RANGE")),
+            )
+        });
+
+        match (token, chain, account, action, identity) {
+            (Some(token), Some(chain), Some(account), Some(action), Some(identity))
+                if errors.is_empty() =>
+            {
+                Ok(DataModel {
+                    identity: identity.map(|(transaction_hash, block_hash, log_index)| {
+                        EventIdentity {
+                            chain: chain.clone(),
+                            transaction_hash,
+                            log_index,
+                            block_hash,
+                        }
+                    }),
+                    token,
+                    chain,
+                    amount: event.amount,
+                    account,
+                    action,
+                    nonce,
+                })
+            }
+            _ => Err(errors),
         }
     }
 }
-pub type SubscriberStore = BTreeMap<Principal, Subscriber>;
 
-#[derive(Clone, Debug, Deserialize, CandidType, PartialEq, Hash, Eq)]
-pub struct RemittanceSubscriber {
-    pub canister_principal: Principal,
-    pub subscribed: bool,
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wallet(address: &str) -> Result<Wallet, String> {
+        Wallet::try_from(String::from(address))
+    }
+
+    #[test]
+    fn checksummed_addresses_are_parsed() {
+        // the test vectors of EIP-55
+        let checksummed = [
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+            "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+            "0xD1220A0cf47c7B9Be7A2E6BA89F429762e7b9aDb",
+        ];
+        for address in checksummed {
+            let parsed = wallet(address).unwrap();
+            assert_eq!(parsed.to_string(), address);
+            assert_eq!(wallet(&parsed.to_string()), Ok(parsed));
+        }
+
+        // single case addresses carry no checksum but are displayed with one
+        let single_case = [
+            (
+                "0x52908400098527886E0F7030069857D2E4169EE7",
+                "0x52908400098527886E0F7030069857D2E4169EE7",
+            ),
+            (
+                "0xde709f2102306220921060314715629080e2fb77",
+                "0xde709f2102306220921060314715629080e2fb77",
+            ),
+            (
+                "5aaeb6053f3e94c9b9a09f33669435e7ef1beaed",
+                "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            ),
+            (
+                "0xFB6916095CA1DF60BB79CE92CE3EA74C37C5D359",
+                "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+            ),
+        ];
+        for (address, displayed) in single_case {
+            assert_eq!(wallet(address).unwrap().to_string(), displayed);
+        }
+    }
+
+    #[test]
+    fn invalid_addresses_are_rejected() {
+        // the case of the last character is flipped
+        assert_eq!(
+            wallet("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD"),
+            Err(String::from("INVALID_ADDRESS_CHECKSUM"))
+        );
+        assert_eq!(
+            wallet("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAe"),
+            Err(String::from("INVALID_ADDRESSS_LENGTH"))
+        );
+        assert_eq!(wallet("0x"), Err(String::from("INVALID_ADDRESSS_LENGTH")));
+        assert_eq!(
+            wallet("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeg"),
+            Err(String::from("INVALID_ADDRESS_CHARACTERS"))
+        );
+        assert_eq!(
+            wallet("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeA d"),
+            Err(String::from("INVALID_ADDRESS_CHARACTERS"))
+        );
+    }
+}
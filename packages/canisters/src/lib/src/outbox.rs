@@ -0,0 +1,526 @@
+use candid::{CandidType, Principal};
+use ic_cdk::api::{self, time};
+use serde::Deserialize;
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+};
+
+use crate::{constants::REMITTANCE_TOPIC, dc, CcampError, DataModel};
+
+// the delay in nanoseconds before a failed delivery is retried, doubled after every failure
+pub const RETRY_BASE_DELAY: u64 = 30_000_000_000;
+pub const RETRY_MAX_DELAY: u64 = 3_600_000_000_000;
+// the time in nanoseconds after which a subscriber is delivered to again although its last delivery didn't finish
+// e.g because the callback of the delivery trapped
+pub const IN_FLIGHT_TIMEOUT: u64 = 600_000_000_000;
+
+thread_local! {
+    pub static OUTBOX: RefCell<Outbox> = RefCell::default();
+    // the subscribers a delivery is awaited for along with when it started, so their batches aren't sent out of order
+    static IN_FLIGHT: RefCell<BTreeMap<Principal, u64>> = RefCell::default();
+}
+
+// a batch of events along with its position in the stream of batches sent to a subscriber
+#[derive(Clone, Debug, Deserialize, CandidType, PartialEq)]
+pub struct Delivery {
+    pub sequence: u64,
+    pub events: Vec<DataModel>,
+    pub dc_canister: Principal,
+}
+
+// returned by a subscriber once a batch was applied, or if it had been applied already
+#[derive(Clone, Debug, Deserialize, CandidType, PartialEq)]
+pub struct Acknowledgement {
+    // the last sequence number applied by the subscriber
+    pub sequence: u64,
+    // set when the batch can never be applied e.g because it contains a duplicate event
+    // the batch is acknowledged anyway so it doesn't hold back the next ones
+    pub rejection: Option<CcampError>,
+}
+
+#[derive(Clone, Debug, Deserialize, CandidType, PartialEq)]
+pub struct OutboxEntry {
+    pub subscriber: Principal,
+    pub delivery: Delivery,
+    pub attempts: u32,
+    // timestamp in nanoseconds after which the batch is sent again
+    pub next_attempt: u64,
+    pub last_error: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, CandidType, PartialEq)]
+pub struct Outbox {
+    // the last sequence number assigned to a batch of each subscriber, the first batch is 1
+    pub sequences: BTreeMap<Principal, u64>,
+    // the batches which weren't acknowledged yet keyed by subscriber and sequence number
+    pub pending: BTreeMap<(Principal, u64), OutboxEntry>,
+    // the batches rejected by their subscriber or skipped by an operator as they kept failing
+    // kept along with their events so they can be replayed
+    pub skipped: BTreeMap<(Principal, u64), OutboxEntry>,
+}
+
+// queue the events for every subscriber interested in them
+// they stay in the outbox until the subscriber acknowledges them
+pub fn enqueue(events: &[DataModel], dc_canister: Principal, now: u64) -> Result<(), CcampError> {
+    dc::ensure_subscribed(REMITTANCE_TOPIC)?;
+
+    OUTBOX.with(|outbox| {
+        let mut outbox = outbox.borrow_mut();
+        for (subscriber, matching_events) in dc::matching_subscribers(REMITTANCE_TOPIC, events) {
+            let sequence = outbox.sequences.entry(subscriber).or_default();
+            *sequence += 1;
+            let sequence = *sequence;
+
+            outbox.pending.insert(
+                (subscriber, sequence),
+                OutboxEntry {
+                    subscriber,
+                    delivery: Delivery {
+                        sequence,
+                        events: matching_events,
+                        dc_canister,
+                    },
+                    attempts: 0,
+                    next_attempt: now,
+                    last_error: None,
+                },
+            );
+        }
+    });
+
+    Ok(())
+}
+
+pub fn backoff(attempts: u32) -> u64 {
+    RETRY_BASE_DELAY
+        .saturating_mul(2u64.saturating_pow(attempts.saturating_sub(1)))
+        .min(RETRY_MAX_DELAY)
+}
+
+// the oldest batch of the subscriber if it is due, later batches wait for it to be acknowledged
+pub fn next_due(subscriber: Principal, now: u64) -> Option<Delivery> {
+    OUTBOX.with(|outbox| {
+        outbox
+            .borrow()
+            .pending
+            .range((subscriber, 0)..=(subscriber, u64::MAX))
+            .next()
+            .filter(|(_, entry)| entry.next_attempt <= now)
+            .map(|(_, entry)| entry.delivery.clone())
+    })
+}
+
+// remove every batch of the subscriber up to the acknowledged one
+pub fn acknowledge(subscriber: Principal, acknowledgement: &Acknowledgement) {
+    OUTBOX.with(|outbox| {
+        let mut outbox = outbox.borrow_mut();
+        let acknowledged: Vec<(Principal, u64)> = outbox
+            .pending
+            .range((subscriber, 0)..=(subscriber, acknowledgement.sequence))
+            .map(|(key, _)| *key)
+            .collect();
+        for key in acknowledged {
+            outbox.pending.remove(&key);
+        }
+    })
+}
+
+pub fn record_failure(subscriber: Principal, sequence: u64, error: &CcampError, now: u64) {
+    OUTBOX.with(|outbox| {
+        if let Some(entry) = outbox.borrow_mut().pending.get_mut(&(subscriber, sequence)) {
+            entry.attempts += 1;
+            entry.next_attempt = now.saturating_add(backoff(entry.attempts));
+            entry.last_error = Some(error.to_string());
+        }
+    })
+}
+
+// drop the events of the oldest batch of a subscriber e.g because applying it traps every time
+// the batch is delivered without them so the subscriber still moves on to the next sequence number
+pub fn skip(subscriber: Principal, sequence: u64, now: u64) -> Result<OutboxEntry, CcampError> {
+    OUTBOX.with(|outbox| {
+        let mut outbox = outbox.borrow_mut();
+        let oldest = outbox
+            .pending
+            .range_mut((subscriber, 0)..=(subscriber, u64::MAX))
+            .next()
+            .map(|(_, entry)| entry)
+            .filter(|entry| entry.delivery.sequence == sequence)
+            .ok_or(CcampError::DeliveryNotPending { sequence })?;
+
+        let skipped = oldest.clone();
+        oldest.delivery.events = vec![];
+        oldest.next_attempt = now;
+        outbox
+            .skipped
+            .insert((subscriber, sequence), skipped.clone());
+
+        Ok(skipped)
+    })
+}
+
+// whether a subscriber rejects a batch with this error no matter how many times it is delivered i.e its events are malformed
+// those batches are acknowledged along with the error, anything else e.g an unregistered chain is retried until it is fixed
+pub fn is_rejection(error: &CcampError) -> bool {
+    matches!(
+        error,
+        CcampError::DuplicateEvent { .. }
+            | CcampError::InvalidEvent { .. }
+            | CcampError::InvalidAddress { .. }
+            | CcampError::InvalidAmount { .. }
+    )
+}
+
+// keep a batch rejected by its subscriber along with the error so an operator can replay it
+pub fn set_aside(subscriber: Principal, sequence: u64, rejection: &CcampError) {
+    OUTBOX.with(|outbox| {
+        let mut outbox = outbox.borrow_mut();
+        if let Some(entry) = outbox.pending.get(&(subscriber, sequence)) {
+            let rejected = OutboxEntry {
+                last_error: Some(rejection.to_string()),
+                ..entry.clone()
+            };
+            outbox.skipped.insert((subscriber, sequence), rejected);
+        }
+    })
+}
+
+// queue the events of a rejected or skipped batch again as the next batch of its subscriber
+pub fn replay(subscriber: Principal, sequence: u64, now: u64) -> Result<u64, CcampError> {
+    OUTBOX.with(|outbox| {
+        let mut outbox = outbox.borrow_mut();
+        let skipped = outbox
+            .skipped
+            .remove(&(subscriber, sequence))
+            .ok_or(CcampError::DeliveryNotSkipped { sequence })?;
+        let next_sequence = outbox.sequences.entry(subscriber).or_default();
+        *next_sequence += 1;
+        let next_sequence = *next_sequence;
+
+        outbox.pending.insert(
+            (subscriber, next_sequence),
+            OutboxEntry {
+                subscriber,
+                delivery: Delivery {
+                    sequence: next_sequence,
+                    ..skipped.delivery
+                },
+                attempts: 0,
+                next_attempt: now,
+                last_error: None,
+            },
+        );
+
+        Ok(next_sequence)
+    })
+}
+
+// mark a delivery to the subscriber as started unless one is already awaited
+// one which started before the timeout is considered over as the call it awaited may have trapped
+pub fn start_delivery(subscriber: Principal, now: u64) -> bool {
+    IN_FLIGHT.with(|in_flight| {
+        let mut in_flight = in_flight.borrow_mut();
+        let is_idle = in_flight
+            .get(&subscriber)
+            .is_none_or(|started| now.saturating_sub(*started) >= IN_FLIGHT_TIMEOUT);
+        if is_idle {
+            in_flight.insert(subscriber, now);
+        }
+
+        is_idle
+    })
+}
+
+pub fn finish_delivery(subscriber: Principal) {
+    IN_FLIGHT.with(|in_flight| in_flight.borrow_mut().remove(&subscriber));
+}
+
+// make every pending batch due e.g once the reason they were rejected for was fixed
+pub fn reset_backoff(now: u64) {
+    OUTBOX.with(|outbox| {
+        for entry in outbox.borrow_mut().pending.values_mut() {
+            entry.next_attempt = now;
+        }
+    })
+}
+
+// send the due batches of a subscriber one after the other until one of them fails
+pub async fn deliver(subscriber: Principal) {
+    if !start_delivery(subscriber, time()) {
+        return;
+    }
+
+    while let Some(delivery) = next_due(subscriber, time()) {
+        let sequence = delivery.sequence;
+        let result = api::call::call(subscriber, "deliver_remittance", (delivery,))
+            .await
+            .map_err(|(code, message)| CcampError::CallFailed {
+                method: String::from("deliver_remittance"),
+                reason: format!("{:?}:{}", code, message),
+            })
+            .and_then(|(response,): (Result<Acknowledgement, CcampError>,)| response)
+            .and_then(
+                |acknowledgement| match acknowledgement.sequence >= sequence {
+                    true => Ok(acknowledgement),
+                    false => Err(CcampError::SequenceGap {
+                        expected: sequence,
+                        received: acknowledgement.sequence,
+                    }),
+                },
+            );
+
+        let outcome = match &result {
+            Ok(acknowledgement) => acknowledgement.rejection.clone().map_or(Ok(()), Err),
+            Err(error) => Err(error.clone()),
+        };
+        dc::record_delivery(&subscriber, &outcome);
+        match result {
+            Ok(acknowledgement) => {
+                if let Some(rejection) = &acknowledgement.rejection {
+                    set_aside(subscriber, sequence, rejection);
+                }
+                acknowledge(subscriber, &acknowledgement);
+            }
+            Err(error) => {
+                record_failure(subscriber, sequence, &error, time());
+                break;
+            }
+        }
+    }
+
+    finish_delivery(subscriber);
+}
+
+// deliver the due batches of every subscriber, called by the timers of the publishing canister
+pub async fn flush() {
+    let subscribers: BTreeSet<Principal> = OUTBOX.with(|outbox| {
+        outbox
+            .borrow()
+            .pending
+            .keys()
+            .map(|(subscriber, _)| *subscriber)
+            .collect()
+    });
+
+    for subscriber in subscribers {
+        deliver(subscriber).await;
+    }
+}
+
+// whether a subscriber which applied every batch up to `last_applied` has to apply the delivered one
+// batches which were applied already are only acknowledged again
+pub fn should_apply(last_applied: u64, sequence: u64) -> Result<bool, CcampError> {
+    if sequence <= last_applied {
+        return Ok(false);
+    }
+    if sequence != last_applied + 1 {
+        return Err(CcampError::SequenceGap {
+            expected: last_applied + 1,
+            received: sequence,
+        });
+    }
+
+    Ok(true)
+}
+
+pub fn get_entries() -> Vec<OutboxEntry> {
+    OUTBOX.with(|outbox| outbox.borrow().pending.values().cloned().collect())
+}
+
+pub fn get_skipped() -> Vec<OutboxEntry> {
+    OUTBOX.with(|outbox| outbox.borrow().skipped.values().cloned().collect())
+}
+
+pub fn get_state() -> Outbox {
+    OUTBOX.with(|outbox| outbox.borrow().clone())
+}
+
+pub fn restore_state(state: Outbox) {
+    OUTBOX.with(|outbox| *outbox.borrow_mut() = state);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Action;
+
+    fn deposit() -> DataModel {
+        DataModel {
+            token: String::from("0xB24a30A3971e4d9bf771BDc81435c25EA69A445c")
+                .try_into()
+                .unwrap(),
+            chain: String::from("ethereum:5").try_into().unwrap(),
+            amount: candid::Int::from(100),
+            account: String::from("0x9C81E8F60a9B8743678F1b6Ae893Cc72c6Bc6840")
+                .try_into()
+                .unwrap(),
+            action: Action::Deposit,
+            identity: None,
+            nonce: None,
+        }
+    }
+
+    #[test]
+    fn batches_are_retried_in_order_until_acknowledged() {
+        let subscriber = Principal::from_slice(&[1]);
+        let dc_canister = Principal::from_slice(&[2]);
+        dc::add_subscriber(subscriber);
+        dc::SUBSCRIBERS.with(|subscribers| {
+            subscribers.borrow_mut().get_mut(&subscriber).unwrap().topic =
+                Some(REMITTANCE_TOPIC.to_string());
+        });
+
+        enqueue(&[deposit()], dc_canister, 0).unwrap();
+        enqueue(&[deposit()], dc_canister, 0).unwrap();
+        assert_eq!(
+            next_due(subscriber, 0).map(|delivery| delivery.sequence),
+            Some(1)
+        );
+
+        // a failed batch holds back the batches queued after it until its backoff elapsed
+        let error = CcampError::OperationPaused {
+            operation: crate::pause::Operation::UpdateRemittance,
+        };
+        record_failure(subscriber, 1, &error, 0);
+        assert_eq!(next_due(subscriber, RETRY_BASE_DELAY - 1), None);
+        assert_eq!(
+            next_due(subscriber, RETRY_BASE_DELAY).map(|delivery| delivery.sequence),
+            Some(1)
+        );
+        record_failure(subscriber, 1, &error, 0);
+        assert_eq!(next_due(subscriber, 2 * RETRY_BASE_DELAY - 1), None);
+
+        acknowledge(
+            subscriber,
+            &Acknowledgement {
+                sequence: 1,
+                rejection: None,
+            },
+        );
+        assert_eq!(
+            next_due(subscriber, 0).map(|delivery| delivery.sequence),
+            Some(2)
+        );
+        acknowledge(
+            subscriber,
+            &Acknowledgement {
+                sequence: 2,
+                rejection: None,
+            },
+        );
+        assert_eq!(get_entries(), vec![]);
+    }
+
+    #[test]
+    fn skipped_batches_are_delivered_without_their_events() {
+        let subscriber = Principal::from_slice(&[3]);
+        dc::add_subscriber(subscriber);
+        dc::SUBSCRIBERS.with(|subscribers| {
+            subscribers.borrow_mut().get_mut(&subscriber).unwrap().topic =
+                Some(REMITTANCE_TOPIC.to_string());
+        });
+        let dc_canister = Principal::from_slice(&[2]);
+        enqueue(&[deposit()], dc_canister, 0).unwrap();
+        enqueue(&[deposit()], dc_canister, 0).unwrap();
+        let trapped = CcampError::CallFailed {
+            method: String::from("deliver_remittance"),
+            reason: String::from("CanisterError:trapped"),
+        };
+        record_failure(subscriber, 1, &trapped, 0);
+
+        // only the batch holding back the others can be skipped
+        assert_eq!(
+            skip(subscriber, 2, 0),
+            Err(CcampError::DeliveryNotPending { sequence: 2 })
+        );
+        let skipped = skip(subscriber, 1, 0).unwrap();
+        assert_eq!(skipped.delivery.events, vec![deposit()]);
+        assert_eq!(get_skipped(), vec![skipped]);
+        assert_eq!(
+            next_due(subscriber, 0),
+            Some(Delivery {
+                sequence: 1,
+                events: vec![],
+                dc_canister,
+            })
+        );
+        assert!(!is_rejection(&trapped));
+        // an unregistered chain is retried until an admin registers it
+        assert!(!is_rejection(&CcampError::ChainNotRegistered {
+            chain: String::from("ethereum:5")
+        }));
+    }
+
+    #[test]
+    fn rejected_batches_are_kept_until_they_are_replayed() {
+        let subscriber = Principal::from_slice(&[4]);
+        dc::add_subscriber(subscriber);
+        dc::SUBSCRIBERS.with(|subscribers| {
+            subscribers.borrow_mut().get_mut(&subscriber).unwrap().topic =
+                Some(REMITTANCE_TOPIC.to_string());
+        });
+        let dc_canister = Principal::from_slice(&[2]);
+        enqueue(&[deposit()], dc_canister, 0).unwrap();
+
+        let rejection = CcampError::InvalidEvent {
+            reason: String::from("INVALID_BLOCK_HASH"),
+        };
+        set_aside(subscriber, 1, &rejection);
+        acknowledge(
+            subscriber,
+            &Acknowledgement {
+                sequence: 1,
+                rejection: Some(rejection.clone()),
+            },
+        );
+        assert_eq!(next_due(subscriber, 0), None);
+        let rejected = get_skipped()
+            .into_iter()
+            .find(|entry| entry.subscriber == subscriber)
+            .unwrap();
+        assert_eq!(rejected.delivery.events, vec![deposit()]);
+        assert_eq!(rejected.last_error, Some(rejection.to_string()));
+
+        // the events are delivered again as the next batch
+        assert_eq!(replay(subscriber, 1, 5), Ok(2));
+        assert_eq!(
+            next_due(subscriber, 5),
+            Some(Delivery {
+                sequence: 2,
+                events: vec![deposit()],
+                dc_canister,
+            })
+        );
+        assert_eq!(
+            replay(subscriber, 1, 5),
+            Err(CcampError::DeliveryNotSkipped { sequence: 1 })
+        );
+    }
+
+    #[test]
+    fn deliveries_which_never_finished_stop_holding_back_their_subscriber() {
+        let subscriber = Principal::from_slice(&[5]);
+        assert!(start_delivery(subscriber, 0));
+        assert!(!start_delivery(subscriber, IN_FLIGHT_TIMEOUT - 1));
+        // the callback of the delivery trapped so it was never finished
+        assert!(start_delivery(subscriber, IN_FLIGHT_TIMEOUT));
+
+        finish_delivery(subscriber);
+        assert!(start_delivery(subscriber, IN_FLIGHT_TIMEOUT + 1));
+    }
+
+    #[test]
+    fn deliveries_are_applied_once_and_in_order() {
+        assert_eq!(should_apply(0, 1), Ok(true));
+        assert_eq!(should_apply(3, 3), Ok(false));
+        assert_eq!(
+            should_apply(3, 5),
+            Err(CcampError::SequenceGap {
+                expected: 4,
+                received: 5
+            })
+        );
+        assert_eq!(backoff(100), RETRY_MAX_DELAY);
+    }
+}
@@ -1,26 +1,224 @@
-use std::cell::RefCell;
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+};
 
-use candid::Principal;
+use candid::{CandidType, Principal};
 use ic_cdk::caller;
+use serde::Deserialize;
+
+use crate::{audit, CcampError};
 
 thread_local! {
-    static OWNER: RefCell<Option<Principal>> = RefCell::default();
+    pub static ACCESS_CONTROL: RefCell<AccessControl> = RefCell::default();
+}
+
+// the privileges which can be granted to principals besides the owner
+// the owner implicitly holds every role and admins hold every role but admin
+#[derive(Clone, Copy, Debug, Deserialize, CandidType, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Role {
+    // configures the canister and grants or revokes the other roles
+    Admin,
+    // performs the day to day operations such as manual publishing
+    Operator,
+    // manages the publishers a canister accepts events from
+    PublisherManager,
+    // stops and resumes the processing of a canister
+    Pauser,
+    // reads the records of the canister which aren't public
+    Auditor,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, CandidType, PartialEq)]
+pub struct AccessControl {
+    pub owner: Option<Principal>,
+    // the principal the owner proposed to transfer the ownership to until it accepts it
+    pub pending_owner: Option<Principal>,
+    pub roles: BTreeMap<Principal, BTreeSet<Role>>,
+}
+impl AccessControl {
+    pub fn is_owner(&self, principal: &Principal) -> bool {
+        self.owner == Some(*principal)
+    }
+
+    pub fn has_role(&self, principal: &Principal, role: Role) -> bool {
+        if self.is_owner(principal) {
+            return true;
+        }
+
+        self.roles.get(principal).is_some_and(|roles| {
+            roles.contains(&role) || (role != Role::Admin && roles.contains(&Role::Admin))
+        })
+    }
 }
 
 pub fn only_owner() {
+    if let Err(error) = ensure_owner() {
+        panic!("{}", error);
+    }
+}
+
+// the non panicking version of `only_owner` for endpoints which return a result
+pub fn ensure_owner() -> Result<(), CcampError> {
+    let caller_principal_id = caller();
+    if !ACCESS_CONTROL.with(|access_control| access_control.borrow().is_owner(&caller_principal_id))
+    {
+        return Err(CcampError::NotAllowed {
+            caller: caller_principal_id,
+        });
+    }
+
+    Ok(())
+}
+
+pub fn has_role(principal: &Principal, role: Role) -> bool {
+    ACCESS_CONTROL.with(|access_control| access_control.borrow().has_role(principal, role))
+}
+
+// the owner implicitly holds every role
+pub fn ensure_role(role: Role) -> Result<(), CcampError> {
     let caller_principal_id = caller();
-    if !OWNER.with(|owner| owner.borrow().expect("NO_OWNER") == caller_principal_id) {
-        panic!("NOT_ALLOWED");
+    if !has_role(&caller_principal_id, role) {
+        return Err(CcampError::MissingRole {
+            caller: caller_principal_id,
+            role,
+        });
     }
+
+    Ok(())
 }
 
+// only called when a canister is installed, upgrades restore the access control instead
 pub fn init_owner() {
     let caller_principal_id = caller();
-    OWNER.with(|token| {
-        token.replace(Some(caller_principal_id));
+    ACCESS_CONTROL.with(|access_control| {
+        access_control.borrow_mut().owner = Some(caller_principal_id);
     });
 }
 
 pub fn get_owner() -> String {
-    OWNER.with(|owner| owner.borrow().clone().expect("NO_OWNER").to_string())
-}
\ No newline at end of file
+    ACCESS_CONTROL
+        .with(|access_control| access_control.borrow().owner.expect("NO_OWNER").to_string())
+}
+
+pub fn get_access_control() -> AccessControl {
+    ACCESS_CONTROL.with(|access_control| access_control.borrow().clone())
+}
+
+// upgrades restore the owner and roles so whoever upgrades the canister doesn't become its owner
+pub fn restore_access_control(access_control: AccessControl) {
+    ACCESS_CONTROL.with(|store| *store.borrow_mut() = access_control);
+}
+
+pub fn get_roles(principal: Principal) -> Vec<Role> {
+    ACCESS_CONTROL.with(|access_control| {
+        access_control
+            .borrow()
+            .roles
+            .get(&principal)
+            .map(|roles| roles.iter().cloned().collect())
+            .unwrap_or_default()
+    })
+}
+
+// admins can only be granted or revoked by the owner, the other roles by admins as well
+fn ensure_can_manage(role: Role) -> Result<(), CcampError> {
+    match role {
+        Role::Admin => ensure_owner(),
+        _ => ensure_role(Role::Admin),
+    }
+}
+
+pub fn grant_role(role: Role, principal: Principal) -> Result<(), CcampError> {
+    let result = ensure_can_manage(role).map(|_| {
+        ACCESS_CONTROL.with(|access_control| {
+            access_control
+                .borrow_mut()
+                .roles
+                .entry(principal)
+                .or_default()
+                .insert(role);
+        })
+    });
+    audit::record("grant_role", &(role, principal), &result);
+
+    result
+}
+
+pub fn revoke_role(role: Role, principal: Principal) -> Result<(), CcampError> {
+    let result = ensure_can_manage(role).map(|_| {
+        ACCESS_CONTROL.with(|access_control| {
+            let roles = &mut access_control.borrow_mut().roles;
+            if let Some(principal_roles) = roles.get_mut(&principal) {
+                principal_roles.remove(&role);
+                if principal_roles.is_empty() {
+                    roles.remove(&principal);
+                }
+            }
+        })
+    });
+    audit::record("revoke_role", &(role, principal), &result);
+
+    result
+}
+
+// the first step of an ownership transfer, the owner stays in charge until the new one accepts
+// proposing `None` cancels a pending transfer
+pub fn propose_owner(new_owner: Option<Principal>) -> Result<(), CcampError> {
+    let result = ensure_owner().map(|_| {
+        ACCESS_CONTROL.with(|access_control| access_control.borrow_mut().pending_owner = new_owner)
+    });
+    audit::record("propose_owner", &(new_owner,), &result);
+
+    result
+}
+
+// the second step of an ownership transfer which has to be called by the proposed owner
+pub fn accept_ownership() -> Result<(), CcampError> {
+    let caller_principal_id = caller();
+    let result = ACCESS_CONTROL.with(|access_control| {
+        let mut access_control = access_control.borrow_mut();
+        if access_control.pending_owner != Some(caller_principal_id) {
+            return Err(CcampError::NotPendingOwner {
+                caller: caller_principal_id,
+            });
+        }
+
+        access_control.owner = Some(caller_principal_id);
+        access_control.pending_owner = None;
+
+        Ok(())
+    });
+    audit::record("accept_ownership", &(), &result);
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn admins_hold_every_role_but_admin() {
+        let owner = Principal::from_slice(&[1]);
+        let admin = Principal::from_slice(&[2]);
+        let pauser = Principal::from_slice(&[3]);
+        let access_control = AccessControl {
+            owner: Some(owner),
+            pending_owner: None,
+            roles: BTreeMap::from([
+                (admin, BTreeSet::from([Role::Admin])),
+                (pauser, BTreeSet::from([Role::Pauser])),
+            ]),
+        };
+
+        assert!(access_control.has_role(&owner, Role::Admin));
+        assert!(access_control.has_role(&admin, Role::Operator));
+        assert!(access_control.has_role(&pauser, Role::Pauser));
+        assert!(!access_control.has_role(&pauser, Role::Operator));
+        assert!(!access_control.has_role(&Principal::anonymous(), Role::Auditor));
+
+        // a canister without an owner denies every role instead of trapping
+        assert!(!AccessControl::default().has_role(&owner, Role::Pauser));
+    }
+}
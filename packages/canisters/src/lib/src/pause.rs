@@ -0,0 +1,143 @@
+use candid::{CandidType, Principal};
+use ic_cdk::{api::time, caller};
+use serde::Deserialize;
+use std::{cell::RefCell, collections::BTreeSet};
+
+use crate::{audit, owner::Role, CcampError};
+
+thread_local! {
+    pub static PAUSE_STATE: RefCell<PauseState> = RefCell::default();
+}
+
+// the update calls which can be stopped independently of each other
+// queries are never paused so balances and receipts can still be read
+#[derive(Clone, Copy, Debug, Deserialize, CandidType, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Operation {
+    // the signing of withdrawals by the remittance canister
+    Remit,
+    // the events the remittance canister accepts from data collection canisters
+    UpdateRemittance,
+    // the events the protocol data collection canister accepts from publishers
+    ProcessEvent,
+    // the minting and burning of tokens by the bridge
+    Bridge,
+}
+
+#[derive(Clone, Debug, Deserialize, CandidType, PartialEq)]
+pub struct PauseRecord {
+    pub operation: Operation,
+    // whether the operation was paused or unpaused
+    pub paused: bool,
+    pub reason: String,
+    pub caller: Principal,
+    // timestamp in nanoseconds
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, CandidType, PartialEq)]
+pub struct PauseState {
+    pub paused: BTreeSet<Operation>,
+    // every pause and unpause in the order they happened
+    pub history: Vec<PauseRecord>,
+}
+
+pub fn is_paused(operation: Operation) -> bool {
+    PAUSE_STATE.with(|state| state.borrow().paused.contains(&operation))
+}
+
+pub fn ensure_not_paused(operation: Operation) -> Result<(), CcampError> {
+    if is_paused(operation) {
+        return Err(CcampError::OperationPaused { operation });
+    }
+
+    Ok(())
+}
+
+// pause or unpause an operation and record who did it and why
+// the caller is expected to hold the pauser role
+pub fn set_paused(
+    operation: Operation,
+    paused: bool,
+    reason: String,
+    caller: Principal,
+    timestamp: u64,
+) {
+    PAUSE_STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        match paused {
+            true => state.paused.insert(operation),
+            false => state.paused.remove(&operation),
+        };
+        state.history.push(PauseRecord {
+            operation,
+            paused,
+            reason,
+            caller,
+            timestamp,
+        });
+    })
+}
+
+// stop an operation until it is unpaused, only callable by pausers
+pub fn pause(operation: Operation, reason: String) -> Result<(), CcampError> {
+    let result = crate::owner::ensure_role(Role::Pauser);
+    audit::record("pause", &(operation, &reason), &result);
+    result?;
+    set_paused(operation, true, reason, caller(), time());
+
+    Ok(())
+}
+
+pub fn unpause(operation: Operation, reason: String) -> Result<(), CcampError> {
+    let result = crate::owner::ensure_role(Role::Pauser);
+    audit::record("unpause", &(operation, &reason), &result);
+    result?;
+    set_paused(operation, false, reason, caller(), time());
+
+    Ok(())
+}
+
+pub fn get_paused() -> Vec<Operation> {
+    PAUSE_STATE.with(|state| state.borrow().paused.iter().cloned().collect())
+}
+
+pub fn get_history() -> Vec<PauseRecord> {
+    PAUSE_STATE.with(|state| state.borrow().history.clone())
+}
+
+pub fn get_state() -> PauseState {
+    PAUSE_STATE.with(|state| state.borrow().clone())
+}
+
+pub fn restore_state(pause_state: PauseState) {
+    PAUSE_STATE.with(|state| *state.borrow_mut() = pause_state);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paused_operations_are_rejected_until_unpaused() {
+        let pauser = Principal::from_slice(&[1]);
+
+        set_paused(Operation::Remit, true, String::from("incident"), pauser, 1);
+        assert_eq!(
+            ensure_not_paused(Operation::Remit),
+            Err(CcampError::OperationPaused {
+                operation: Operation::Remit
+            })
+        );
+        assert_eq!(ensure_not_paused(Operation::UpdateRemittance), Ok(()));
+
+        set_paused(Operation::Remit, false, String::from("resolved"), pauser, 2);
+        assert_eq!(ensure_not_paused(Operation::Remit), Ok(()));
+        assert_eq!(
+            get_history()
+                .iter()
+                .map(|record| (record.paused, record.reason.as_str()))
+                .collect::<Vec<_>>(),
+            vec![(true, "incident"), (false, "resolved")]
+        );
+    }
+}
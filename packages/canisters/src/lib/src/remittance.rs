@@ -11,6 +11,16 @@ pub enum Environment {
     Production,
 }
 
+// how the withdrawal authorizations issued by the remittance canister are signed
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq, Default)]
+pub enum SigningMode {
+    // personal_sign over keccak256(abi.encodePacked(nonce, amount, account, chain, canister, token))
+    #[default]
+    PersonalSign,
+    // EIP-712 typed data bound to the locker contract registered for the chain
+    Eip712,
+}
+
 #[derive(CandidType, Deserialize, Debug, Clone)]
 pub struct Config {
     pub env: Environment,
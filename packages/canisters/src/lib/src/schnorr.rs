@@ -0,0 +1,120 @@
+use crate::remittance::Config;
+use ed25519_compact::{PublicKey, Signature};
+use ic_cdk::export::{
+    candid::CandidType,
+    serde::{Deserialize, Serialize},
+    Principal,
+};
+
+#[derive(CandidType, Serialize, Debug)]
+pub struct Ed25519PublicKeyReply {
+    pub public_key: String,
+    // the base58 encoded public key i.e the solana address
+    pub address: String,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum SchnorrAlgorithm {
+    #[serde(rename = "bip340secp256k1")]
+    Bip340Secp256k1,
+    #[serde(rename = "ed25519")]
+    Ed25519,
+}
+
+#[derive(CandidType, Serialize, Debug, Clone)]
+pub struct SchnorrKeyId {
+    pub algorithm: SchnorrAlgorithm,
+    pub name: String,
+}
+
+#[derive(CandidType, Serialize, Debug)]
+pub struct SchnorrPublicKey {
+    pub canister_id: Option<Principal>,
+    pub derivation_path: Vec<Vec<u8>>,
+    pub key_id: SchnorrKeyId,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub struct SchnorrPublicKeyReply {
+    pub public_key: Vec<u8>,
+    pub chain_code: Vec<u8>,
+}
+
+#[derive(CandidType, Serialize, Debug)]
+pub struct SignWithSchnorr {
+    pub message: Vec<u8>,
+    pub derivation_path: Vec<Vec<u8>>,
+    pub key_id: SchnorrKeyId,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub struct SignWithSchnorrReply {
+    pub signature: Vec<u8>,
+}
+
+// the schnorr keys of a subnet have the same names as its ecdsa keys
+pub fn to_key_id(config: &Config, algorithm: SchnorrAlgorithm) -> SchnorrKeyId {
+    SchnorrKeyId {
+        algorithm,
+        name: config.key.to_key_id().name,
+    }
+}
+
+pub async fn derive_pk(
+    config: &Config,
+    algorithm: SchnorrAlgorithm,
+    derivation_path: Vec<Vec<u8>>,
+) -> Result<Vec<u8>, String> {
+    let request = SchnorrPublicKey {
+        canister_id: None,
+        derivation_path,
+        key_id: to_key_id(config, algorithm),
+    };
+    let (res,): (SchnorrPublicKeyReply,) = ic_cdk::call(
+        Principal::management_canister(),
+        "schnorr_public_key",
+        (request,),
+    )
+    .await
+    .map_err(|e| format!("SCHNORR_PUBLIC_KEY_FAILED {}", e.1))?;
+
+    Ok(res.public_key)
+}
+
+// sign the message itself, schnorr signatures don't need it to be hashed beforehand
+pub async fn sign(
+    config: &Config,
+    algorithm: SchnorrAlgorithm,
+    derivation_path: Vec<Vec<u8>>,
+    message: Vec<u8>,
+) -> Result<Vec<u8>, String> {
+    let request = SignWithSchnorr {
+        message,
+        derivation_path,
+        key_id: to_key_id(config, algorithm),
+    };
+    let (response,): (SignWithSchnorrReply,) = ic_cdk::api::call::call_with_payment(
+        Principal::management_canister(),
+        "sign_with_schnorr",
+        (request,),
+        config.sign_cycles,
+    )
+    .await
+    .map_err(|e| format!("SIGN_WITH_SCHNORR_FAILED {}", e.1))?;
+
+    Ok(response.signature)
+}
+
+// verify a hex encoded ed25519 signature of a message by a 32 byte public key
+pub fn verify_ed25519(public_key: &[u8], message: &[u8], signature: &str) -> Result<(), String> {
+    let public_key =
+        PublicKey::from_slice(public_key).map_err(|_| String::from("INVALID_PUBLIC_KEY"))?;
+    let signature = hex::decode(signature.strip_prefix("0x").unwrap_or(signature))
+        .map_err(|_| String::from("INVALID_SIGNATURE_HEX"))?;
+    let signature =
+        Signature::from_slice(&signature).map_err(|_| String::from("INVALID_SIGNATURE_LENGTH"))?;
+
+    public_key
+        .verify(message, &signature)
+        .map_err(|_| String::from("ED25519_VERIFICATION_FAILED"))
+}
@@ -0,0 +1,308 @@
+use candid::Principal;
+use ed25519_compact::{KeyPair, Seed};
+use k256::ecdsa::{signature::hazmat::PrehashSigner, SigningKey};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    ecdsa,
+    remittance::Config,
+    schnorr::{self, SchnorrAlgorithm},
+};
+
+// produces the secp256k1 signatures and public key the protocol authorizes withdrawals with
+// canisters are single threaded so the returned futures don't need to be `Send`
+#[allow(async_fn_in_trait)]
+pub trait Signer {
+    // the signer of the key derived from this one with the derivation path
+    fn derive(&self, derivation_path: Vec<Vec<u8>>) -> Self
+    where
+        Self: Sized;
+
+    // the SEC1 compressed public key (33 bytes) the signatures can be verified with
+    async fn public_key(&self) -> Result<Vec<u8>, String>;
+
+    // sign a 32 byte digest as is and return the `r || s` signature (64 bytes)
+    async fn sign_digest(&self, message_hash: &[u8]) -> Result<Vec<u8>, String>;
+}
+
+// signs with the threshold ECDSA key of the subnet through the management canister
+#[derive(Clone, Debug)]
+pub struct ThresholdEcdsaSigner {
+    pub config: Config,
+    pub derivation_path: Vec<Vec<u8>>,
+}
+impl ThresholdEcdsaSigner {
+    // the signer of the root key of the canister
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            derivation_path: vec![],
+        }
+    }
+}
+impl Signer for ThresholdEcdsaSigner {
+    fn derive(&self, derivation_path: Vec<Vec<u8>>) -> Self {
+        Self {
+            config: self.config.clone(),
+            derivation_path,
+        }
+    }
+
+    async fn public_key(&self) -> Result<Vec<u8>, String> {
+        // the key only has to be derived through the management canister the first time
+        let cached = ecdsa::cached_public_key(&self.config, self.derivation_path.clone()).await?;
+
+        Ok(cached.public_key)
+    }
+
+    async fn sign_digest(&self, message_hash: &[u8]) -> Result<Vec<u8>, String> {
+        let request = ecdsa::SignWithECDSA {
+            message_hash: message_hash.to_vec(),
+            derivation_path: self.derivation_path.clone(),
+            key_id: self.config.key.to_key_id(),
+        };
+
+        let (response,): (ecdsa::SignWithECDSAReply,) = ic_cdk::api::call::call_with_payment(
+            Principal::management_canister(),
+            "sign_with_ecdsa",
+            (request,),
+            self.config.sign_cycles,
+        )
+        .await
+        .map_err(|e| format!("SIGN_WITH_ECDSA_FAILED {}", e.1))?;
+
+        Ok(response.signature)
+    }
+}
+
+// signs in process with a key derived from a seed
+// signatures are deterministic (RFC 6979) which makes it suitable for native tests
+#[derive(Clone, Debug)]
+pub struct LocalSigner {
+    signing_key: SigningKey,
+}
+impl LocalSigner {
+    // the private key is the sha256 hash of the seed
+    pub fn from_seed(seed: &[u8]) -> Self {
+        let secret = Sha256::digest(seed);
+
+        Self {
+            signing_key: SigningKey::from_bytes(&secret).expect("INVALID_SEED"),
+        }
+    }
+}
+impl Signer for LocalSigner {
+    // the derived private key is the sha256 hash of the parent key and every segment of the path
+    // the empty path derives the key itself like it does for threshold keys
+    fn derive(&self, derivation_path: Vec<Vec<u8>>) -> Self {
+        if derivation_path.is_empty() {
+            return self.clone();
+        }
+
+        let mut hasher = Sha256::new();
+        hasher.update(self.signing_key.to_bytes());
+        for segment in derivation_path {
+            hasher.update((segment.len() as u64).to_be_bytes());
+            hasher.update(segment);
+        }
+
+        Self {
+            signing_key: SigningKey::from_bytes(&hasher.finalize())
+                .expect("INVALID_DERIVATION_PATH"),
+        }
+    }
+
+    async fn public_key(&self) -> Result<Vec<u8>, String> {
+        Ok(self
+            .signing_key
+            .verifying_key()
+            .to_encoded_point(true)
+            .as_bytes()
+            .to_vec())
+    }
+
+    async fn sign_digest(&self, message_hash: &[u8]) -> Result<Vec<u8>, String> {
+        let signature: k256::ecdsa::Signature = self
+            .signing_key
+            .sign_prehash(message_hash)
+            .map_err(|e| format!("SIGN_DIGEST_FAILED {}", e))?;
+
+        Ok(signature.to_bytes().to_vec())
+    }
+}
+
+// produces the ed25519 signatures withdrawals on chains such as solana are authorized with
+#[allow(async_fn_in_trait)]
+pub trait Ed25519Signer {
+    // the signer of the key derived from this one with the derivation path
+    fn derive(&self, derivation_path: Vec<Vec<u8>>) -> Self
+    where
+        Self: Sized;
+
+    // the public key (32 bytes) the signatures can be verified with
+    async fn public_key(&self) -> Result<Vec<u8>, String>;
+
+    // sign the message and return the signature (64 bytes)
+    async fn sign(&self, message: &[u8]) -> Result<Vec<u8>, String>;
+}
+
+// signs with the threshold ed25519 key of the subnet through the management canister
+#[derive(Clone, Debug)]
+pub struct ThresholdEd25519Signer {
+    pub config: Config,
+    pub derivation_path: Vec<Vec<u8>>,
+}
+impl ThresholdEd25519Signer {
+    // the signer of the root key of the canister
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            derivation_path: vec![],
+        }
+    }
+}
+impl Ed25519Signer for ThresholdEd25519Signer {
+    fn derive(&self, derivation_path: Vec<Vec<u8>>) -> Self {
+        Self {
+            config: self.config.clone(),
+            derivation_path,
+        }
+    }
+
+    async fn public_key(&self) -> Result<Vec<u8>, String> {
+        schnorr::derive_pk(
+            &self.config,
+            SchnorrAlgorithm::Ed25519,
+            self.derivation_path.clone(),
+        )
+        .await
+    }
+
+    async fn sign(&self, message: &[u8]) -> Result<Vec<u8>, String> {
+        schnorr::sign(
+            &self.config,
+            SchnorrAlgorithm::Ed25519,
+            self.derivation_path.clone(),
+            message.to_vec(),
+        )
+        .await
+    }
+}
+
+// signs in process with an ed25519 key derived from a seed, ed25519 signatures are deterministic
+#[derive(Clone, Debug)]
+pub struct LocalEd25519Signer {
+    seed: [u8; 32],
+}
+impl LocalEd25519Signer {
+    // the private key seed is the sha256 hash of the seed
+    pub fn from_seed(seed: &[u8]) -> Self {
+        Self {
+            seed: Sha256::digest(seed).into(),
+        }
+    }
+
+    fn key_pair(&self) -> KeyPair {
+        KeyPair::from_seed(Seed::new(self.seed))
+    }
+}
+impl Ed25519Signer for LocalEd25519Signer {
+    fn derive(&self, derivation_path: Vec<Vec<u8>>) -> Self {
+        if derivation_path.is_empty() {
+            return self.clone();
+        }
+
+        let mut hasher = Sha256::new();
+        hasher.update(self.seed);
+        for segment in derivation_path {
+            hasher.update((segment.len() as u64).to_be_bytes());
+            hasher.update(segment);
+        }
+
+        Self {
+            seed: hasher.finalize().into(),
+        }
+    }
+
+    async fn public_key(&self) -> Result<Vec<u8>, String> {
+        Ok(self.key_pair().pk.to_vec())
+    }
+
+    async fn sign(&self, message: &[u8]) -> Result<Vec<u8>, String> {
+        Ok(self.key_pair().sk.sign(message, None).to_vec())
+    }
+}
+
+// the signers of every signature scheme withdrawals can be authorized with
+#[derive(Clone, Debug)]
+pub struct Signers<S: Signer, E: Ed25519Signer> {
+    pub ecdsa: S,
+    pub ed25519: E,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ethereum, keys, Chain};
+    use futures::executor::block_on;
+    use std::collections::HashSet;
+
+    #[test]
+    fn local_signer_is_deterministic() {
+        let digest = [7u8; 32];
+        let signer = LocalSigner::from_seed(b"seed");
+
+        assert_eq!(
+            block_on(signer.sign_digest(&digest)),
+            block_on(LocalSigner::from_seed(b"seed").sign_digest(&digest))
+        );
+        assert_eq!(block_on(signer.public_key()).unwrap().len(), 33);
+    }
+
+    #[test]
+    fn local_signer_signs_recoverable_eth_messages() {
+        let signer = LocalSigner::from_seed(b"seed");
+        let address =
+            ethereum::get_address_from_public_key(block_on(signer.public_key()).unwrap()).unwrap();
+
+        let signature = block_on(ethereum::sign_message(b"100000", &signer)).unwrap();
+
+        assert_eq!(
+            ethereum::recover_address_from_eth_signature(
+                signature.signature_hex,
+                String::from("100000")
+            ),
+            Ok(address)
+        );
+    }
+
+    #[test]
+    fn threshold_signer_uses_cached_public_keys_until_retired() {
+        let config = Config::default();
+        let public_key = block_on(LocalSigner::from_seed(b"seed").public_key()).unwrap();
+        let chain: Chain = String::from("ethereum:5").try_into().unwrap();
+        let version = keys::rotate(false, 0).unwrap();
+        keys::activate(version).unwrap();
+        let key_id = keys::key_id(version, Some(&chain), None).unwrap();
+        ecdsa::PUBLIC_KEY_CACHE.with(|cache| {
+            cache.borrow_mut().insert(
+                (config.key.to_key_id().name, key_id.derivation_path()),
+                ecdsa::CachedPublicKey {
+                    address: ethereum::get_address_from_public_key(public_key.clone()).unwrap(),
+                    public_key: public_key.clone(),
+                },
+            )
+        });
+
+        // no call to the management canister is made while the key is cached
+        let signer = ThresholdEcdsaSigner::new(config.clone()).derive(key_id.derivation_path());
+        assert_eq!(block_on(signer.public_key()), Ok(public_key));
+
+        keys::activate(keys::rotate(false, 0).unwrap()).unwrap();
+        assert_eq!(keys::retire_unused(&HashSet::new()), vec![0, version]);
+        assert_eq!(
+            ecdsa::get_cached_public_key(&config, &key_id.derivation_path()),
+            None
+        );
+    }
+}
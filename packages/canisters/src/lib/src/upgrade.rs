@@ -0,0 +1,192 @@
+// the stores of a canister are saved across upgrades along with the version of their layout
+// so the next version of the canister knows which migrations they need before they are restored
+use candid::{
+    utils::{ArgumentDecoder, ArgumentEncoder},
+    CandidType, Principal,
+};
+use ic_cdk::api::stable;
+use serde::Deserialize;
+use std::io::Write;
+
+use crate::{
+    chain::{default_chains, ChainMetadata},
+    constants::REMITTANCE_TOPIC,
+    dc::{DeliveryStatus, SubscriberStore, Subscription, TopicFilter},
+    owner::AccessControl,
+    CcampError, Chain,
+};
+
+// the stores encoded after the version of their layout
+pub fn encode<T: ArgumentEncoder>(version: u32, stores: T) -> Result<Vec<u8>, String> {
+    let mut ser = candid::ser::IDLBuilder::new();
+    ser.arg(&version).map_err(|e| format!("{:?}", e))?;
+    stores.encode(&mut ser).map_err(|e| format!("{:?}", e))?;
+
+    ser.serialize_to_vec().map_err(|e| format!("{:?}", e))
+}
+
+pub fn save<T: ArgumentEncoder>(version: u32, stores: T) -> Result<(), String> {
+    let bytes = encode(version, stores)?;
+
+    stable::StableWriter::default()
+        .write_all(&bytes)
+        .map_err(|e| format!("{:?}", e))
+}
+
+pub fn saved_bytes() -> Vec<u8> {
+    stable::stable_bytes()
+}
+
+// decode the leading values of the saved bytes, the bytes after them are ignored like `stable_restore` does
+pub fn decode<T>(bytes: &[u8]) -> Result<T, String>
+where
+    T: for<'de> ArgumentDecoder<'de>,
+{
+    let mut de = candid::de::IDLDeserialize::new(bytes).map_err(|e| format!("{:?}", e))?;
+    ArgumentDecoder::decode(&mut de).map_err(|e| format!("{:?}", e))
+}
+
+// the version of the layout of the saved stores, none for the stores saved before versions
+pub fn version(bytes: &[u8]) -> Option<u32> {
+    decode::<(u32,)>(bytes).ok().map(|(version,)| version)
+}
+
+// the stores following the version, which has to be the one expected
+pub fn decode_versioned<T>(bytes: &[u8], expected: u32) -> Result<T, String>
+where
+    T: for<'de> ArgumentDecoder<'de>,
+{
+    let mut de = candid::de::IDLDeserialize::new(bytes).map_err(|e| format!("{:?}", e))?;
+    let version: u32 = de.get_value().map_err(|e| format!("{:?}", e))?;
+    if version != expected {
+        return Err(CcampError::UnsupportedStateVersion { version }.to_string());
+    }
+
+    ArgumentDecoder::decode(&mut de).map_err(|e| format!("{:?}", e))
+}
+
+// the chains supported by the first version of the canisters
+#[derive(Clone, Debug, Deserialize, CandidType, PartialEq, Hash, Eq)]
+pub enum LegacyChain {
+    Ethereum1,
+    Ethereum5,
+    Polygon137,
+    Icp,
+}
+impl From<LegacyChain> for Chain {
+    // the identifiers the legacy chains were displayed with, which the withdrawals were signed with
+    fn from(chain: LegacyChain) -> Self {
+        let (namespace, reference) = match chain {
+            LegacyChain::Ethereum1 => ("ethereum", "1"),
+            LegacyChain::Ethereum5 => ("ethereum", "5"),
+            LegacyChain::Polygon137 => ("polygon", "137"),
+            LegacyChain::Icp => ("icp", "mainnet"),
+        };
+
+        Chain {
+            namespace: namespace.to_string(),
+            reference: reference.to_string(),
+        }
+    }
+}
+
+// the chains the first version of the canisters kept balances on, registered when their stores are migrated
+// the Internet Computer has no locker and its blocks are final once they are made
+pub fn legacy_chains() -> Vec<(Chain, ChainMetadata)> {
+    let mut chains = default_chains();
+    chains.push((
+        LegacyChain::Icp.into(),
+        ChainMetadata {
+            name: String::from("Internet Computer"),
+            finality_depth: 0,
+            locker_address: None,
+        },
+    ));
+
+    chains
+}
+
+// the single subscriber of the remittance topic kept by the first version of the canisters
+#[derive(Clone, Debug, Deserialize, CandidType, PartialEq, Hash, Eq)]
+pub struct RemittanceSubscriber {
+    pub canister_principal: Principal,
+    pub subscribed: bool,
+}
+
+// the subscriber whitelisted by the first version, subscribed to the remittance topic if it had subscribed
+pub fn subscriber_store(subscriber: Option<RemittanceSubscriber>) -> SubscriberStore {
+    subscriber
+        .into_iter()
+        .map(|subscriber| {
+            (
+                subscriber.canister_principal,
+                Subscription {
+                    canister_principal: subscriber.canister_principal,
+                    topic: subscriber.subscribed.then(|| REMITTANCE_TOPIC.to_string()),
+                    filter: TopicFilter::default(),
+                    delivery: DeliveryStatus::default(),
+                },
+            )
+        })
+        .collect()
+}
+
+// the first version didn't save its owner, the canister is owned by the caller of the upgrade as it was then
+pub fn legacy_access_control(owner: Principal) -> AccessControl {
+    AccessControl {
+        owner: Some(owner),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn saved_stores_are_decoded_along_with_their_version() {
+        let stores = (String::from("stores"), 7u64);
+        let versioned = encode(3, stores.clone()).unwrap();
+        let mut padded = versioned.clone();
+        padded.extend([0u8; 32]);
+
+        assert_eq!(version(&padded), Some(3));
+        assert_eq!(decode_versioned::<(String, u64)>(&padded, 3), Ok(stores));
+        assert_eq!(
+            decode_versioned::<(String, u64)>(&versioned, 4),
+            Err(String::from("UNSUPPORTED_STATE_VERSION:3"))
+        );
+
+        // the stores saved by the first version of the canisters have no version
+        let subscriber = RemittanceSubscriber {
+            canister_principal: Principal::from_slice(&[1]),
+            subscribed: true,
+        };
+        let legacy = candid::encode_args((
+            Some(subscriber.clone()),
+            HashMap::from([(Principal::from_slice(&[2]), true)]),
+        ))
+        .unwrap();
+        assert_eq!(version(&legacy), None);
+        let (subscriber, _): (Option<RemittanceSubscriber>, HashMap<Principal, bool>) =
+            decode(&legacy).unwrap();
+        let store = subscriber_store(subscriber);
+        assert_eq!(
+            store[&Principal::from_slice(&[1])].topic.as_deref(),
+            Some(REMITTANCE_TOPIC)
+        );
+    }
+
+    #[test]
+    fn legacy_chains_keep_the_identifier_they_were_signed_with() {
+        let chain: Chain = LegacyChain::Polygon137.into();
+        assert_eq!(chain.to_string(), "polygon:137");
+        let chain: Chain = LegacyChain::Ethereum5.into();
+        assert_eq!(chain, Chain::try_from(String::from("ethereum:5")).unwrap());
+
+        // every chain of the first version is registered when its stores are migrated
+        let chain: Chain = LegacyChain::Icp.into();
+        assert!(legacy_chains().iter().any(|(legacy, _)| *legacy == chain));
+    }
+}
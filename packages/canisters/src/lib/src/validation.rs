@@ -0,0 +1,290 @@
+use candid::{CandidType, Principal};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{CcampError, DataModel, Event};
+
+// the reason a single field of an event failed validation
+#[derive(Clone, Debug, Deserialize, CandidType, PartialEq)]
+pub struct FieldError {
+    pub field: String,
+    pub reason: String,
+}
+impl FieldError {
+    pub fn new(field: &str, reason: String) -> Self {
+        Self {
+            field: field.to_string(),
+            reason,
+        }
+    }
+
+    // record the error of a field if any and return the parsed value otherwise
+    pub fn collect<T>(
+        errors: &mut Vec<FieldError>,
+        field: &str,
+        result: Result<T, String>,
+    ) -> Option<T> {
+        result
+            .map_err(|reason| errors.push(Self::new(field, reason)))
+            .ok()
+    }
+}
+
+// what to do with a batch of events when some of them fail validation
+#[derive(Clone, Debug, Deserialize, CandidType, PartialEq, Default)]
+pub enum BatchMode {
+    // apply nothing if a single event is invalid
+    #[default]
+    RejectAll,
+    // apply the valid events and skip the invalid ones
+    ApplyValid,
+}
+impl BatchMode {
+    // decide whether the accepted events of a batch should be applied
+    pub fn should_apply(&self, results: &[EventValidation]) -> bool {
+        let accepted = results
+            .iter()
+            .filter(|result| result.status == EventStatus::Accepted)
+            .count();
+
+        match self {
+            Self::RejectAll => accepted > 0 && accepted == results.len(),
+            Self::ApplyValid => accepted > 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, CandidType, PartialEq)]
+pub enum EventStatus {
+    Accepted,
+    Rejected { errors: Vec<FieldError> },
+}
+
+#[derive(Clone, Debug, Deserialize, CandidType, PartialEq)]
+pub struct EventValidation {
+    // the position of the event in the array which was published
+    pub index: u64,
+    pub status: EventStatus,
+}
+
+#[derive(Clone, Debug, Deserialize, CandidType, PartialEq)]
+pub struct ValidationReport {
+    pub events: Vec<EventValidation>,
+    // whether the accepted events were forwarded to the remittance canister
+    pub applied: bool,
+}
+
+// an event which passed validation
+pub struct ValidatedEvent {
+    pub dc_canister: Principal,
+    pub data_model: DataModel,
+}
+
+// parse a json array of events and validate each one of them
+// returns the valid events along with the validation result of every event in the array
+pub fn validate_json_events(
+    json_data: &str,
+) -> Result<(Vec<ValidatedEvent>, Vec<EventValidation>), CcampError> {
+    let json_events: Value =
+        serde_json::from_str(json_data).map_err(|error| CcampError::InvalidJson {
+            reason: error.to_string(),
+        })?;
+    let json_events = match json_events {
+        Value::Array(events) => events,
+        _ => {
+            return Err(CcampError::InvalidJson {
+                reason: String::from("EXPECTED_ARRAY_OF_EVENTS"),
+            })
+        }
+    };
+
+    let mut validated_events: Vec<ValidatedEvent> = Vec::new();
+    let mut results: Vec<EventValidation> = Vec::new();
+    for (index, json_event) in json_events.into_iter().enumerate() {
+        let status = match validate_json_event(json_event) {
+            Ok((dc_canister, data_model)) => {
+                validated_events.push(ValidatedEvent {
+                    dc_canister,
+                    data_model,
+                });
+                EventStatus::Accepted
+            }
+            Err(errors) => EventStatus::Rejected { errors },
+        };
+
+        results.push(EventValidation {
+            index: index as u64,
+            status,
+        });
+    }
+
+    Ok((validated_events, results))
+}
+
+fn validate_json_event(json_event: Value) -> Result<(Principal, DataModel), Vec<FieldError>> {
+    // parse the json object into an "'Event' struct"
+    let event: Event = serde_json::from_value(json_event)
+        .map_err(|error| vec![FieldError::new("event", error.to_string())])?;
+
+    let mut errors: Vec<FieldError> = Vec::new();
+    let dc_canister = FieldError::collect(
+        &mut errors,
+        "canister_id",
+        Principal::from_text(&event.canister_id).map_err(|error| error.to_string()),
+    );
+    // convert the event into a data model while collecting the errors of every field
+    let data_model = DataModel::try_from(event)
+        .map_err(|field_errors| errors.extend(field_errors))
+        .ok();
+
+    match (dc_canister, data_model) {
+        (Some(dc_canister), Some(data_model)) => Ok((dc_canister, data_model)),
+        _ => Err(errors),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn deposit() -> Value {
+        json!({
+            "event_name": "FundsDeposited",
+            "canister_id": "bkyz2-fmaaa-aaaaa-qaaaq-cai",
+            "account": "0x9C81E8F60a9B8743678F1b6Ae893Cc72c6Bc6840",
+            "amount": 100,
+            "chain": "ethereum:5",
+            "token": "0xB24a30A3971e4d9bf771BDc81435c25EA69A445c",
+            "transaction_hash": "0x1111111111111111111111111111111111111111111111111111111111111111",
+            "block_hash": "0xfffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff1",
+            "log_index": 0
+        })
+    }
+
+    fn validate(events: Vec<Value>) -> (Vec<ValidatedEvent>, Vec<EventValidation>) {
+        validate_json_events(&Value::Array(events).to_string()).unwrap()
+    }
+
+    #[test]
+    fn every_invalid_field_is_reported_at_its_index() {
+        let mut invalid = deposit();
+        invalid["canister_id"] = json!("not-a-principal");
+        invalid["account"] = json!("0x9C81");
+        invalid["chain"] = json!("ethereum");
+        let mut incomplete = deposit();
+        incomplete.as_object_mut().unwrap().remove("block_hash");
+        let mut adjustment = deposit();
+        adjustment["event_name"] = json!("BalanceAdjusted");
+        for field in ["transaction_hash", "block_hash", "log_index"] {
+            adjustment.as_object_mut().unwrap().remove(field);
+        }
+
+        let (validated, results) = validate(vec![
+            deposit(),
+            invalid,
+            adjustment,
+            incomplete,
+            json!({ "event_name": "FundsDeposited" }),
+        ]);
+
+        assert_eq!(validated.len(), 2);
+        assert_eq!(validated[1].data_model.identity, None);
+        let rejected: Vec<(u64, Vec<String>)> = results
+            .iter()
+            .filter_map(|result| match &result.status {
+                EventStatus::Accepted => None,
+                EventStatus::Rejected { errors } => Some((
+                    result.index,
+                    errors.iter().map(|error| error.field.clone()).collect(),
+                )),
+            })
+            .collect();
+        assert_eq!(
+            rejected,
+            vec![
+                (
+                    1,
+                    vec![
+                        String::from("canister_id"),
+                        String::from("chain"),
+                        String::from("account")
+                    ]
+                ),
+                (3, vec![String::from("block_hash")]),
+                (4, vec![String::from("event")]),
+            ]
+        );
+        assert_eq!(
+            results[3].status,
+            EventStatus::Rejected {
+                errors: vec![FieldError::new("block_hash", String::from("MISSING_FIELD"))]
+            }
+        );
+    }
+
+    #[test]
+    fn withdrawals_carry_the_nonce_of_their_ticket() {
+        let mut withdrawal = deposit();
+        withdrawal["event_name"] = json!("FundsWithdrawn");
+        let without_nonce = withdrawal.clone();
+        // nonces beyond the safe integers of javascript are published as strings
+        withdrawal["nonce"] = json!("18446744073709551615");
+        let mut out_of_range = withdrawal.clone();
+        out_of_range["nonce"] = json!("18446744073709551616");
+
+        let (validated, results) = validate(vec![withdrawal, without_nonce, out_of_range]);
+
+        assert_eq!(validated.len(), 1);
+        assert_eq!(validated[0].data_model.nonce, Some(u64::MAX));
+        assert_eq!(
+            results[1].status,
+            EventStatus::Rejected {
+                errors: vec![FieldError::new(
+                    "nonce",
+                    String::from("REQUIRED_FOR_WITHDRAWALS")
+                )]
+            }
+        );
+        assert_eq!(
+            results[2].status,
+            EventStatus::Rejected {
+                errors: vec![FieldError::new("nonce", String::from("NONCE_OUT_OF_RANGE"))]
+            }
+        );
+    }
+
+    #[test]
+    fn batch_mode_decides_whether_a_partially_valid_batch_is_applied() {
+        let mut invalid = deposit();
+        invalid["amount"] = json!("one hundred");
+
+        let (_, valid) = validate(vec![deposit(), deposit()]);
+        let (_, partial) = validate(vec![deposit(), invalid.clone()]);
+        let (_, rejected) = validate(vec![invalid]);
+        let (_, empty) = validate(vec![]);
+
+        for mode in [BatchMode::RejectAll, BatchMode::ApplyValid] {
+            assert!(mode.should_apply(&valid));
+            assert!(!mode.should_apply(&rejected));
+            assert!(!mode.should_apply(&empty));
+        }
+        assert!(!BatchMode::RejectAll.should_apply(&partial));
+        assert!(BatchMode::ApplyValid.should_apply(&partial));
+        assert_eq!(BatchMode::default(), BatchMode::RejectAll);
+    }
+
+    #[test]
+    fn a_batch_has_to_be_a_json_array() {
+        assert_eq!(
+            validate_json_events(&deposit().to_string()).err(),
+            Some(CcampError::InvalidJson {
+                reason: String::from("EXPECTED_ARRAY_OF_EVENTS")
+            })
+        );
+        assert!(matches!(
+            validate_json_events("[{"),
+            Err(CcampError::InvalidJson { .. })
+        ));
+    }
+}
@@ -1,9 +1,15 @@
 use candid::Principal;
-use ic_cdk::{caller, storage};
+use ic_cdk::{api::time, caller};
 use ic_cdk_macros::{init, post_upgrade, pre_upgrade, query, update};
 use lib::{
+    audit::{AuditFilter, AuditPage},
+    dc::Subscription,
+    outbox::OutboxEntry,
+    owner::{AccessControl, Role},
+    pause::{Operation, PauseRecord},
     remittance::{Config, Environment},
-    RemittanceSubscriber,
+    validation::{BatchMode, ValidationReport},
+    CcampError,
 };
 use std::{cell::RefCell, collections::HashMap};
 
@@ -11,7 +17,6 @@ mod logstore;
 mod remittance;
 
 thread_local! {
-    static REMITTANCE_CANISTER: RefCell<Option<lib::RemittanceSubscriber>> = RefCell::default();
     static CONFIG: RefCell<Config> = RefCell::default();
     static WHITELISTED_PUBLISHERS: RefCell<HashMap<Principal, bool>> = RefCell::default();
 }
@@ -20,6 +25,7 @@ thread_local! {
 #[init]
 async fn init(env_opt: Option<Environment>) {
     lib::owner::init_owner();
+    remittance::start_retry_timer();
 
     // save the environment this is running in
     if let Some(env) = env_opt {
@@ -43,86 +49,267 @@ fn owner() -> String {
     lib::owner::get_owner()
 }
 
+#[query]
+fn get_access_control() -> AccessControl {
+    lib::owner::get_access_control()
+}
+
+#[query]
+fn get_roles(principal: Principal) -> Vec<Role> {
+    lib::owner::get_roles(principal)
+}
+
 #[update]
-pub async fn set_remittance_canister(remittance_principal: Principal) {
-    lib::owner::only_owner();
-    REMITTANCE_CANISTER.with(|rc| {
-        let _ = rc.borrow_mut().insert(lib::RemittanceSubscriber {
-            canister_principal: remittance_principal,
-            subscribed: false,
-        });
-    })
+fn grant_role(role: Role, principal: Principal) -> Result<(), CcampError> {
+    lib::owner::grant_role(role, principal)
+}
+
+#[update]
+fn revoke_role(role: Role, principal: Principal) -> Result<(), CcampError> {
+    lib::owner::revoke_role(role, principal)
+}
+
+#[update]
+fn propose_owner(new_owner: Option<Principal>) -> Result<(), CcampError> {
+    lib::owner::propose_owner(new_owner)
+}
+
+#[update]
+fn accept_ownership() -> Result<(), CcampError> {
+    lib::owner::accept_ownership()
+}
+
+// the privileged calls made to this canister, only readable by auditors
+#[query]
+fn get_audit_log(
+    filter: Option<AuditFilter>,
+    start: u64,
+    limit: u64,
+) -> Result<AuditPage, CcampError> {
+    lib::audit::get_page(filter, start, limit)
+}
+
+// stop an operation of this canister e.g when a signer or publisher is compromised
+#[update]
+fn pause(operation: Operation, reason: String) -> Result<(), CcampError> {
+    lib::pause::pause(operation, reason)
+}
+
+#[update]
+fn unpause(operation: Operation, reason: String) -> Result<(), CcampError> {
+    lib::pause::unpause(operation, reason)
+}
+
+#[query]
+fn get_paused_operations() -> Vec<Operation> {
+    lib::pause::get_paused()
 }
 
 #[query]
-pub fn get_remittance_canister() -> RemittanceSubscriber {
-    // confirm at least one remittance canister is subscribed to this pdc
-    crate::REMITTANCE_CANISTER
-        .with(|rc| rc.borrow().clone())
-        .expect("REMITTANCE_CANISTER_NOT_INITIALIZED")
+fn get_pause_history() -> Vec<PauseRecord> {
+    lib::pause::get_history()
 }
 
+// whitelist a canister which can then subscribe to the events of this canister
 #[update]
-pub fn add_publisher(principal: Principal) {
-    lib::owner::only_owner();
+pub fn add_subscriber(canister_principal: Principal) -> Result<(), CcampError> {
+    let result = lib::owner::ensure_role(Role::Admin).map(|_| {
+        lib::dc::add_subscriber(canister_principal);
+    });
+    lib::audit::record("add_subscriber", &(canister_principal,), &result);
 
-    WHITELISTED_PUBLISHERS.with(|rc| rc.borrow_mut().insert(principal, true));
+    result
 }
 
 #[update]
-pub fn remove_publisher(principal: Principal) {
-    lib::owner::only_owner();
+pub fn remove_subscriber(canister_principal: Principal) -> Result<(), CcampError> {
+    let result = lib::owner::ensure_role(Role::Admin).map(|_| {
+        lib::dc::remove_subscriber(canister_principal);
+    });
+    lib::audit::record("remove_subscriber", &(canister_principal,), &result);
 
-    WHITELISTED_PUBLISHERS.with(|rc| rc.borrow_mut().remove(&principal));
+    result
 }
 
-// this function is going to be called by the remittance canister
-// so it can recieve "publish" events from this canister
+// the whitelisted canisters along with their subscription and delivery status
+#[query]
+pub fn get_subscribers() -> Vec<Subscription> {
+    lib::dc::get_subscribers()
+}
+
+// the batches of events which weren't acknowledged by their subscriber yet
+#[query]
+fn get_outbox() -> Vec<OutboxEntry> {
+    lib::outbox::get_entries()
+}
+
+// retry every pending batch right away instead of waiting for its backoff to elapse
 #[update]
-fn subscribe() {
-    // verify if this remittance canister has been whitelisted
-    // set the subscribed value to true if its the same, otherwise panic
-    let subscriber_principal_id = ic_cdk::caller();
-    let whitelisted_remittance_canister = REMITTANCE_CANISTER
-        .with(|rc| rc.borrow().clone())
-        .expect("REMITTANCE_CANISTER_NOT_INITIALIZED");
+async fn retry_deliveries() -> Result<(), CcampError> {
+    let result = lib::owner::ensure_role(Role::Operator);
+    lib::audit::record("retry_deliveries", &(), &result);
+    result?;
 
-    if whitelisted_remittance_canister.canister_principal != subscriber_principal_id {
-        panic!("REMITTANCE_CANISTER_NOT_WHITELISTED")
-    };
+    lib::outbox::reset_backoff(time());
+    lib::outbox::flush().await;
 
-    REMITTANCE_CANISTER.with(|rc| {
-        let _ = rc.borrow_mut().insert(lib::RemittanceSubscriber {
-            canister_principal: subscriber_principal_id,
-            subscribed: true,
-        });
+    Ok(())
+}
+
+// deliver the oldest batch of a subscriber without its events e.g because applying them keeps trapping
+// the events are kept by `get_skipped_deliveries` so they can be looked into
+#[update]
+async fn skip_delivery(subscriber: Principal, sequence: u64) -> Result<(), CcampError> {
+    let result = lib::owner::ensure_role(Role::Operator)
+        .and_then(|_| lib::outbox::skip(subscriber, sequence, time()))
+        .map(|_| ());
+    lib::audit::record("skip_delivery", &(subscriber, sequence), &result);
+    result?;
+
+    lib::outbox::deliver(subscriber).await;
+
+    Ok(())
+}
+
+// queue the events of a rejected or skipped batch again e.g once the chain of its events is registered
+// they are delivered as the next batch of the subscriber
+#[update]
+async fn replay_delivery(subscriber: Principal, sequence: u64) -> Result<u64, CcampError> {
+    let result = lib::owner::ensure_role(Role::Operator)
+        .and_then(|_| lib::outbox::replay(subscriber, sequence, time()));
+    lib::audit::record("replay_delivery", &(subscriber, sequence), &result);
+    let replayed = result?;
+
+    lib::outbox::deliver(subscriber).await;
+
+    Ok(replayed)
+}
+
+// the batches rejected by their subscriber or skipped by an operator along with the events which weren't applied
+#[query]
+fn get_skipped_deliveries() -> Vec<OutboxEntry> {
+    lib::outbox::get_skipped()
+}
+
+#[update]
+pub fn add_publisher(principal: Principal) -> Result<(), CcampError> {
+    let result = lib::owner::ensure_role(Role::PublisherManager).map(|_| {
+        WHITELISTED_PUBLISHERS.with(|rc| rc.borrow_mut().insert(principal, true));
     });
+    lib::audit::record("add_publisher", &(principal,), &result);
+
+    result
 }
 
 #[update]
-async fn manual_publish(json_data: String) {
-    lib::owner::only_owner();
+pub fn remove_publisher(principal: Principal) -> Result<(), CcampError> {
+    let result = lib::owner::ensure_role(Role::PublisherManager).map(|_| {
+        WHITELISTED_PUBLISHERS.with(|rc| rc.borrow_mut().remove(&principal));
+    });
+    lib::audit::record("remove_publisher", &(principal,), &result);
+
+    result
+}
+
+// this function is going to be called by the remittance canister
+// so it can recieve "publish" events from this canister
+#[update]
+fn subscribe(subscriber: lib::Subscriber) -> Result<(), CcampError> {
+    // verify if this canister has been whitelisted
+    // and save the topic and filter of the events it wants to recieve
+    lib::dc::subscribe(subscriber)
+}
+
+// the optional mode decides if a batch containing invalid events is rejected entirely
+// or if only its valid events are applied, by default the whole batch is rejected
+#[update]
+async fn manual_publish(
+    json_data: String,
+    mode: Option<BatchMode>,
+) -> Result<ValidationReport, CcampError> {
+    let result = match lib::owner::ensure_role(Role::Operator) {
+        Ok(()) => {
+            remittance::publish_json(json_data.clone(), mode.clone().unwrap_or_default()).await
+        }
+        Err(error) => Err(error),
+    };
+    lib::audit::record("manual_publish", &(json_data, mode), &result);
 
-    let _ = remittance::publish_json(json_data).await;
+    result
 }
 
 #[update]
-async fn process_event(json_data: String) {
+async fn process_event(json_data: String) -> Result<(), CcampError> {
+    lib::pause::ensure_not_paused(Operation::ProcessEvent)?;
     let caller_principal_id = caller();
     let whitelisted = WHITELISTED_PUBLISHERS.with(|rc| rc.borrow().clone());
 
     if !whitelisted.contains_key(&caller_principal_id) {
-        panic!("PRINCPAL NOT WHITELISTED")
+        return Err(CcampError::PublisherNotWhitelisted {
+            caller: caller_principal_id,
+        });
     }
-    let _ = remittance::publish_event(json_data).await;
+
+    remittance::publish_event(json_data).await
+}
+
+// called by the publishers as the blocks of the events they published get deeper
+// the report is forwarded to the remittance canisters holding the deposits of the block
+#[update]
+async fn confirm_block(
+    chain: String,
+    block_hash: String,
+    confirmations: u64,
+) -> Result<(), CcampError> {
+    lib::pause::ensure_not_paused(Operation::ProcessEvent)?;
+    ensure_block_reporter()?;
+
+    let parsed_chain = remittance::parse_chain(&chain)?;
+    lib::dc::forward_block_report(
+        "confirm_block",
+        &parsed_chain,
+        (chain, block_hash, confirmations),
+    )
+    .await
+}
+
+// called by the publishers when a block is no longer part of its chain because of a reorg
+#[update]
+async fn orphan_block(chain: String, block_hash: String) -> Result<(), CcampError> {
+    let result = match lib::pause::ensure_not_paused(Operation::ProcessEvent)
+        .and_then(|_| ensure_block_reporter())
+    {
+        Ok(()) => match remittance::parse_chain(&chain) {
+            Ok(parsed_chain) => {
+                let report = (chain.clone(), block_hash.clone());
+                lib::dc::forward_block_report("orphan_block", &parsed_chain, report).await
+            }
+            Err(error) => Err(error),
+        },
+        Err(error) => Err(error),
+    };
+    lib::audit::record("orphan_block", &(chain, block_hash), &result);
+
+    result
+}
+
+// the whitelisted publishers, or an operator reporting a block they missed
+fn ensure_block_reporter() -> Result<(), CcampError> {
+    let caller_principal_id = caller();
+    let whitelisted =
+        WHITELISTED_PUBLISHERS.with(|rc| rc.borrow().contains_key(&caller_principal_id));
+    if whitelisted || lib::owner::has_role(&caller_principal_id, Role::Operator) {
+        return Ok(());
+    }
+
+    Err(CcampError::PublisherNotWhitelisted {
+        caller: caller_principal_id,
+    })
 }
 
 #[query]
 fn is_subscribed(canister_principal: Principal) -> bool {
-    let whitelisted_remittance_canister = get_remittance_canister();
-
-    return whitelisted_remittance_canister.canister_principal == canister_principal
-        && whitelisted_remittance_canister.subscribed;
+    lib::dc::is_subscribed(canister_principal)
 }
 
 #[query]
@@ -131,26 +318,86 @@ fn get_caller() -> Principal {
 }
 
 // --------------------------- upgrade hooks ------------------------- //
+// the version of the layout saved by `pre_upgrade`
+const STATE_VERSION: u32 = 1;
+// the stores saved across upgrades
+type StableState = (
+    lib::dc::SubscriberStore,
+    Config,
+    HashMap<Principal, bool>,
+    AccessControl,
+    lib::pause::PauseState,
+    lib::audit::AuditLog,
+    lib::outbox::Outbox,
+);
+
 #[pre_upgrade]
 fn pre_upgrade() {
-    let cloned_store = REMITTANCE_CANISTER.with(|rc| rc.borrow().clone());
+    let cloned_store = lib::dc::SUBSCRIBERS.with(|store| store.borrow().clone());
     let config_store = CONFIG.with(|store| store.borrow().clone());
     let whitelisted_store = WHITELISTED_PUBLISHERS.with(|store| store.borrow().clone());
 
-    storage::stable_save((cloned_store, config_store, whitelisted_store)).unwrap()
+    let access_control = lib::owner::get_access_control();
+    let pause_state = lib::pause::get_state();
+    let audit_log = lib::audit::get_log();
+    let outbox = lib::outbox::get_state();
+
+    lib::upgrade::save(
+        STATE_VERSION,
+        (
+            cloned_store,
+            config_store,
+            whitelisted_store,
+            access_control,
+            pause_state,
+            audit_log,
+            outbox,
+        ),
+    )
+    .unwrap()
 }
 #[post_upgrade]
 async fn post_upgrade() {
-    let (old_store, cloned_config, whitelisted_store): (
-        Option<lib::RemittanceSubscriber>,
-        Config,
-        HashMap<Principal, bool>,
-    ) = storage::stable_restore().unwrap();
+    let bytes = lib::upgrade::saved_bytes();
+    let restored: Result<StableState, String> = match lib::upgrade::version(&bytes) {
+        Some(_) => lib::upgrade::decode_versioned(&bytes, STATE_VERSION),
+        // the subscriber, config and publishers saved by the first version
+        None => lib::upgrade::decode(&bytes).map(
+            |(subscriber, config, whitelisted): (
+                Option<lib::upgrade::RemittanceSubscriber>,
+                Config,
+                HashMap<Principal, bool>,
+            )| {
+                (
+                    lib::upgrade::subscriber_store(subscriber),
+                    config,
+                    whitelisted,
+                    lib::upgrade::legacy_access_control(caller()),
+                    lib::pause::PauseState::default(),
+                    lib::audit::AuditLog::default(),
+                    lib::outbox::Outbox::default(),
+                )
+            },
+        ),
+    };
+    let (
+        old_store,
+        cloned_config,
+        whitelisted_store,
+        access_control,
+        pause_state,
+        audit_log,
+        outbox,
+    ) = restored.unwrap_or_else(|error| ic_cdk::trap(&error));
 
-    REMITTANCE_CANISTER.with(|store| *store.borrow_mut() = old_store);
+    lib::dc::SUBSCRIBERS.with(|store| *store.borrow_mut() = old_store);
     CONFIG.with(|c| *c.borrow_mut() = cloned_config);
     WHITELISTED_PUBLISHERS.with(|c| *c.borrow_mut() = whitelisted_store);
-
-    lib::owner::init_owner();
+    lib::owner::restore_access_control(access_control);
+    lib::pause::restore_state(pause_state);
+    lib::audit::restore_log(audit_log);
+    // the batches which weren't acknowledged before the upgrade are retried by the new timer
+    lib::outbox::restore_state(outbox);
+    remittance::start_retry_timer();
 }
 // --------------------------- upgrade hooks ------------------------- //
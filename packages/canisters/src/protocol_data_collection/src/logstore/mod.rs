@@ -5,6 +5,7 @@ use crate::logstore::{
     constants::{
         FUNDS_CANCELED_DECLARATION, FUNDS_DEPOSITED_DECLARATION, FUNDS_WITHDRAWN_DECLARATION,
     },
+    types::BrokerPayloadContent,
     utils::{extract_event_name, fmt_event_data},
 };
 
@@ -13,7 +14,9 @@ pub mod traits;
 pub mod types;
 pub mod utils;
 
-pub fn derive_event_model(topics: &Vec<String>, data: &String) -> lib::Event {
+pub fn derive_event_model(content: &BrokerPayloadContent) -> lib::Event {
+    let topics = &content.topics;
+    let data = &content.data;
     let event_selector = &topics[0][..];
 
     // match an event selector to a corresponding parser
@@ -21,10 +24,10 @@ pub fn derive_event_model(topics: &Vec<String>, data: &String) -> lib::Event {
         "0x7b2c468feb026788630cfbdc9c64aa29bde0318b58c8ab900614cc96f9305955" => {
             EventDescriptor::parse_declaration(FUNDS_DEPOSITED_DECLARATION)
         }
-        "0x8407e01c63958861c3963bdc9dd3ea91ccde4c64aeef37c136ae8549cf828808" => {
+        "0xb3cb07b9d323dd285ce976a9a1195d770ebcbf52573aab3e88acf1040b34e8b0" => {
             EventDescriptor::parse_declaration(FUNDS_WITHDRAWN_DECLARATION)
         }
-        "0x0f26c836f96a618c08949606c5dea169c2ae9ade5c3b42b00e7aacfc4be0612a" => {
+        "0x2def1560e9189c9f7b5c895977c6eec4eec0484d711c5f0e49163808051a27af" => {
             EventDescriptor::parse_declaration(FUNDS_CANCELED_DECLARATION)
         }
         _ => panic!("INVALID_EVENT_TYPE"),
@@ -46,14 +49,23 @@ pub fn derive_event_model(topics: &Vec<String>, data: &String) -> lib::Event {
     let amount = fmt_event_data(&parsed_logs[2]);
     let chain = fmt_event_data(&parsed_logs[3]);
     let token_address = fmt_event_data(&parsed_logs[4]);
+    // withdrawals and cancellations carry the nonce of the ticket they resolve
+    let nonce = parsed_logs
+        .get(5)
+        .map(|nonce| fmt_event_data(nonce).parse::<candid::Nat>().unwrap());
     let event_name = extract_event_name(&event_descriptor.canonical().to_string()[..]).to_string();
 
     Event {
         event_name: event_name,
         canister_id: canister_id,
         account: account_address,
-        amount: amount.parse::<i64>().unwrap(),
+        amount: amount.parse::<candid::Int>().unwrap(),
         chain: chain,
         token: token_address,
+        // the position of the log makes sure the event is only credited once
+        transaction_hash: Some(content.transaction_hash.clone()),
+        block_hash: Some(content.block_hash.clone()),
+        log_index: Some(content.log_index),
+        nonce,
     }
 }
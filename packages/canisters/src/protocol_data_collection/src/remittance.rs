@@ -1,20 +1,29 @@
-use core::panic;
-
 use crate::logstore::derive_event_model;
 use crate::logstore::types::JSONPayload;
 use candid::Principal;
-use ic_cdk::api::call::RejectionCode;
-use lib::{ethereum::recover_address_from_eth_signature, Event};
-use serde_json::Value;
-
-pub async fn publish_event(json_data: String) -> Result<(), String> {
-    // pub async fn publish_json(json_data: String) -> Result<(), String> {
+use ic_cdk::api::time;
+use lib::{
+    ethereum::recover_address_from_eth_signature,
+    validation::{validate_json_events, BatchMode, ValidationReport},
+    CcampError,
+};
+use std::time::Duration;
+
+// how often the batches which couldn't be delivered are retried once their backoff elapsed
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+pub async fn publish_event(json_data: String) -> Result<(), CcampError> {
     let validation_treshold = 1;
     let payload: JSONPayload =
-        serde_json::from_str(&json_data[..]).expect("FAILED_TO_DESERIALIZE_JSON");
+        serde_json::from_str(&json_data[..]).map_err(|error| CcampError::InvalidJson {
+            reason: error.to_string(),
+        })?;
 
     if payload.validation.len() < validation_treshold {
-        panic!("NOT_ENOUGH_VALIDATIONS")
+        return Err(CcampError::NotEnoughValidations {
+            required: validation_treshold as u64,
+            received: payload.validation.len() as u64,
+        });
     }
 
     // validate the signature and get the hashes from the source for comparison
@@ -22,12 +31,12 @@ pub async fn publish_event(json_data: String) -> Result<(), String> {
     let publisher_id = payload.source.stream_message.message_id.publisher_id;
     let signature = payload.source.stream_message.signature;
 
-    let recovered = recover_address_from_eth_signature(signature, message).unwrap();
+    let recovered = recover_address_from_eth_signature(signature, message)?;
     if recovered != publisher_id {
-        panic!(
-            "SIGNATURE_VERIFICATION_FAILED:recovered key: {}; public key:{}",
-            recovered, publisher_id
-        );
+        return Err(CcampError::SignatureMismatch {
+            recovered,
+            expected: publisher_id,
+        });
     }
     // verify the signature of each validation and content
     for validation in payload.validation.clone() {
@@ -35,41 +44,52 @@ pub async fn publish_event(json_data: String) -> Result<(), String> {
         let publisher_id = validation.metadata.stream_message.message_id.publisher_id;
         let signature = validation.metadata.stream_message.signature;
 
-        let recovered = recover_address_from_eth_signature(signature, message).unwrap();
+        let recovered = recover_address_from_eth_signature(signature, message)?;
         if recovered != publisher_id {
-            panic!(
-                "SIGNATURE_VERIFICATION_FAILED:recovered key: {}; public key:{}",
-                recovered, publisher_id
-            )
+            return Err(CcampError::SignatureMismatch {
+                recovered,
+                expected: publisher_id,
+            });
         }
         let is_valid = validation.content.block_hash == payload.source.content.block_hash
             && validation.content.log_index == payload.source.content.log_index
             && validation.content.transaction_hash == payload.source.content.transaction_hash;
 
         if !is_valid {
-            panic!("INVALID_MESSAGE_CONTENT")
+            return Err(CcampError::InvalidMessageContent);
         }
     }
 
     let validations = payload.validation.clone();
 
-    let parsed_event =
-        derive_event_model(&validations[0].content.topics, &validations[0].content.data);
+    let parsed_event = derive_event_model(&validations[0].content);
 
     // panic!("{:?}", parsed_event);
-    let dc_canister: Principal = (&parsed_event.canister_id[..]).try_into().unwrap();
+    let dc_canister = parse_canister_id(&parsed_event.canister_id)?;
 
-    let data_model: lib::DataModel = parsed_event.try_into().unwrap();
-    let broadcast_response = broadcast_to_subscribers(&vec![data_model], dc_canister);
     // convert the event details to a data model
+    let data_model = lib::DataModel::try_from(parsed_event).map_err(|errors| {
+        let reasons: Vec<String> = errors
+            .iter()
+            .map(|error| format!("{}:{}", error.field, error.reason))
+            .collect();
+
+        CcampError::InvalidEvent {
+            reason: reasons.join(";"),
+        }
+    })?;
 
-    broadcast_response.or(Err("ERR_SAVING_DATA".to_string()))
+    broadcast_to_subscribers(&[data_model], dc_canister)
 }
 
-pub async fn publish_json(json_data: String) -> Result<(), String> {
+pub async fn publish_json(
+    json_data: String,
+    mode: BatchMode,
+) -> Result<ValidationReport, CcampError> {
     // the string provided should be an array of events
     // the same format the ccamp uses to fetch events from logstore
     // probably for some reason were missed by the poller or for some other reason we need a manual provision of events
+    // amounts which are too large to be represented as a json number can be passed as a string
     // schema
     // [{
     //     "event_name": "FundsDeposited",
@@ -80,47 +100,50 @@ pub async fn publish_json(json_data: String) -> Result<(), String> {
     //     "token": "0xB24a30A3971e4d9bf771BDc81435c25EA69A445c"
     // }]
 
-    // Parse the string of data into serde_json::Value.
-    let json_event: Value =
-        serde_json::from_str(&json_data[..]).expect("JSON_DESERIALIZATION_FAILED");
-    // Make sure the top-level JSON is an array
-    let update_succesful = if let Value::Array(events) = json_event {
-        for event in events {
-            // parse the json object gotten back into an "'Event' struct"
-            let json_event: Event = serde_json::from_value(event).unwrap();
-            // parse the canister_id which is a string into a principal
-            let dc_canister: Principal = (&json_event.canister_id[..]).try_into().unwrap();
-            // convert each "event" object into a data model and send it to the remittance canister
-            let parsed_event: lib::DataModel = json_event.into();
+    // validate every event and keep track of the ones which were rejected
+    let (validated_events, results) = validate_json_events(&json_data)?;
+    let applied = mode.should_apply(&results);
+
+    if applied {
+        for event in validated_events {
             // send this info over to the remittance canister in order to modify the balances
-            // TODO: use the response from the broadcast to return a response
-            let _ = broadcast_to_subscribers(&vec![parsed_event], dc_canister);
+            broadcast_to_subscribers(&[event.data_model], event.dc_canister)?;
         }
-        Ok(())
-    } else {
-        Err("ERROR_PARSING_EVENT_INTO_DATAMODEL".to_string())
-    };
+    }
 
-    update_succesful
+    Ok(ValidationReport {
+        events: results,
+        applied,
+    })
 }
 
-// we would use this method to publish data to the subscriber
-// which would be the remittance model
-// so when we have some new data, we would publish it to the remittance model
+// we would use this method to publish data to the subscribers
+// which would be the remittance models interested in it
+// the events are kept in the outbox until every subscriber acknowledged them
 pub fn broadcast_to_subscribers(
-    events: &Vec<lib::DataModel>,
+    events: &[lib::DataModel],
     dc_canister: Principal,
-) -> Result<(), RejectionCode> {
-    let whitelisted_remittance_canister = crate::get_remittance_canister();
-    if !whitelisted_remittance_canister.subscribed {
-        panic!("REMITTANCE_CANISTER_NOT_INITIALIZED")
-    }
+) -> Result<(), CcampError> {
+    lib::outbox::enqueue(events, dc_canister, time())?;
+    // deliver the events once this message is done instead of waiting for the retry timer
+    ic_cdk_timers::set_timer(Duration::ZERO, || ic_cdk::spawn(lib::outbox::flush()));
 
-    let remittance_response: Result<(), RejectionCode> = ic_cdk::notify(
-        whitelisted_remittance_canister.canister_principal,
-        "update_remittance",
-        (&events, dc_canister),
-    );
+    Ok(())
+}
+
+// has to be started again after every upgrade as timers aren't kept
+pub fn start_retry_timer() {
+    ic_cdk_timers::set_timer_interval(RETRY_INTERVAL, || ic_cdk::spawn(lib::outbox::flush()));
+}
+
+pub fn parse_chain(chain: &str) -> Result<lib::Chain, CcampError> {
+    lib::Chain::try_from(chain.to_string()).map_err(|_| CcampError::InvalidChain {
+        chain: chain.to_string(),
+    })
+}
 
-    remittance_response
+fn parse_canister_id(canister_id: &str) -> Result<Principal, CcampError> {
+    Principal::from_text(canister_id).map_err(|error| CcampError::InvalidEvent {
+        reason: format!("INVALID_CANISTER_ID:{}", error),
+    })
 }
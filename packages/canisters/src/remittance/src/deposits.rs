@@ -0,0 +1,405 @@
+// deposits are only credited once the block they were included in is deep enough to be final
+use candid::{CandidType, Nat, Principal};
+use lib::{dedup::EventKey, CcampError, Chain, DataModel, EventIdentity};
+use serde_derive::Deserialize;
+use std::collections::BTreeMap;
+
+use crate::remittance::{self, Account};
+
+// the most blocks whose status is kept, the ones reported first are forgotten first once it is reached
+pub const MAX_TRACKED_BLOCKS: usize = 10_000;
+
+// a block is identified by its chain and hash
+pub type BlockKey = (Chain, String);
+
+// a deposit which was seen on chain but whose block isn't final yet
+// it is visible through `get_pending_balance` but can't be remitted
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub struct PendingDeposit {
+    pub deposit: DataModel,
+    pub dc_canister: Principal,
+    // the number of blocks built on top of its block when it was last reported
+    pub confirmations: u64,
+}
+impl PendingDeposit {
+    fn is_in(&self, block: &BlockKey) -> bool {
+        self.deposit
+            .identity
+            .as_ref()
+            .is_some_and(|identity| identity.chain == block.0 && identity.block_hash == block.1)
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum BlockStatus {
+    Confirmed { confirmations: u64 },
+    // the block is no longer part of the chain because of a reorg
+    Orphaned,
+}
+
+#[derive(Clone, Debug, Default, CandidType, Deserialize, PartialEq)]
+pub struct PendingDepositStore {
+    pub deposits: BTreeMap<EventKey, PendingDeposit>,
+    // the last status reported for a block along with the position it was first reported at
+    // the deposits delivered after their block was reported are then handled too
+    pub blocks: BTreeMap<BlockKey, (u64, BlockStatus)>,
+    pub block_positions: BTreeMap<u64, BlockKey>,
+    pub next_position: u64,
+}
+impl PendingDepositStore {
+    pub fn block_status(&self, block: &BlockKey) -> Option<BlockStatus> {
+        self.blocks.get(block).map(|(_, status)| status.clone())
+    }
+
+    pub fn set_block_status(&mut self, block: BlockKey, status: BlockStatus, capacity: usize) {
+        match self.blocks.get_mut(&block) {
+            Some((_, existing_status)) => *existing_status = status,
+            None => {
+                self.blocks
+                    .insert(block.clone(), (self.next_position, status));
+                self.block_positions.insert(self.next_position, block);
+                self.next_position += 1;
+            }
+        }
+
+        while self.block_positions.len() > capacity {
+            if let Some((_, evicted)) = self.block_positions.pop_first() {
+                self.blocks.remove(&evicted);
+            }
+        }
+    }
+
+    // remove the pending deposits of a block
+    fn take_deposits_in(&mut self, block: &BlockKey) -> Vec<PendingDeposit> {
+        let keys: Vec<EventKey> = self
+            .deposits
+            .iter()
+            .filter(|(_, pending_deposit)| pending_deposit.is_in(block))
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        keys.iter()
+            .filter_map(|key| self.deposits.remove(key))
+            .collect()
+    }
+}
+
+// the number of confirmations after which the blocks of a chain are final
+pub fn required_confirmations(chain: &Chain) -> u64 {
+    lib::chain::get_chain_metadata(chain).map_or(0, |metadata| metadata.finality_depth)
+}
+
+// the deposits are validated when they are received so crediting them can't fail
+// unless the balances are inconsistent, trap so that the report is rolled back
+fn credit_or_trap(pending_deposit: &PendingDeposit, now: u64) {
+    let credited =
+        remittance::record_deposit(&pending_deposit.deposit, pending_deposit.dc_canister, now);
+    if let Err(error) = credited {
+        ic_cdk::trap(&error.to_string());
+    }
+}
+
+// hold a deposit until its block is final, it is credited right away if it already is
+pub fn receive(deposit: DataModel, dc_canister: Principal, now: u64) -> Result<(), CcampError> {
+    let identity = match deposit.identity.clone() {
+        Some(identity) => identity,
+        None => return remittance::record_deposit(&deposit, dc_canister, now).map(|_| ()),
+    };
+    let block = (identity.chain.clone(), identity.block_hash.clone());
+
+    let status = crate::PENDING_DEPOSITS.with(|pending| pending.borrow().block_status(&block));
+    let confirmations = match status {
+        Some(BlockStatus::Confirmed { confirmations }) => confirmations,
+        // the deposit of an orphaned block is dropped, it is received again once it is included in another block
+        Some(BlockStatus::Orphaned) => {
+            lib::dedup::forget(&identity);
+            return Ok(());
+        }
+        None => 0,
+    };
+    if confirmations >= required_confirmations(&identity.chain) {
+        return remittance::record_deposit(&deposit, dc_canister, now).map(|_| ());
+    }
+
+    crate::PENDING_DEPOSITS.with(|pending| {
+        pending.borrow_mut().deposits.insert(
+            identity.key(),
+            PendingDeposit {
+                deposit,
+                dc_canister,
+                confirmations,
+            },
+        )
+    });
+
+    Ok(())
+}
+
+// record how deep a block is and credit its deposits once it is final
+// returns the identities of the deposits which were credited
+pub fn confirm_block(
+    chain: Chain,
+    block_hash: String,
+    confirmations: u64,
+    now: u64,
+) -> Result<Vec<EventIdentity>, CcampError> {
+    let block = (chain, block_hash);
+    let required = required_confirmations(&block.0);
+
+    let finalized = crate::PENDING_DEPOSITS.with(|pending| {
+        let mut pending = pending.borrow_mut();
+        let confirmations = match pending.block_status(&block) {
+            Some(BlockStatus::Orphaned) => {
+                return Err(CcampError::BlockOrphaned {
+                    chain: block.0.to_string(),
+                    block_hash: block.1.clone(),
+                })
+            }
+            // the reports can arrive out of order so a block never gets less deep
+            Some(BlockStatus::Confirmed {
+                confirmations: reported,
+            }) => confirmations.max(reported),
+            None => confirmations,
+        };
+        pending.set_block_status(
+            block.clone(),
+            BlockStatus::Confirmed { confirmations },
+            MAX_TRACKED_BLOCKS,
+        );
+
+        if confirmations < required {
+            for pending_deposit in pending.deposits.values_mut() {
+                if pending_deposit.is_in(&block) {
+                    pending_deposit.confirmations = confirmations;
+                }
+            }
+            return Ok(vec![]);
+        }
+
+        Ok(pending.take_deposits_in(&block))
+    })?;
+
+    for pending_deposit in &finalized {
+        credit_or_trap(pending_deposit, now);
+    }
+
+    Ok(finalized
+        .into_iter()
+        .filter_map(|pending_deposit| pending_deposit.deposit.identity)
+        .collect())
+}
+
+// reverse the pending deposits of a block which was orphaned by a reorg
+// they are forgotten so they can be received again once they are included in another block
+// returns the identities of the deposits which were reversed
+pub fn orphan_block(chain: Chain, block_hash: String) -> Result<Vec<EventIdentity>, CcampError> {
+    let block = (chain, block_hash);
+    let required = required_confirmations(&block.0);
+
+    let reversed = crate::PENDING_DEPOSITS.with(|pending| {
+        let mut pending = pending.borrow_mut();
+        let confirmations = match pending.block_status(&block) {
+            Some(BlockStatus::Confirmed { confirmations }) => confirmations,
+            Some(BlockStatus::Orphaned) => return Ok(vec![]),
+            None => 0,
+        };
+        // the deposits of a final block were credited and might have been remitted already
+        if confirmations >= required {
+            return Err(CcampError::BlockFinalized {
+                chain: block.0.to_string(),
+                block_hash: block.1.clone(),
+            });
+        }
+
+        pending.set_block_status(block.clone(), BlockStatus::Orphaned, MAX_TRACKED_BLOCKS);
+
+        Ok(pending.take_deposits_in(&block))
+    })?;
+
+    Ok(reversed
+        .into_iter()
+        .filter_map(|pending_deposit| pending_deposit.deposit.identity)
+        .inspect(lib::dedup::forget)
+        .collect())
+}
+
+// the sum of the deposits of an account which aren't final yet
+pub fn get_pending_balance(
+    token: lib::Address,
+    chain: lib::Chain,
+    account: lib::Address,
+    dc_canister: Principal,
+) -> Account {
+    let balance = crate::PENDING_DEPOSITS.with(|pending| {
+        pending
+            .borrow()
+            .deposits
+            .values()
+            .filter(|pending_deposit| {
+                let deposit = &pending_deposit.deposit;
+                deposit.token == token
+                    && deposit.chain == chain
+                    && deposit.account == account
+                    && pending_deposit.dc_canister == dc_canister
+            })
+            .fold(Nat::default(), |sum, pending_deposit| {
+                sum + lib::amount::abs(&pending_deposit.deposit.amount)
+            })
+    });
+
+    Account { balance }
+}
+
+pub fn get_pending_deposits() -> Vec<PendingDeposit> {
+    crate::PENDING_DEPOSITS.with(|pending| pending.borrow().deposits.values().cloned().collect())
+}
+
+pub fn get_state() -> PendingDepositStore {
+    crate::PENDING_DEPOSITS.with(|pending| pending.borrow().clone())
+}
+
+pub fn restore_state(state: PendingDepositStore) {
+    crate::PENDING_DEPOSITS.with(|pending| *pending.borrow_mut() = state);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHAIN: &str = "ethereum:5";
+    const TOKEN: &str = "0xB24a30A3971e4d9bf771BDc81435c25EA69A445c";
+    const ACCOUNT: &str = "0x9C81E8F60a9B8743678F1b6Ae893Cc72c6Bc6840";
+    const DC_CANISTER: &str = "bkyz2-fmaaa-aaaaa-qaaaq-cai";
+
+    fn setup(finality_depth: u64) -> Chain {
+        let chain: Chain = CHAIN.to_string().try_into().unwrap();
+        lib::chain::register_chain(
+            chain.clone(),
+            lib::chain::ChainMetadata {
+                name: String::from("Ethereum Goerli"),
+                finality_depth,
+                locker_address: None,
+            },
+        );
+
+        chain
+    }
+
+    fn deposit(transaction_hash: &str, block_hash: &str) -> DataModel {
+        DataModel::try_from(lib::Event {
+            event_name: String::from("FundsDeposited"),
+            canister_id: DC_CANISTER.to_string(),
+            account: ACCOUNT.to_string(),
+            amount: candid::Int::from(100),
+            chain: CHAIN.to_string(),
+            token: TOKEN.to_string(),
+            transaction_hash: Some(transaction_hash.to_string()),
+            block_hash: Some(block_hash.to_string()),
+            log_index: Some(0),
+            nonce: None,
+        })
+        .unwrap()
+    }
+
+    // the available and pending balances of the account
+    fn balances() -> (Nat, Nat) {
+        let token: lib::Address = TOKEN.to_string().try_into().unwrap();
+        let chain: Chain = CHAIN.to_string().try_into().unwrap();
+        let account: lib::Address = ACCOUNT.to_string().try_into().unwrap();
+        let dc_canister = Principal::from_text(DC_CANISTER).unwrap();
+
+        let available = remittance::get_available_balance(
+            token.clone(),
+            chain.clone(),
+            account.clone(),
+            dc_canister,
+        );
+        let pending = get_pending_balance(token, chain, account, dc_canister);
+
+        (available.balance, pending.balance)
+    }
+
+    #[test]
+    fn deposits_are_credited_once_their_block_is_final() {
+        let chain = setup(2);
+        let dc_canister = Principal::from_text(DC_CANISTER).unwrap();
+
+        receive(deposit("0x01", "0xb1"), dc_canister, 0).unwrap();
+        assert_eq!(balances(), (Nat::from(0), Nat::from(100)));
+
+        assert_eq!(
+            confirm_block(chain.clone(), String::from("0xb1"), 1, 0),
+            Ok(vec![])
+        );
+        assert_eq!(get_pending_deposits()[0].confirmations, 1);
+
+        let credited = confirm_block(chain.clone(), String::from("0xb1"), 2, 0).unwrap();
+        assert_eq!(credited, vec![deposit("0x01", "0xb1").identity.unwrap()]);
+        assert_eq!(balances(), (Nat::from(100), Nat::from(0)));
+
+        // a deposit delivered after its block was reported final is credited right away
+        receive(deposit("0x02", "0xb1"), dc_canister, 0).unwrap();
+        assert_eq!(balances(), (Nat::from(200), Nat::from(0)));
+        assert_eq!(
+            orphan_block(chain, String::from("0xb1")),
+            Err(CcampError::BlockFinalized {
+                chain: CHAIN.to_string(),
+                block_hash: String::from("0xb1")
+            })
+        );
+    }
+
+    #[test]
+    fn deposits_of_an_orphaned_block_are_reversed() {
+        let chain = setup(2);
+        let dc_canister = Principal::from_text(DC_CANISTER).unwrap();
+        let orphaned = deposit("0x01", "0xb1");
+        lib::dedup::record(std::slice::from_ref(&orphaned));
+        receive(orphaned.clone(), dc_canister, 0).unwrap();
+        confirm_block(chain.clone(), String::from("0xb1"), 1, 0).unwrap();
+
+        let reversed = orphan_block(chain.clone(), String::from("0xb1")).unwrap();
+        assert_eq!(reversed, vec![orphaned.identity.clone().unwrap()]);
+        assert_eq!(balances(), (Nat::from(0), Nat::from(0)));
+        assert!(!lib::dedup::is_processed(&orphaned.identity.unwrap()));
+        assert_eq!(
+            confirm_block(chain.clone(), String::from("0xb1"), 2, 0),
+            Err(CcampError::BlockOrphaned {
+                chain: CHAIN.to_string(),
+                block_hash: String::from("0xb1")
+            })
+        );
+
+        // the deposit is dropped if it is delivered again from the orphaned block
+        receive(deposit("0x01", "0xb1"), dc_canister, 0).unwrap();
+        assert_eq!(get_pending_deposits(), vec![]);
+
+        // and it is credited once it is final in the block it was included in after the reorg
+        receive(deposit("0x01", "0xb2"), dc_canister, 0).unwrap();
+        confirm_block(chain, String::from("0xb2"), 2, 0).unwrap();
+        assert_eq!(balances(), (Nat::from(100), Nat::from(0)));
+    }
+
+    #[test]
+    fn the_oldest_blocks_are_forgotten() {
+        let chain: Chain = CHAIN.to_string().try_into().unwrap();
+        let mut store = PendingDepositStore::default();
+        for block_hash in ["0xb1", "0xb2", "0xb3"] {
+            store.set_block_status(
+                (chain.clone(), block_hash.to_string()),
+                BlockStatus::Orphaned,
+                2,
+            );
+        }
+
+        assert_eq!(
+            store.block_status(&(chain.clone(), String::from("0xb1"))),
+            None
+        );
+        assert_eq!(
+            store.block_status(&(chain, String::from("0xb3"))),
+            Some(BlockStatus::Orphaned)
+        );
+        assert_eq!(store.blocks.len(), 2);
+    }
+}
@@ -0,0 +1,743 @@
+// the balances of the canister are kept in a double-entry ledger
+// every change is a journal entry whose debits are equal to its credits
+use candid::{CandidType, Int, Nat, Principal};
+use lib::{Address, Asset, CcampError, Chain};
+use serde_derive::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::remittance::{AvailableBalanceStore, CanisterBalanceStore, WithheldBalanceStore};
+
+// the most entries a single page of the journal can contain
+pub const MAX_JOURNAL_PAGE_SIZE: u64 = 100;
+// the most entries kept, the oldest ones are summed up in the opening balances first once it is reached
+pub const MAX_JOURNAL_ENTRIES: usize = 100_000;
+// the most entries replayed by a single call to `verify`
+pub const MAX_VERIFICATION_BATCH: u64 = 10_000;
+
+// every account holds the balance of a single token on a single chain
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq, Eq, Hash)]
+pub enum LedgerAccount {
+    // the balance a user can remit
+    Available {
+        account: Address,
+        dc_canister: Principal,
+    },
+    // the balance a user remitted until the withdrawal is confirmed or cancelled
+    Withheld {
+        account: Address,
+        dc_canister: Principal,
+    },
+    // the funds locked in the contracts on behalf of the users of a data collection canister
+    Pool {
+        dc_canister: Principal,
+    },
+    // the fees collected by the protocol, no fee is charged yet
+    Fees {
+        dc_canister: Principal,
+    },
+    // the funds the pool held beyond the balances of its users when the ledger was opened
+    OpeningSurplus {
+        dc_canister: Principal,
+    },
+    // the funds the pool lacked to cover the balances of its users when the ledger was opened
+    OpeningShortfall {
+        dc_canister: Principal,
+    },
+}
+impl LedgerAccount {
+    // the pool and the funds it lacked when it was opened are assets so debits increase them
+    // the other accounts are owed to users or the protocol so credits increase them
+    fn is_asset(&self) -> bool {
+        matches!(self, Self::Pool { .. } | Self::OpeningShortfall { .. })
+    }
+
+    pub fn dc_canister(&self) -> Principal {
+        match self {
+            Self::Available { dc_canister, .. }
+            | Self::Withheld { dc_canister, .. }
+            | Self::Pool { dc_canister }
+            | Self::Fees { dc_canister }
+            | Self::OpeningSurplus { dc_canister }
+            | Self::OpeningShortfall { dc_canister } => *dc_canister,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, CandidType, Deserialize, PartialEq, Eq)]
+pub enum Side {
+    Debit,
+    Credit,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub struct Posting {
+    pub token: Address,
+    pub chain: Chain,
+    pub account: LedgerAccount,
+    pub side: Side,
+    pub amount: Nat,
+}
+impl Posting {
+    pub fn asset(&self) -> Asset {
+        Asset {
+            token: self.token.clone(),
+            chain: self.chain.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub enum EntryKind {
+    Deposit,
+    Adjustment,
+    Remittance,
+    Withdrawal,
+    Cancellation,
+    // a remittance whose signature couldn't be made
+    Release,
+    // the balances kept by the canister before the ledger
+    Opening,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub struct JournalEntry {
+    // the position of the entry in the journal, ids are never reused
+    pub id: u64,
+    pub kind: EntryKind,
+    // what the entry was made for e.g the identity of an event or the nonce of a withdrawal
+    pub reference: Option<String>,
+    pub postings: Vec<Posting>,
+    // timestamp in nanoseconds
+    pub timestamp: u64,
+}
+
+pub type BalanceKey = (Address, Chain, LedgerAccount);
+
+// a replay of the journal which is continued where it stopped by the next call to `verify`
+#[derive(Clone, Debug, Default, CandidType, Deserialize, PartialEq)]
+pub struct Replay {
+    // the id of the next entry to replay
+    pub next_id: u64,
+    // the balances once every entry before `next_id` is replayed
+    pub balances: HashMap<BalanceKey, Nat>,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub struct LedgerVerification {
+    // the id of the entry the next call starts from
+    pub next_id: u64,
+    // set once the end of the journal is reached, whether replaying it resulted in the current balances
+    pub consistent: Option<bool>,
+}
+
+#[derive(Clone, Debug, Default, CandidType, Deserialize, PartialEq)]
+pub struct Ledger {
+    // the entries by id in the order they were appended, ids are never reused
+    pub journal: BTreeMap<u64, JournalEntry>,
+    pub next_id: u64,
+    // the balances before the oldest entry of the journal, which sum up the entries dropped from it
+    pub opening_balances: HashMap<BalanceKey, Nat>,
+    // the balance of every account, only ever changed along with an entry of the journal
+    pub balances: HashMap<BalanceKey, Nat>,
+    pub replay: Replay,
+}
+impl Ledger {
+    pub fn balance(&self, key: &BalanceKey) -> Nat {
+        self.balances.get(key).cloned().unwrap_or_default()
+    }
+
+    // append an entry and update the balances of its accounts
+    // nothing changes if the entry isn't balanced or would make a balance negative
+    pub fn post(
+        &mut self,
+        kind: EntryKind,
+        reference: Option<String>,
+        postings: Vec<Posting>,
+        timestamp: u64,
+    ) -> Result<u64, CcampError> {
+        let postings: Vec<Posting> = postings
+            .into_iter()
+            .filter(|posting| posting.amount != 0u64)
+            .collect();
+        let updated_balances = updated_balances(&self.balances, &postings)?;
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.balances.extend(updated_balances);
+        self.journal.insert(
+            id,
+            JournalEntry {
+                id,
+                kind,
+                reference,
+                postings,
+                timestamp,
+            },
+        );
+        self.archive(MAX_JOURNAL_ENTRIES);
+
+        Ok(id)
+    }
+
+    // drop the oldest entries once there are more than `capacity` by adding them to the opening balances
+    // an entry which can't be added stays in the journal so that verifying it fails
+    pub fn archive(&mut self, capacity: usize) {
+        while self.journal.len() > capacity {
+            let Some(oldest) = self.journal.first_entry() else {
+                return;
+            };
+            let Ok(updated_balances) =
+                updated_balances(&self.opening_balances, &oldest.get().postings)
+            else {
+                return;
+            };
+            oldest.remove();
+            self.opening_balances.extend(updated_balances);
+        }
+    }
+
+    // the id of the oldest entry of the journal
+    fn first_id(&self) -> u64 {
+        self.journal.keys().next().copied().unwrap_or(self.next_id)
+    }
+
+    // replay at most `limit` entries from where the previous call stopped, starting from the opening balances
+    // once the end of the journal is reached the replayed balances are compared with the current ones
+    pub fn verify(&mut self, limit: u64) -> LedgerVerification {
+        let first_id = self.first_id();
+        // the entries the replay stopped at were dropped from the journal in the meantime
+        if self.replay.next_id < first_id || self.replay.next_id > self.next_id {
+            self.replay = Replay {
+                next_id: first_id,
+                balances: self.opening_balances.clone(),
+            };
+        }
+
+        let limit = limit.clamp(1, MAX_VERIFICATION_BATCH) as usize;
+        let mut is_postable = true;
+        for (id, entry) in self.journal.range(self.replay.next_id..).take(limit) {
+            match updated_balances(&self.replay.balances, &entry.postings) {
+                Ok(updated_balances) => {
+                    self.replay.balances.extend(updated_balances);
+                    self.replay.next_id = id + 1;
+                }
+                Err(_) => {
+                    is_postable = false;
+                    break;
+                }
+            }
+        }
+
+        match (is_postable, self.replay.next_id == self.next_id) {
+            // an entry which can't be posted again means the journal was tampered with
+            (false, _) => self.finish_replay(false),
+            (true, true) => {
+                let consistent = self.replay.balances == self.balances;
+                self.finish_replay(consistent)
+            }
+            (true, false) => LedgerVerification {
+                next_id: self.replay.next_id,
+                consistent: None,
+            },
+        }
+    }
+
+    // the next call to `verify` starts over from the opening balances
+    fn finish_replay(&mut self, consistent: bool) -> LedgerVerification {
+        self.replay = Replay {
+            next_id: self.first_id(),
+            balances: self.opening_balances.clone(),
+        };
+
+        LedgerVerification {
+            next_id: self.replay.next_id,
+            consistent: Some(consistent),
+        }
+    }
+
+    // whether the balances are the ones obtained by replaying the whole journal from the opening balances
+    #[cfg(test)]
+    pub fn is_consistent(&self) -> bool {
+        let mut ledger = self.clone();
+        ledger.replay = Replay::default();
+        loop {
+            if let Some(consistent) = ledger.verify(MAX_VERIFICATION_BATCH).consistent {
+                return consistent;
+            }
+        }
+    }
+}
+
+// the balances of the accounts of an entry once it is posted
+fn updated_balances(
+    balances: &HashMap<BalanceKey, Nat>,
+    postings: &[Posting],
+) -> Result<HashMap<BalanceKey, Nat>, CcampError> {
+    // every asset of a data collection canister has to balance on its own
+    let mut deltas: Vec<(Asset, Principal, Int)> = Vec::new();
+    for posting in postings {
+        let asset = posting.asset();
+        let dc_canister = posting.account.dc_canister();
+        let amount = match posting.side {
+            Side::Debit => Int::from(posting.amount.clone()),
+            Side::Credit => lib::amount::negate(&Int::from(posting.amount.clone())),
+        };
+        match deltas
+            .iter_mut()
+            .find(|(other, other_dc, _)| *other == asset && *other_dc == dc_canister)
+        {
+            Some((_, _, delta)) => *delta = delta.clone() + amount,
+            None => deltas.push((asset, dc_canister, amount)),
+        }
+    }
+    if let Some((asset, _, delta)) = deltas.into_iter().find(|(_, _, delta)| *delta != 0) {
+        return Err(CcampError::UnbalancedEntry { asset, delta });
+    }
+
+    let mut updated_balances: HashMap<BalanceKey, Nat> = HashMap::new();
+    for posting in postings {
+        let key = (
+            posting.token.clone(),
+            posting.chain.clone(),
+            posting.account.clone(),
+        );
+        let balance = updated_balances
+            .get(&key)
+            .or_else(|| balances.get(&key))
+            .cloned()
+            .unwrap_or_default();
+        let updated_balance = apply(&balance, posting).ok_or_else(|| match posting.account {
+            LedgerAccount::Pool { .. } => CcampError::InsufficientCanisterBalance {
+                requested: posting.amount.clone(),
+                available: balance.clone(),
+            },
+            _ => CcampError::InsufficientBalance {
+                requested: posting.amount.clone(),
+                available: balance.clone(),
+            },
+        })?;
+        updated_balances.insert(key, updated_balance);
+    }
+
+    Ok(updated_balances)
+}
+
+// the balance of an account once a posting is applied to it, none if it would be negative
+fn apply(balance: &Nat, posting: &Posting) -> Option<Nat> {
+    let increases = (posting.side == Side::Debit) == posting.account.is_asset();
+    match increases {
+        true => Some(balance.clone() + posting.amount.clone()),
+        false => lib::amount::checked_sub(balance, &posting.amount),
+    }
+}
+
+pub fn debit(token: &Address, chain: &Chain, account: LedgerAccount, amount: Nat) -> Posting {
+    Posting {
+        token: token.clone(),
+        chain: chain.clone(),
+        account,
+        side: Side::Debit,
+        amount,
+    }
+}
+
+pub fn credit(token: &Address, chain: &Chain, account: LedgerAccount, amount: Nat) -> Posting {
+    Posting {
+        token: token.clone(),
+        chain: chain.clone(),
+        account,
+        side: Side::Credit,
+        amount,
+    }
+}
+
+pub fn post(
+    kind: EntryKind,
+    reference: Option<String>,
+    postings: Vec<Posting>,
+    now: u64,
+) -> Result<u64, CcampError> {
+    crate::LEDGER.with(|ledger| ledger.borrow_mut().post(kind, reference, postings, now))
+}
+
+pub fn balance(token: &Address, chain: &Chain, account: LedgerAccount) -> Nat {
+    crate::LEDGER.with(|ledger| {
+        ledger
+            .borrow()
+            .balance(&(token.clone(), chain.clone(), account))
+    })
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub struct JournalPage {
+    pub entries: Vec<JournalEntry>,
+    // the id to start the next page from, none once the end of the journal is reached
+    pub next: Option<u64>,
+}
+
+// the entries with an id of at least `start` which have a posting on the account if one is given
+pub fn get_entries(account: Option<LedgerAccount>, start: u64, limit: u64) -> JournalPage {
+    let limit = limit.clamp(1, MAX_JOURNAL_PAGE_SIZE) as usize;
+
+    crate::LEDGER.with(|ledger| {
+        let ledger = ledger.borrow();
+        let mut matching = ledger
+            .journal
+            .range(start..)
+            .map(|(_, entry)| entry)
+            .filter(|entry| {
+                account.as_ref().is_none_or(|account| {
+                    entry
+                        .postings
+                        .iter()
+                        .any(|posting| posting.account == *account)
+                })
+            });
+
+        let entries: Vec<JournalEntry> = matching.by_ref().take(limit).cloned().collect();
+        let next = matching.next().map(|entry| entry.id);
+
+        JournalPage { entries, next }
+    })
+}
+
+// the time the latest entry of a kind was posted at for each reference e.g the remittance of each nonce
+pub fn timestamps(kind: &EntryKind) -> HashMap<String, u64> {
+    crate::LEDGER.with(|ledger| {
+        ledger
+            .borrow()
+            .journal
+            .values()
+            .filter(|entry| entry.kind == *kind)
+            .filter_map(|entry| Some((entry.reference.clone()?, entry.timestamp)))
+            .collect()
+    })
+}
+
+pub fn verify(limit: u64) -> LedgerVerification {
+    crate::LEDGER.with(|ledger| ledger.borrow_mut().verify(limit))
+}
+
+// the ledger opened with the balances kept by the canister before it, one entry per asset of a data collection canister
+// a balance of the canister which doesn't match the balances of its users is balanced by its opening surplus or shortfall
+pub fn open(
+    available: AvailableBalanceStore,
+    withheld: &WithheldBalanceStore,
+    canister_balances: CanisterBalanceStore,
+    now: u64,
+) -> Result<Ledger, CcampError> {
+    let mut entries: BTreeMap<(Principal, String, String), Vec<Posting>> = BTreeMap::new();
+    let mut add = |posting: Posting| {
+        let key = (
+            posting.account.dc_canister(),
+            posting.chain.to_string(),
+            posting.token.to_string(),
+        );
+        entries.entry(key).or_default().push(posting);
+    };
+
+    for ((token, chain, dc_canister), account) in canister_balances {
+        add(debit(
+            &token,
+            &chain,
+            LedgerAccount::Pool { dc_canister },
+            account.balance,
+        ));
+    }
+    for ((token, chain, account, dc_canister), balance) in available {
+        let account = LedgerAccount::Available {
+            account,
+            dc_canister,
+        };
+        add(credit(&token, &chain, account, balance.balance));
+    }
+    for ((token, chain, account, dc_canister, _), withheld_account) in withheld {
+        let account = LedgerAccount::Withheld {
+            account: account.clone(),
+            dc_canister: *dc_canister,
+        };
+        add(credit(
+            token,
+            chain,
+            account,
+            withheld_account.balance.clone(),
+        ));
+    }
+
+    let mut ledger = Ledger::default();
+    for ((dc_canister, _, _), mut postings) in entries {
+        let (token, chain) = (postings[0].token.clone(), postings[0].chain.clone());
+        let sum = |side: Side| -> Nat {
+            postings
+                .iter()
+                .filter(|posting| posting.side == side)
+                .map(|posting| posting.amount.clone())
+                .fold(Nat::from(0u64), |sum, amount| sum + amount)
+        };
+        let (debits, credits) = (sum(Side::Debit), sum(Side::Credit));
+        if let Some(surplus) = lib::amount::checked_sub(&debits, &credits) {
+            let account = LedgerAccount::OpeningSurplus { dc_canister };
+            postings.push(credit(&token, &chain, account, surplus));
+        } else if let Some(shortfall) = lib::amount::checked_sub(&credits, &debits) {
+            let account = LedgerAccount::OpeningShortfall { dc_canister };
+            postings.push(debit(&token, &chain, account, shortfall));
+        }
+        ledger.post(EntryKind::Opening, None, postings, now)?;
+    }
+
+    Ok(ledger)
+}
+
+pub fn get_state() -> Ledger {
+    crate::LEDGER.with(|ledger| ledger.borrow().clone())
+}
+
+pub fn restore_state(state: Ledger) {
+    crate::LEDGER.with(|ledger| *ledger.borrow_mut() = state);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accounts() -> (Address, Chain, LedgerAccount, LedgerAccount, LedgerAccount) {
+        let dc_canister = Principal::from_text("bkyz2-fmaaa-aaaaa-qaaaq-cai").unwrap();
+        let account: Address = String::from("0x9C81E8F60a9B8743678F1b6Ae893Cc72c6Bc6840")
+            .try_into()
+            .unwrap();
+
+        (
+            String::from("0xB24a30A3971e4d9bf771BDc81435c25EA69A445c")
+                .try_into()
+                .unwrap(),
+            String::from("ethereum:5").try_into().unwrap(),
+            LedgerAccount::Pool { dc_canister },
+            LedgerAccount::Available {
+                account: account.clone(),
+                dc_canister,
+            },
+            LedgerAccount::Withheld {
+                account,
+                dc_canister,
+            },
+        )
+    }
+
+    #[test]
+    fn balances_follow_the_journal() {
+        let (token, chain, pool, available, withheld) = accounts();
+        let mut ledger = Ledger::default();
+        let balance = |ledger: &Ledger, account: &LedgerAccount| {
+            ledger.balance(&(token.clone(), chain.clone(), account.clone()))
+        };
+
+        // a deposit grows the pool along with the balance owed to the user
+        let deposit = vec![
+            debit(&token, &chain, pool.clone(), Nat::from(100u64)),
+            credit(&token, &chain, available.clone(), Nat::from(100u64)),
+        ];
+        ledger.post(EntryKind::Deposit, None, deposit, 0).unwrap();
+        let remittance = vec![
+            debit(&token, &chain, available.clone(), Nat::from(40u64)),
+            credit(&token, &chain, withheld.clone(), Nat::from(40u64)),
+        ];
+        ledger
+            .post(EntryKind::Remittance, None, remittance, 1)
+            .unwrap();
+        let withdrawal = vec![
+            debit(&token, &chain, withheld.clone(), Nat::from(40u64)),
+            credit(&token, &chain, pool.clone(), Nat::from(40u64)),
+        ];
+        ledger
+            .post(EntryKind::Withdrawal, None, withdrawal, 2)
+            .unwrap();
+
+        assert_eq!(balance(&ledger, &pool), Nat::from(60u64));
+        assert_eq!(balance(&ledger, &available), Nat::from(60u64));
+        assert_eq!(balance(&ledger, &withheld), Nat::from(0u64));
+        assert_eq!(ledger.journal.len(), 3);
+        assert!(ledger.is_consistent());
+
+        ledger.balances.insert(
+            (token.clone(), chain.clone(), available.clone()),
+            Nat::from(1_000u64),
+        );
+        assert!(!ledger.is_consistent());
+    }
+
+    #[test]
+    fn the_journal_is_verified_from_the_opening_balances() {
+        let (token, chain, pool, available, withheld) = accounts();
+        let mut ledger = Ledger::default();
+        let deposit = vec![
+            debit(&token, &chain, pool.clone(), Nat::from(100u64)),
+            credit(&token, &chain, available.clone(), Nat::from(100u64)),
+        ];
+        ledger.post(EntryKind::Deposit, None, deposit, 0).unwrap();
+        for timestamp in 1..3 {
+            let remittance = vec![
+                debit(&token, &chain, available.clone(), Nat::from(10u64)),
+                credit(&token, &chain, withheld.clone(), Nat::from(10u64)),
+            ];
+            ledger
+                .post(EntryKind::Remittance, None, remittance, timestamp)
+                .unwrap();
+        }
+
+        // the oldest entry is summed up in the opening balances, the ids keep counting
+        ledger.archive(2);
+        assert_eq!(
+            ledger.journal.keys().copied().collect::<Vec<u64>>(),
+            vec![1, 2]
+        );
+        assert_eq!(
+            ledger
+                .opening_balances
+                .get(&(token.clone(), chain.clone(), available.clone())),
+            Some(&Nat::from(100u64))
+        );
+
+        // the replay is continued by the next call until the end of the journal is reached
+        assert_eq!(
+            ledger.verify(1),
+            LedgerVerification {
+                next_id: 2,
+                consistent: None
+            }
+        );
+        assert_eq!(
+            ledger.verify(1),
+            LedgerVerification {
+                next_id: 1,
+                consistent: Some(true)
+            }
+        );
+
+        ledger.balances.insert(
+            (token.clone(), chain.clone(), withheld),
+            Nat::from(1_000u64),
+        );
+        assert_eq!(ledger.verify(10).consistent, Some(false));
+        assert!(!ledger.is_consistent());
+    }
+
+    #[test]
+    fn the_balances_kept_before_the_ledger_open_it() {
+        let (token, chain, pool, available, withheld) = accounts();
+        let dc_canister = pool.dc_canister();
+        let account: Address = String::from("0x9C81E8F60a9B8743678F1b6Ae893Cc72c6Bc6840")
+            .try_into()
+            .unwrap();
+        let canister_balances = |balance: u64| {
+            CanisterBalanceStore::from([(
+                (token.clone(), chain.clone(), dc_canister),
+                crate::remittance::Account {
+                    balance: Nat::from(balance),
+                },
+            )])
+        };
+        let available_balances = AvailableBalanceStore::from([(
+            (token.clone(), chain.clone(), account.clone(), dc_canister),
+            crate::remittance::Account {
+                balance: Nat::from(60u64),
+            },
+        )]);
+        let withheld_balances = WithheldBalanceStore::from([(
+            (
+                token.clone(),
+                chain.clone(),
+                account,
+                dc_canister,
+                Nat::from(40u64),
+            ),
+            crate::remittance::WithheldAccount {
+                balance: Nat::from(40u64),
+                ..Default::default()
+            },
+        )]);
+
+        let ledger = open(
+            available_balances.clone(),
+            &withheld_balances,
+            canister_balances(100),
+            7,
+        )
+        .unwrap();
+        let balance =
+            |account: LedgerAccount| ledger.balance(&(token.clone(), chain.clone(), account));
+        assert_eq!(balance(pool), Nat::from(100u64));
+        assert_eq!(balance(available), Nat::from(60u64));
+        assert_eq!(balance(withheld), Nat::from(40u64));
+        assert_eq!(ledger.journal.len(), 1);
+        assert_eq!(ledger.journal[&0].kind, EntryKind::Opening);
+        assert!(ledger.is_consistent());
+
+        // the difference with a canister balance which doesn't match its users is kept apart
+        let shortfall = LedgerAccount::OpeningShortfall { dc_canister };
+        let surplus = LedgerAccount::OpeningSurplus { dc_canister };
+        for (canister_balance, account, difference) in [(90, shortfall, 10), (104, surplus, 4)] {
+            let ledger = open(
+                available_balances.clone(),
+                &withheld_balances,
+                canister_balances(canister_balance),
+                7,
+            )
+            .unwrap();
+            let key = (token.clone(), chain.clone(), account);
+            assert_eq!(ledger.balance(&key), Nat::from(difference as u64));
+            assert!(ledger.is_consistent());
+        }
+    }
+
+    #[test]
+    fn invalid_entries_are_rejected() {
+        let (token, chain, pool, available, withheld) = accounts();
+        let mut ledger = Ledger::default();
+
+        let unbalanced = vec![
+            debit(&token, &chain, pool.clone(), Nat::from(100u64)),
+            credit(&token, &chain, available.clone(), Nat::from(90u64)),
+        ];
+        let asset = Asset {
+            token: token.clone(),
+            chain: chain.clone(),
+        };
+        assert_eq!(
+            ledger.post(EntryKind::Deposit, None, unbalanced, 0),
+            Err(CcampError::UnbalancedEntry {
+                asset: asset.clone(),
+                delta: Int::from(10)
+            })
+        );
+
+        // the debits of an asset can't be balanced by the credits of another one
+        let other_token: Address = String::from("0x1c7D4B196Cb0C7B01d743Fbc6116a902379C7238")
+            .try_into()
+            .unwrap();
+        let cross_asset = vec![
+            debit(&token, &chain, pool.clone(), Nat::from(100u64)),
+            credit(&other_token, &chain, available.clone(), Nat::from(100u64)),
+        ];
+        assert_eq!(
+            ledger.post(EntryKind::Deposit, None, cross_asset, 0),
+            Err(CcampError::UnbalancedEntry {
+                asset,
+                delta: Int::from(100)
+            })
+        );
+
+        // a balance can't become negative, the entry is rejected instead of wrapping around
+        let overdraft = vec![
+            debit(&token, &chain, available.clone(), Nat::from(1u64)),
+            credit(&token, &chain, withheld, Nat::from(1u64)),
+        ];
+        assert_eq!(
+            ledger.post(EntryKind::Remittance, None, overdraft, 0),
+            Err(CcampError::InsufficientBalance {
+                requested: Nat::from(1u64),
+                available: Nat::from(0u64)
+            })
+        );
+        assert_eq!(ledger, Ledger::default());
+    }
+}
@@ -1,34 +1,58 @@
-use candid::Principal;
-use ic_cdk::{caller, storage};
+use candid::{Nat, Principal};
+use ic_cdk::{api::time, caller};
 use ic_cdk_macros::*;
 
-use core::panic;
-use std::{cell::RefCell, collections::HashMap};
-use utils::vec_u8_to_string;
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+};
 
+mod deposits;
+mod ledger;
+mod migration;
 mod owner;
 mod random;
 mod remittance;
+mod solvency;
+mod tickets;
 mod utils;
 use lib::{
     self,
-    ethereum::{recover_address_from_eth_signature, sign_message}, remittance::{Config, Environment},
+    audit::{AuditFilter, AuditPage},
+    constants::REMITTANCE_TOPIC,
+    dc::TopicFilter,
+    outbox::{Acknowledgement, Delivery},
+    owner::{AccessControl, Role},
+    pause::{Operation, PauseRecord},
+    remittance::{Config, Environment, SigningMode},
+    signer::{Ed25519Signer, Signers, ThresholdEcdsaSigner, ThresholdEd25519Signer},
+    CcampError, EventIdentity,
 };
 
-const REMITTANCE_EVENT: &str = "REMITTANCE";
 thread_local! {
-    static REMITTANCE: RefCell<remittance::AvailableBalanceStore> = RefCell::default();
-    static WITHHELD_REMITTANCE: RefCell<remittance::WithheldBalanceStore> = RefCell::default();
-    static WITHHELD_AMOUNTS: RefCell<remittance::WithheldAmountsStore> = RefCell::default();
+    // the balances of the users and data collection canisters along with the journal they are derived from
+    static LEDGER: RefCell<ledger::Ledger> = RefCell::default();
+    // the remittances which weren't withdrawn or cancelled yet keyed by their nonce
+    static TICKETS: RefCell<tickets::WithdrawalTicketStore> = RefCell::default();
 
     static IS_PDC_CANISTER: RefCell<HashMap<Principal, bool>> = RefCell::default();
 
     static DC_CANISTERS: RefCell<Vec<Principal>> = RefCell::default();
+    // the sequence number of the last batch delivered by the outbox of each publisher
+    static LAST_APPLIED_SEQUENCES: RefCell<BTreeMap<Principal, u64>> = RefCell::default();
+    // the deposits which are held until their block is final
+    static PENDING_DEPOSITS: RefCell<deposits::PendingDepositStore> = RefCell::default();
 
     static REMITTANCE_RECIEPTS: RefCell<remittance::RemittanceRecieptsStore> = RefCell::default();
-    static CANISTER_BALANCE: RefCell<remittance::CanisterBalanceStore> = RefCell::default();
 
     static CONFIG: RefCell<Config> = RefCell::default();
+    static SIGNING_MODE: RefCell<SigningMode> = RefCell::default();
+
+    static PROOF_NONCES: RefCell<remittance::ProofNoncesStore> = RefCell::default();
+    // proofs which only sign the amount, only accepted by canisters upgraded while clients move to the new format
+    static ALLOW_LEGACY_PROOFS: RefCell<bool> = const { RefCell::new(false) };
+    // whether the pools cover the balances of the users, checked by a timer
+    static SOLVENCY: RefCell<solvency::SolvencyState> = RefCell::default();
 }
 
 // ----------------------------------- init hooks
@@ -36,6 +60,8 @@ thread_local! {
 fn init(env_opt: Option<Environment>) {
     lib::owner::init_owner();
     random::init_ic_rand();
+    lib::chain::init_default_chains();
+    solvency::start_reconciliation_timer();
 
     // save the environment this is running in
     if let Some(env) = env_opt {
@@ -47,27 +73,217 @@ fn init(env_opt: Option<Environment>) {
 }
 // ----------------------------------- init hooks
 
+// the signers of the withdrawal authorizations
+// they use the threshold keys configured for the environment of the canister
+fn signers() -> Signers<ThresholdEcdsaSigner, ThresholdEd25519Signer> {
+    let config = CONFIG.with(|config| config.borrow().clone());
+
+    Signers {
+        ecdsa: ThresholdEcdsaSigner::new(config.clone()),
+        ed25519: ThresholdEd25519Signer::new(config),
+    }
+}
+
 // get deployer of contract
 #[query]
 fn owner() -> String {
     lib::owner::get_owner()
 }
 
+#[query]
+fn get_access_control() -> AccessControl {
+    lib::owner::get_access_control()
+}
+
+#[query]
+fn get_roles(principal: Principal) -> Vec<Role> {
+    lib::owner::get_roles(principal)
+}
+
+#[update]
+fn grant_role(role: Role, principal: Principal) -> Result<(), CcampError> {
+    lib::owner::grant_role(role, principal)
+}
+
+#[update]
+fn revoke_role(role: Role, principal: Principal) -> Result<(), CcampError> {
+    lib::owner::revoke_role(role, principal)
+}
+
+#[update]
+fn propose_owner(new_owner: Option<Principal>) -> Result<(), CcampError> {
+    lib::owner::propose_owner(new_owner)
+}
+
+#[update]
+fn accept_ownership() -> Result<(), CcampError> {
+    lib::owner::accept_ownership()
+}
+
+// the privileged calls made to this canister, only readable by auditors
+#[query]
+fn get_audit_log(
+    filter: Option<AuditFilter>,
+    start: u64,
+    limit: u64,
+) -> Result<AuditPage, CcampError> {
+    lib::audit::get_page(filter, start, limit)
+}
+
+// stop an operation of this canister e.g when a signer or publisher is compromised
+#[update]
+fn pause(operation: Operation, reason: String) -> Result<(), CcampError> {
+    lib::pause::pause(operation, reason)
+}
+
+#[update]
+fn unpause(operation: Operation, reason: String) -> Result<(), CcampError> {
+    lib::pause::unpause(operation, reason)
+}
+
+#[query]
+fn get_paused_operations() -> Vec<Operation> {
+    lib::pause::get_paused()
+}
+
+#[query]
+fn get_pause_history() -> Vec<PauseRecord> {
+    lib::pause::get_history()
+}
+
 // @dev test function
 #[query]
 fn name() -> String {
-    format!("remittance canister")
+    "remittance canister".to_string()
+}
+
+// register a new chain or update the metadata of an already registered chain
+#[update]
+fn add_chain(chain: String, metadata: lib::chain::ChainMetadata) -> Result<(), CcampError> {
+    let result = lib::owner::ensure_role(Role::Admin).and_then(|_| {
+        let parsed_chain =
+            lib::Chain::try_from(chain.clone()).map_err(|_| CcampError::InvalidChain {
+                chain: chain.clone(),
+            })?;
+
+        lib::chain::register_chain(parsed_chain, metadata.clone());
+        Ok(())
+    });
+    lib::audit::record("add_chain", &(&chain, &metadata), &result);
+
+    result
+}
+
+#[update]
+fn remove_chain(chain: String) -> Result<(), CcampError> {
+    let result = lib::owner::ensure_role(Role::Admin).and_then(|_| {
+        let chain = lib::chain::resolve_chain(chain.clone())?;
+
+        lib::chain::deregister_chain(&chain);
+        Ok(())
+    });
+    lib::audit::record("remove_chain", &(&chain,), &result);
+
+    result
+}
+
+#[query]
+fn get_chains() -> Vec<(lib::Chain, lib::chain::ChainMetadata)> {
+    lib::chain::get_registered_chains()
+}
+
+// choose how the withdrawal authorizations returned by `remit` are signed
+// pending withdrawals keep the signature they were issued with so the mode should be set before any is requested
+#[update]
+fn set_signing_mode(signing_mode: SigningMode) -> Result<(), CcampError> {
+    let result = lib::owner::ensure_role(Role::Admin).map(|_| {
+        SIGNING_MODE.with(|mode| *mode.borrow_mut() = signing_mode.clone());
+    });
+    lib::audit::record("set_signing_mode", &(&signing_mode,), &result);
+
+    result
+}
+
+#[query]
+fn get_signing_mode() -> SigningMode {
+    SIGNING_MODE.with(|mode| mode.borrow().clone())
+}
+
+// enable or disable `remit` proofs which only sign the amount
+#[update]
+fn set_allow_legacy_proofs(allow: bool) -> Result<(), CcampError> {
+    let result = lib::owner::ensure_role(Role::Admin).map(|_| {
+        ALLOW_LEGACY_PROOFS.with(|allow_legacy_proofs| *allow_legacy_proofs.borrow_mut() = allow);
+    });
+    lib::audit::record("set_allow_legacy_proofs", &(allow,), &result);
+
+    result
+}
+
+#[query]
+fn get_allow_legacy_proofs() -> bool {
+    ALLOW_LEGACY_PROOFS.with(|allow_legacy_proofs| *allow_legacy_proofs.borrow())
+}
+
+// the nonce the next `remit` proof of this account has to be signed with
+#[query]
+fn get_proof_nonce(account: String) -> Result<u64, CcampError> {
+    let account = remittance::parse_address(account)?;
+
+    Ok(remittance::get_proof_nonce(&account))
 }
 
 // we call this method, with the id of the data_collection canister
 // this then subscribes the remittance canister to "REMITTANCE" events from the data cannister
+// the optional filter restricts the events recieved e.g to the chains this canister handles
+#[update]
+async fn subscribe_to_dc(
+    canister_id: Principal,
+    filter: Option<TopicFilter>,
+) -> Result<(), CcampError> {
+    let result = match lib::owner::ensure_role(Role::PublisherManager) {
+        Ok(_) => subscribe(canister_id, filter.clone()).await,
+        Err(error) => Err(error),
+    };
+    lib::audit::record("subscribe_to_dc", &(canister_id, &filter), &result);
+
+    result
+}
+
+// we call this method to subscribe to a pdc
+// it can only be called by the address who deployed the contract
 #[update]
-async fn subscribe_to_dc(canister_id: Principal) {
-    lib::owner::only_owner();
+async fn subscribe_to_pdc(
+    pdc_canister_id: Principal,
+    filter: Option<TopicFilter>,
+) -> Result<(), CcampError> {
+    let result = match lib::owner::ensure_role(Role::PublisherManager) {
+        Ok(_) => subscribe(pdc_canister_id, filter.clone()).await,
+        Err(error) => Err(error),
+    };
+    if result.is_ok() {
+        IS_PDC_CANISTER.with(|is_pdc_canister| {
+            is_pdc_canister.borrow_mut().insert(pdc_canister_id, true);
+        });
+    }
+    lib::audit::record("subscribe_to_pdc", &(pdc_canister_id, &filter), &result);
+
+    result
+}
+
+async fn subscribe(canister_id: Principal, filter: Option<TopicFilter>) -> Result<(), CcampError> {
     let subscriber = lib::Subscriber {
-        topic: REMITTANCE_EVENT.to_string(),
+        topic: REMITTANCE_TOPIC.to_string(),
+        filter,
     };
-    let _call_result: Result<(), _> = ic_cdk::call(canister_id, "subscribe", (subscriber,)).await;
+    ic_cdk::call(canister_id, "subscribe", (subscriber,))
+        .await
+        .map_err(|(code, message)| CcampError::CallFailed {
+            method: String::from("subscribe"),
+            reason: format!("{:?}:{}", code, message),
+        })
+        .and_then(|(subscribe_response,): (Result<(), CcampError>,)| subscribe_response)?;
+
     // update the list of all the publishers subscribed to while avoiding duplicates
     DC_CANISTERS.with(|dc_canister| {
         let mut borrowed_canister = dc_canister.borrow_mut();
@@ -75,17 +291,8 @@ async fn subscribe_to_dc(canister_id: Principal) {
             borrowed_canister.push(canister_id)
         }
     });
-}
 
-// we call this method to subscribe to a pdc
-// it can only be called by the address who deployed the contract
-#[update]
-async fn subscribe_to_pdc(pdc_canister_id: Principal) {
-    lib::owner::only_owner();
-    subscribe_to_dc(pdc_canister_id).await;
-    IS_PDC_CANISTER.with(|is_pdc_canister| {
-        is_pdc_canister.borrow_mut().insert(pdc_canister_id, true);
-    });
+    Ok(())
 }
 
 // this is an external function which is going to be called by  the data collection canister
@@ -94,67 +301,164 @@ async fn subscribe_to_pdc(pdc_canister_id: Principal) {
 fn update_remittance(
     new_remittances: Vec<lib::DataModel>,
     dc_canister: Principal,
-) -> Result<(), String> {
-    owner::only_publisher();
+) -> Result<(), CcampError> {
+    lib::pause::ensure_not_paused(Operation::UpdateRemittance)?;
+    owner::only_publisher()?;
+
+    apply_remittances(new_remittances, dc_canister)
+}
+
+// called by the protocol data collection canisters to deliver the batches of their outbox
+// a batch is only applied once, delivering it again only acknowledges it
+#[update]
+fn deliver_remittance(delivery: Delivery) -> Result<Acknowledgement, CcampError> {
+    lib::pause::ensure_not_paused(Operation::UpdateRemittance)?;
+    owner::only_publisher()?;
+    let publisher = caller();
+    let last_applied = get_last_applied_sequence(publisher);
+
+    if !lib::outbox::should_apply(last_applied, delivery.sequence)? {
+        return Ok(Acknowledgement {
+            sequence: last_applied,
+            rejection: None,
+        });
+    }
+
+    // a batch which is never going to be applied e.g because of a duplicate or a malformed event
+    // is acknowledged along with the reason instead of being retried
+    let rejection = match apply_remittances(delivery.events, delivery.dc_canister) {
+        Ok(()) => None,
+        Err(error) if lib::outbox::is_rejection(&error) => Some(error),
+        Err(error) => return Err(error),
+    };
+    LAST_APPLIED_SEQUENCES
+        .with(|sequences| sequences.borrow_mut().insert(publisher, delivery.sequence));
+
+    Ok(Acknowledgement {
+        sequence: delivery.sequence,
+        rejection,
+    })
+}
+
+// called by the protocol data collection canisters as the blocks reported by their publishers get deeper
+// the pending deposits of a block are credited once it reaches the finality depth of its chain
+#[update]
+fn confirm_block(
+    chain: String,
+    block_hash: String,
+    confirmations: u64,
+) -> Result<Vec<EventIdentity>, CcampError> {
+    lib::pause::ensure_not_paused(Operation::UpdateRemittance)?;
+    owner::only_block_reporter()?;
+    let chain = lib::chain::resolve_chain(chain)?;
+    let block_hash = parse_block_hash(block_hash)?;
+
+    deposits::confirm_block(chain, block_hash, confirmations, time())
+}
+
+// reverse the pending deposits of a block which is no longer part of its chain because of a reorg
+#[update]
+fn orphan_block(chain: String, block_hash: String) -> Result<Vec<EventIdentity>, CcampError> {
+    let result = lib::pause::ensure_not_paused(Operation::UpdateRemittance)
+        .and_then(|_| owner::only_block_reporter())
+        .and_then(|_| {
+            let chain = lib::chain::resolve_chain(chain.clone())?;
+            let block_hash = parse_block_hash(block_hash.clone())?;
+
+            deposits::orphan_block(chain, block_hash)
+        });
+    lib::audit::record("orphan_block", &(&chain, &block_hash), &result);
+
+    result
+}
+
+fn parse_block_hash(block_hash: String) -> Result<String, CcampError> {
+    lib::normalize_hash(block_hash).map_err(|reason| CcampError::InvalidEvent {
+        reason: format!("block_hash:{}", reason),
+    })
+}
+
+#[query]
+fn is_event_processed(identity: EventIdentity) -> bool {
+    lib::dedup::is_processed(&identity)
+}
+
+#[query]
+fn get_last_applied_sequence(publisher: Principal) -> u64 {
+    LAST_APPLIED_SEQUENCES.with(|sequences| {
+        sequences
+            .borrow()
+            .get(&publisher)
+            .copied()
+            .unwrap_or_default()
+    })
+}
+
+fn apply_remittances(
+    new_remittances: Vec<lib::DataModel>,
+    dc_canister: Principal,
+) -> Result<(), CcampError> {
     let is_pdc =
         IS_PDC_CANISTER.with(|is_pdc_canister| is_pdc_canister.borrow().contains_key(&caller()));
 
     // add checks here to make sure that the input data is error free
     // if there is any error, return it to the calling dc canister
-    if let Err(text) = remittance::validate_remittance_data(is_pdc, &new_remittances, dc_canister) {
-        return Err(text);
+    remittance::validate_remittance_data(is_pdc, &new_remittances, dc_canister)?;
+    // an on-chain event is only credited once no matter how many times it is published
+    // the identities are rolled back along with the balances if applying the batch traps
+    lib::dedup::ensure_unique(&new_remittances)?;
+    lib::dedup::record(&new_remittances);
+
+    let now = time();
+    // the adjustments of the batch are balanced against each other so they are recorded together
+    let (adjustments, other_remittances): (Vec<lib::DataModel>, Vec<lib::DataModel>) =
+        new_remittances
+            .into_iter()
+            .partition(|new_remittance| new_remittance.action == lib::Action::Adjust);
+    if !adjustments.is_empty() {
+        if let Err(error) = remittance::record_adjustments(&adjustments, dc_canister, now) {
+            ic_cdk::trap(&error.to_string());
+        }
     }
 
     // process each 'MESSAGE' sent to the DC canister based on
     // the request type and if the canister calling the method is a request canister
-    for new_remittance in new_remittances {
-        // leave it named as underscore until we have implemented a use for the response
-
-        let _: Result<(), String> = match new_remittance.action.clone() {
-            lib::Action::Adjust => {
-                remittance::update_balance(&new_remittance, dc_canister);
-                Ok(())
-            }
-            lib::Action::Deposit => {
-                remittance::update_balance(&new_remittance, dc_canister);
-                // upon deposit, we increment the canister's balance of that token
-                remittance::update_canister_balance(
-                    new_remittance.token,
-                    new_remittance.chain,
-                    dc_canister,
-                    new_remittance.amount,
-                );
-                Ok(())
-            }
-            lib::Action::Withdraw => {
-                remittance::confirm_withdrawal(
-                    new_remittance.token.to_string(),
-                    new_remittance.chain.to_string(),
-                    new_remittance.account.to_string(),
-                    new_remittance.amount.abs() as u64,
-                    dc_canister,
-                );
-                // upon withdrawal we can remove the withdrawn amount from the canister's pool for that amount
-                remittance::update_canister_balance(
-                    new_remittance.token,
-                    new_remittance.chain,
-                    dc_canister,
-                    -new_remittance.amount,
-                );
-                Ok(())
-            }
-            lib::Action::CancelWithdraw => {
-                remittance::cancel_withdrawal(
-                    new_remittance.token.to_string(),
-                    new_remittance.chain.to_string(),
-                    new_remittance.account.to_string(),
-                    new_remittance.amount.abs() as u64,
-                    dc_canister,
-                );
-                Ok(())
-            }
+    for new_remittance in other_remittances {
+        let update_response: Result<(), CcampError> = match new_remittance.action.clone() {
+            // recorded above along with the other adjustments of the batch
+            lib::Action::Adjust => Ok(()),
+            // deposits are only credited once they are final
+            lib::Action::Deposit => deposits::receive(new_remittance, dc_canister, now),
+            // the withdrawn amount leaves the canister's pool along with the withheld balance
+            // the nonce of every withdrawal and cancellation was checked along with the batch
+            lib::Action::Withdraw => remittance::confirm_withdrawal(
+                new_remittance.nonce.unwrap_or_default(),
+                new_remittance.token.to_string(),
+                new_remittance.chain.to_string(),
+                new_remittance.account.to_string(),
+                lib::amount::abs(&new_remittance.amount),
+                dc_canister,
+                now,
+            ),
+            lib::Action::CancelWithdraw => remittance::cancel_withdrawal(
+                new_remittance.nonce.unwrap_or_default(),
+                new_remittance.token.to_string(),
+                new_remittance.chain.to_string(),
+                new_remittance.account.to_string(),
+                lib::amount::abs(&new_remittance.amount),
+                dc_canister,
+                now,
+            ),
         };
+
+        // the batch has already been validated so a failure here means the balances are inconsistent
+        // trap so that every update made by this batch is rolled back
+        if let Err(error) = update_response {
+            ic_cdk::trap(&error.to_string());
+        }
     }
+    // confirmed and cancelled withdrawals might have been the last ones signed by a previous key
+    remittance::retire_unused_keys();
 
     Ok(())
 }
@@ -166,134 +470,22 @@ async fn remit(
     chain: String,
     account: String,
     dc_canister: Principal,
-    amount: u64,
+    amount: Nat,
     proof: String,
-) -> remittance::RemittanceReply {
-    // make sure the 'proof' is a signature of the amount by the provided address
-    let _derived_address =
-        recover_address_from_eth_signature(proof, format!("{amount}")).expect("INVALID_SIGNATURE");
-
-    // make sure the signature belongs to the provided account
-    assert!(
-        _derived_address == account.to_lowercase(),
-        "INVALID_SIGNATURE"
-    );
-    // make sure the amount being remitted is none zero
-    assert!(amount > 0, "AMOUNT < 0");
-
-    // generate key values
-    let chain: lib::Chain = chain.try_into().unwrap();
-    let token: lib::Wallet = token.try_into().unwrap();
-    let account: lib::Wallet = account.try_into().unwrap();
-
-    let hash_key = (
-        token.clone(),
-        chain.clone(),
-        account.clone(),
-        dc_canister.clone(),
-    );
-
-    // check if there is a withheld 'balance' for this particular amount
-    let withheld_balance = remittance::get_remitted_balance(
-        token.clone(),
-        chain.clone(),
-        account.clone(),
-        dc_canister.clone(),
+    proof_parameters: Option<remittance::ProofParameters>,
+) -> Result<remittance::RemittanceReply, CcampError> {
+    lib::pause::ensure_not_paused(Operation::Remit)?;
+    let request = remittance::RemitRequest {
+        token,
+        chain,
+        account,
+        dc_canister,
         amount,
-    );
-
-    let response: remittance::RemittanceReply;
-    // if the amount exists in a withheld map then return the cached signature and nonce
-    if withheld_balance.balance == amount {
-        let message_hash = remittance::hash_remittance_parameters(
-            withheld_balance.nonce,
-            amount,
-            &account.to_string(),
-            &chain.to_string(),
-            &dc_canister.to_string(),
-            &token.to_string(),
-        );
-
-        response = remittance::RemittanceReply {
-            hash: vec_u8_to_string(&message_hash),
-            signature: withheld_balance.signature.clone(),
-            nonce: withheld_balance.nonce,
-            amount,
-        };
-    } else {
-        let nonce = random::get_random_number();
-        let message_hash = remittance::hash_remittance_parameters(
-            nonce,
-            amount,
-            &account.to_string(),
-            &chain.to_string(),
-            &dc_canister.to_string(),
-            &token.to_string(),
-        );
-        let balance = get_available_balance(
-            token.to_string(),
-            chain.to_string(),
-            account.to_string(),
-            dc_canister.clone(),
-        )
-        .balance;
-
-        // make sure this user actually has enough funds to withdraw
-        if amount > balance {
-            panic!("REMIT_AMOUNT:{amount} > AVAILABLE_BALANCE:{balance}")
-        }
-
-        // generate a signature for these parameters
-        let config_store = CONFIG.with(|store| store.borrow().clone());
-        let signature_reply = sign_message(&message_hash, &config_store)
-            .await
-            .expect("ERROR_SIGNING_MESSAGE");
-        let signature_string = format!("0x{}", signature_reply.signature_hex);
-
-        // deduct amount to remit from main balance
-        REMITTANCE.with(|remittance| {
-            if let Some(existing_data) = remittance.borrow_mut().get_mut(&hash_key) {
-                existing_data.balance = existing_data.balance - amount;
-            }
-        });
-        // add amount to mapping (token, chain, recipient) => [amount_1, amount_2, amount_3]
-        // to keep track of individual amounts remitted per (token, chain, recipient) combination
-        WITHHELD_AMOUNTS.with(|withheld_amount| {
-            // Append value to existing entry or create new entry
-            withheld_amount
-                .borrow_mut()
-                .entry(hash_key.clone())
-                .or_insert(Vec::new())
-                .push(amount);
-        });
-        // update the withheld balance of the said user and generate a new signature for it
-        WITHHELD_REMITTANCE.with(|withheld| {
-            let mut withheld_remittance_store = withheld.borrow_mut();
-            withheld_remittance_store.insert(
-                (
-                    token.clone(),
-                    chain.clone(),
-                    account.clone(),
-                    dc_canister.clone(),
-                    amount,
-                ),
-                remittance::WithheldAccount {
-                    balance: amount,
-                    signature: signature_string.clone(),
-                    nonce,
-                },
-            );
-        });
-        // create response object
-        response = remittance::RemittanceReply {
-            hash: format!("0x{}", vec_u8_to_string(&message_hash)),
-            signature: signature_string.clone(),
-            nonce,
-            amount,
-        };
-    }
+        proof,
+        proof_parameters,
+    };
 
-    response
+    remittance::remit(&signers(), time() / 1_000_000_000, request).await
 }
 
 // use this function to get the un remitted balance of the 'account' provided
@@ -304,16 +496,44 @@ fn get_available_balance(
     chain: String,
     account: String,
     dc_canister: Principal,
-) -> remittance::Account {
-    let chain: lib::Chain = chain.try_into().unwrap();
-    let token: lib::Wallet = token.try_into().unwrap();
-    let account: lib::Wallet = account.try_into().unwrap();
+) -> Result<remittance::Account, CcampError> {
     // validate the address and the chain
+    let chain = lib::chain::resolve_chain(chain)?;
+    let token = remittance::parse_address(token)?;
+    let account = remittance::parse_address(account)?;
 
     // get available balance for this key
-    let amount = remittance::get_available_balance(token, chain, account, dc_canister);
+    Ok(remittance::get_available_balance(
+        token,
+        chain,
+        account,
+        dc_canister,
+    ))
+}
+
+// the deposits of the 'account' provided which can't be remitted until their block is final
+#[query]
+fn get_pending_balance(
+    token: String,
+    chain: String,
+    account: String,
+    dc_canister: Principal,
+) -> Result<remittance::Account, CcampError> {
+    let chain = lib::chain::resolve_chain(chain)?;
+    let token = remittance::parse_address(token)?;
+    let account = remittance::parse_address(account)?;
+
+    Ok(deposits::get_pending_balance(
+        token,
+        chain,
+        account,
+        dc_canister,
+    ))
+}
 
-    amount
+#[query]
+fn get_pending_deposits() -> Vec<deposits::PendingDeposit> {
+    deposits::get_pending_deposits()
 }
 
 #[query]
@@ -321,15 +541,94 @@ fn get_canister_balance(
     token: String,
     chain: String,
     dc_canister: Principal,
-) -> remittance::Account {
-    let chain: lib::Chain = chain.try_into().unwrap();
-    let token: lib::Wallet = token.try_into().unwrap();
+) -> Result<remittance::Account, CcampError> {
     // validate the address and the chain
+    let chain = lib::chain::resolve_chain(chain)?;
+    let token = remittance::parse_address(token)?;
 
     // get available balance for this key
-    let amount = remittance::get_canister_balance(token, chain, dc_canister);
+    Ok(remittance::get_canister_balance(token, chain, dc_canister))
+}
+
+// the entries of the journal the balances are derived from, only readable by auditors
+// only the entries with a posting on the account are returned if one is given
+#[query]
+fn get_journal(
+    account: Option<ledger::LedgerAccount>,
+    start: u64,
+    limit: u64,
+) -> Result<ledger::JournalPage, CcampError> {
+    lib::owner::ensure_role(Role::Auditor)?;
+
+    Ok(ledger::get_entries(account, start, limit))
+}
+
+// replay at most `limit` entries of the journal from where the previous call stopped
+// once its end is reached, whether replaying it from the opening balances results in the current balances
+#[update]
+fn verify_ledger(limit: u64) -> Result<ledger::LedgerVerification, CcampError> {
+    lib::owner::ensure_role(Role::Auditor)?;
+
+    Ok(ledger::verify(limit))
+}
+
+// check that the pool of every asset covers the balances of its users
+#[query]
+fn reconcile() -> solvency::ReconciliationReport {
+    solvency::reconcile(time())
+}
+
+// record the balance of a data collection canister read from the Locker of a chain
+// the reconciliation compares it with the pool and the pending deposits of the data collection canister
+#[update]
+fn report_locker_balance(
+    token: String,
+    chain: String,
+    dc_canister: Principal,
+    balance: Nat,
+) -> Result<(), CcampError> {
+    let result = lib::owner::ensure_role(Role::Operator).and_then(|_| {
+        let asset = lib::Asset {
+            token: remittance::parse_address(token.clone())?,
+            chain: lib::chain::resolve_chain(chain.clone())?,
+        };
+
+        solvency::report_locker_balance(asset, dc_canister, balance.clone(), time());
+        Ok(())
+    });
+    lib::audit::record(
+        "report_locker_balance",
+        &(&token, &chain, dc_canister, &balance),
+        &result,
+    );
+
+    result
+}
+
+#[query]
+fn get_locker_balances() -> Vec<(lib::Asset, Principal, solvency::LockerBalance)> {
+    solvency::get_locker_balances()
+}
+
+// the report of the last reconciliation made by the timer
+#[query]
+fn get_last_reconciliation() -> Option<solvency::ReconciliationReport> {
+    solvency::get_last_report()
+}
+
+// pause `remit` once the timer finds a discrepancy
+#[update]
+fn set_auto_pause_remit(auto_pause: bool) -> Result<(), CcampError> {
+    let result =
+        lib::owner::ensure_role(Role::Pauser).map(|_| solvency::set_auto_pause(auto_pause));
+    lib::audit::record("set_auto_pause_remit", &(auto_pause,), &result);
 
-    amount
+    result
+}
+
+#[query]
+fn get_auto_pause_remit() -> bool {
+    solvency::get_auto_pause()
 }
 
 // the users use this function to get the withheld balance
@@ -341,128 +640,252 @@ fn get_withheld_balance(
     chain: String,
     account: String,
     dc_canister: Principal,
-) -> remittance::Account {
-    let chain: lib::Chain = chain.try_into().unwrap();
-    let token: lib::Wallet = token.try_into().unwrap();
-    let account: lib::Wallet = account.try_into().unwrap();
+) -> Result<remittance::Account, CcampError> {
+    let chain = lib::chain::resolve_chain(chain)?;
+    let token = remittance::parse_address(token)?;
+    let account = remittance::parse_address(account)?;
+
+    Ok(remittance::get_withheld_balance(
+        token,
+        chain,
+        account,
+        dc_canister,
+    ))
+}
 
-    let existing_key = (token.clone(), chain.clone(), account.clone(), dc_canister);
+// the remittance signed with the nonce, as long as it wasn't withdrawn or cancelled
+#[query]
+fn get_withdrawal_ticket(nonce: u64) -> Option<tickets::WithdrawalTicket> {
+    tickets::get(nonce)
+}
 
-    // sum up all the amounts in the withheld_amount value of this key
-    let sum = WITHHELD_AMOUNTS.with(|withheld_amount| {
-        let withheld_amount = withheld_amount.borrow();
-        let values = withheld_amount.get(&existing_key);
+// the remittances of the account which weren't withdrawn or cancelled yet
+#[query]
+fn get_withdrawal_tickets(account: String) -> Result<Vec<tickets::WithdrawalTicket>, CcampError> {
+    let account = remittance::parse_address(account)?;
 
-        match values {
-            Some(vec) => vec.iter().sum::<u64>(),
-            None => 0,
-        }
-    });
+    Ok(tickets::get_tickets(&account))
+}
 
-    remittance::Account { balance: sum }
+// the reciept of a withdrawal confirmed on chain, none if it wasn't confirmed
+#[query]
+fn get_reciept(dc_canister: Principal, nonce: u64) -> Option<remittance::RemittanceReciept> {
+    let key = (dc_canister, nonce);
+    REMITTANCE_RECIEPTS.with(|remittance_reciepts| remittance_reciepts.borrow().get(&key).cloned())
+}
+
+// get the public key of the canister for a chain and data collection canister
+// `version` defaults to the current key version, the legacy version 0 has the same key for every scope
+// the key is derived through the management canister once and then served from the cache
+#[update]
+async fn public_key(
+    chain: Option<String>,
+    dc_canister: Option<Principal>,
+    version: Option<u32>,
+) -> Result<lib::ecdsa::PublicKeyReply, CcampError> {
+    let key_id = resolve_key_id(chain, dc_canister, version)?;
+    let config = CONFIG.with(|config| config.borrow().clone());
+
+    let cached_public_key = lib::ecdsa::cached_public_key(&config, key_id.derivation_path())
+        .await
+        .map_err(|reason| CcampError::CallFailed {
+            method: String::from("ecdsa_public_key"),
+            reason,
+        })?;
+
+    Ok(cached_public_key.into())
 }
 
+// get a public key which has already been derived without any inter canister call
 #[query]
-async fn get_reciept(dc_canister: Principal, nonce: u64) -> remittance::RemittanceReciept {
-    let key = (dc_canister.clone(), nonce.clone());
-    REMITTANCE_RECIEPTS.with(|remittance_reciepts| {
-        remittance_reciepts
-            .borrow()
-            .get(&key)
-            .expect("RECIEPT_NOT_FOUND")
-            .clone()
+fn get_public_key(
+    chain: Option<String>,
+    dc_canister: Option<Principal>,
+    version: Option<u32>,
+) -> Result<lib::ecdsa::PublicKeyReply, CcampError> {
+    let key_id = resolve_key_id(chain, dc_canister, version)?;
+    let config = CONFIG.with(|config| config.borrow().clone());
+
+    lib::ecdsa::get_cached_public_key(&config, &key_id.derivation_path())
+        .map(|cached_public_key| cached_public_key.into())
+        .ok_or(CcampError::PublicKeyNotCached {
+            version: key_id.version,
+        })
+}
+
+// get the ed25519 public key of the canister for a chain such as solana
+#[update]
+async fn ed25519_public_key(
+    chain: Option<String>,
+    dc_canister: Option<Principal>,
+    version: Option<u32>,
+) -> Result<lib::schnorr::Ed25519PublicKeyReply, CcampError> {
+    let key_id = resolve_key_id(chain, dc_canister, version)?;
+
+    let public_key = signers()
+        .ed25519
+        .derive(key_id.derivation_path())
+        .public_key()
+        .await
+        .map_err(|reason| CcampError::CallFailed {
+            method: String::from("schnorr_public_key"),
+            reason,
+        })?;
+
+    Ok(lib::schnorr::Ed25519PublicKeyReply {
+        public_key: hex::encode(&public_key),
+        address: lib::Address::Solana(public_key).to_string(),
     })
 }
 
+fn resolve_key_id(
+    chain: Option<String>,
+    dc_canister: Option<Principal>,
+    version: Option<u32>,
+) -> Result<lib::keys::KeyId, CcampError> {
+    let chain = chain.map(lib::chain::resolve_chain).transpose()?;
+    let version = version.unwrap_or_else(lib::keys::current_version);
+
+    lib::keys::key_id(version, chain.as_ref(), dc_canister.as_ref())
+}
+
+// create a new version of the key, which is pending until it is activated
+// the addresses of the new version have to be added as signers of the Locker contracts in the meantime
 #[update]
-async fn public_key() -> lib::ecdsa::PublicKeyReply {
-    let config = crate::CONFIG.with(|c| c.borrow().clone());
+fn rotate_key(dc_scoped: bool) -> Result<u32, CcampError> {
+    let result =
+        lib::owner::ensure_role(Role::Admin).and_then(|_| lib::keys::rotate(dc_scoped, time()));
+    lib::audit::record("rotate_key", &(dc_scoped,), &result);
 
-    let request = lib::ecdsa::ECDSAPublicKey {
-        canister_id: None,
-        derivation_path: vec![],
-        key_id: config.key.to_key_id(),
-    };
+    result
+}
 
-    let (res,): (lib::ecdsa::ECDSAPublicKeyReply,) = ic_cdk::call(
-        Principal::management_canister(),
-        "ecdsa_public_key",
-        (request,),
-    )
-    .await
-    .map_err(|e| format!("ECDSA_PUBLIC_KEY_FAILED {}", e.1))
-    .unwrap();
+// start signing with the pending version of the key
+// the previous versions stay valid until the withdrawals they signed are confirmed or cancelled
+#[update]
+fn activate_key(version: u32) -> Result<(), CcampError> {
+    let result = lib::owner::ensure_role(Role::Admin).and_then(|_| lib::keys::activate(version));
+    lib::audit::record("activate_key", &(version,), &result);
 
-    let address = lib::ethereum::get_address_from_public_key(res.public_key.clone())
-        .expect("INVALID_PUBLIC_KEY");
+    result
+}
 
-    lib::ecdsa::PublicKeyReply {
-        sec1_pk: hex::encode(res.public_key),
-        etherum_pk: address,
-    }
+#[query]
+fn get_key_registry() -> lib::keys::KeyRegistry {
+    lib::keys::get_registry()
 }
 
 // --------------------------- upgrade hooks ------------------------- //
 #[pre_upgrade]
 fn pre_upgrade() {
     // clone all important variables
-    let cloned_available_balance_store = REMITTANCE.with(|store| store.borrow().clone());
-    let cloned_witheld_balance_store = WITHHELD_REMITTANCE.with(|store| store.borrow().clone());
-    let cloned_witheld_amounts = WITHHELD_AMOUNTS.with(|store| store.borrow().clone());
+    let ledger_store = ledger::get_state();
+    let tickets_store = tickets::get_state();
     let cloned_is_pdc_canister = IS_PDC_CANISTER.with(|store| store.borrow().clone());
     let dc_canisters = DC_CANISTERS.with(|store| store.borrow().clone());
     let remittance_reciepts_store = REMITTANCE_RECIEPTS.with(|store| store.borrow().clone());
     let config_store = CONFIG.with(|store| store.borrow().clone());
-    let canister_balance_store = CANISTER_BALANCE.with(|store| store.borrow().clone());
-    
-    // save cloned memory
-    storage::stable_save((
-        cloned_available_balance_store,
-        cloned_witheld_balance_store,
-        cloned_witheld_amounts,
-        cloned_is_pdc_canister,
-        dc_canisters,
-        remittance_reciepts_store,
-        config_store,
-        canister_balance_store
-    ))
+    let chain_registry_store = lib::chain::CHAIN_REGISTRY.with(|store| store.borrow().clone());
+    let signing_mode_store = SIGNING_MODE.with(|store| store.borrow().clone());
+    let proof_nonces_store = PROOF_NONCES.with(|store| store.borrow().clone());
+    let allow_legacy_proofs = ALLOW_LEGACY_PROOFS.with(|store| *store.borrow());
+    let key_registry_store = lib::keys::KEY_REGISTRY.with(|store| store.borrow().clone());
+    let public_key_cache_store = lib::ecdsa::PUBLIC_KEY_CACHE.with(|store| store.borrow().clone());
+    // the stores of the events delivered to this canister
+    let event_stores = (
+        LAST_APPLIED_SEQUENCES.with(|store| store.borrow().clone()),
+        lib::dedup::get_state(),
+        deposits::get_state(),
+    );
+    // the stores of the shared modules are saved together as candid encodes at most 16 values
+    let shared_stores = (
+        lib::owner::get_access_control(),
+        lib::pause::get_state(),
+        lib::audit::get_log(),
+    );
+
+    // save cloned memory after the version of its layout
+    lib::upgrade::save(
+        migration::STATE_VERSION,
+        (
+            ledger_store,
+            tickets_store,
+            cloned_is_pdc_canister,
+            dc_canisters,
+            remittance_reciepts_store,
+            config_store,
+            chain_registry_store,
+            signing_mode_store,
+            proof_nonces_store,
+            allow_legacy_proofs,
+            key_registry_store,
+            public_key_cache_store,
+            shared_stores,
+            event_stores,
+            solvency::get_state(),
+        ),
+    )
     .unwrap()
 }
 
 #[post_upgrade]
 async fn post_upgrade() {
-    lib::owner::init_owner();
     random::init_ic_rand();
+    solvency::start_reconciliation_timer();
 
-    // load the variables from memory
+    // load the variables from memory, migrating the stores saved by the first version
+    // a balance of a canister which doesn't match the balances of its users is reported by the reconciliation
+    let (stable_state, legacy_withheld) =
+        migration::restore(&lib::upgrade::saved_bytes(), caller(), time())
+            .unwrap_or_else(|error| ic_cdk::trap(&error));
     let (
-        cloned_available_balance_store,
-        cloned_witheld_balance_store,
-        cloned_witheld_amounts_store,
+        cloned_ledger,
+        cloned_tickets,
         cloned_is_pdc_canister,
         cloned_dc_canisters,
         cloned_remittance_reciepts,
         cloned_config,
-        cloned_canister_balance
-    ): (
-        remittance::AvailableBalanceStore,
-        remittance::WithheldBalanceStore,
-        remittance::WithheldAmountsStore,
-        HashMap<Principal, bool>,
-        Vec<Principal>,
-        remittance::RemittanceRecieptsStore,
-        Config,
-        remittance::CanisterBalanceStore
-    ) = storage::stable_restore().unwrap();
+        cloned_chain_registry,
+        cloned_signing_mode,
+        cloned_proof_nonces,
+        cloned_allow_legacy_proofs,
+        cloned_key_registry,
+        cloned_public_key_cache,
+        (cloned_access_control, cloned_pause_state, cloned_audit_log),
+        (cloned_last_applied_sequences, cloned_processed_events, cloned_pending_deposits),
+        cloned_solvency,
+    ) = stable_state;
 
     //  restore by reassigning to vairiables
-    REMITTANCE.with(|r| *r.borrow_mut() = cloned_available_balance_store);
-    WITHHELD_REMITTANCE.with(|wr| *wr.borrow_mut() = cloned_witheld_balance_store);
-    WITHHELD_AMOUNTS.with(|wa| *wa.borrow_mut() = cloned_witheld_amounts_store);
+    ledger::restore_state(cloned_ledger);
+    tickets::restore_state(cloned_tickets);
     IS_PDC_CANISTER.with(|ipc| *ipc.borrow_mut() = cloned_is_pdc_canister);
     DC_CANISTERS.with(|dc| *dc.borrow_mut() = cloned_dc_canisters);
     REMITTANCE_RECIEPTS.with(|rr| *rr.borrow_mut() = cloned_remittance_reciepts);
     CONFIG.with(|c| *c.borrow_mut() = cloned_config);
-    CANISTER_BALANCE.with(|c| *c.borrow_mut() = cloned_canister_balance);
+    lib::chain::CHAIN_REGISTRY.with(|c| *c.borrow_mut() = cloned_chain_registry);
+    SIGNING_MODE.with(|s| *s.borrow_mut() = cloned_signing_mode);
+    PROOF_NONCES.with(|p| *p.borrow_mut() = cloned_proof_nonces);
+    ALLOW_LEGACY_PROOFS.with(|a| *a.borrow_mut() = cloned_allow_legacy_proofs);
+    lib::keys::KEY_REGISTRY.with(|k| *k.borrow_mut() = cloned_key_registry);
+    lib::ecdsa::PUBLIC_KEY_CACHE.with(|p| *p.borrow_mut() = cloned_public_key_cache);
+    LAST_APPLIED_SEQUENCES.with(|l| *l.borrow_mut() = cloned_last_applied_sequences);
+    lib::dedup::restore_state(cloned_processed_events);
+    deposits::restore_state(cloned_pending_deposits);
+    solvency::restore_state(cloned_solvency);
+    lib::owner::restore_access_control(cloned_access_control);
+    lib::pause::restore_state(cloned_pause_state);
+    lib::audit::restore_log(cloned_audit_log);
+
+    // the withheld entries are turned into tickets with the personal-sign hash they were signed over
+    // they keep the time their remittance was journaled at, the upgrade fails if a hash can't be made
+    if let Some(withheld) = legacy_withheld {
+        let remitted_at = ledger::timestamps(&ledger::EntryKind::Remittance);
+        let remitted_at = |nonce: u64| remitted_at.get(&nonce.to_string()).copied();
+        match tickets::migrate(withheld, remitted_at, time()) {
+            Ok(migrated) => tickets::restore_state(migrated),
+            Err(error) => ic_cdk::trap(&error.to_string()),
+        }
+    }
 }
 // --------------------------- upgrade hooks ------------------------- //
@@ -0,0 +1,280 @@
+// the layouts the stores of the canister were saved with and the migration of the first one
+use candid::{CandidType, Nat, Principal};
+use lib::{
+    owner::AccessControl,
+    remittance::{Config, SigningMode},
+    upgrade::LegacyChain,
+    Address, CcampError, Wallet,
+};
+use serde_derive::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    deposits, ledger,
+    remittance::{self, Account, WithheldAccount},
+    solvency, tickets,
+};
+
+// the version of the layout saved by `pre_upgrade`
+pub const STATE_VERSION: u32 = 1;
+
+// the stores saved across upgrades
+pub type StableState = (
+    ledger::Ledger,
+    tickets::WithdrawalTicketStore,
+    HashMap<Principal, bool>,
+    Vec<Principal>,
+    remittance::RemittanceRecieptsStore,
+    Config,
+    lib::chain::ChainRegistryStore,
+    SigningMode,
+    remittance::ProofNoncesStore,
+    bool,
+    lib::keys::KeyRegistry,
+    lib::ecdsa::PublicKeyCacheStore,
+    (AccessControl, lib::pause::PauseState, lib::audit::AuditLog),
+    (
+        BTreeMap<Principal, u64>,
+        lib::dedup::ProcessedEvents,
+        deposits::PendingDepositStore,
+    ),
+    solvency::SolvencyState,
+);
+
+// the balances of the first version of the canister, kept in u64 for the chains it supported
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct BaselineAccount {
+    pub balance: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct BaselineWithheldAccount {
+    pub balance: u64,
+    pub signature: String,
+    pub nonce: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct BaselineReciept {
+    pub token: String,
+    pub chain: String,
+    pub amount: u64,
+    pub account: String,
+    pub timestamp: u64,
+}
+
+// the stores saved by the first version of the canister, its owner wasn't saved
+pub type BaselineState = (
+    HashMap<(Wallet, LegacyChain, Wallet, Principal), BaselineAccount>,
+    HashMap<(Wallet, LegacyChain, Wallet, Principal, u64), BaselineWithheldAccount>,
+    HashMap<(Wallet, LegacyChain, Wallet, Principal), Vec<u64>>,
+    HashMap<Principal, bool>,
+    Vec<Principal>,
+    HashMap<(Principal, u64), BaselineReciept>,
+    Config,
+    HashMap<(Wallet, LegacyChain, Principal), BaselineAccount>,
+);
+
+// the stores along with the withheld entries of the first version, which are migrated once restored
+// the stores saved without a version can only be the ones of the first version
+pub fn restore(
+    bytes: &[u8],
+    owner: Principal,
+    now: u64,
+) -> Result<(StableState, Option<remittance::WithheldBalanceStore>), String> {
+    if lib::upgrade::version(bytes).is_some() {
+        return Ok((lib::upgrade::decode_versioned(bytes, STATE_VERSION)?, None));
+    }
+
+    let baseline_state = lib::upgrade::decode(bytes)?;
+    let (stable_state, withheld) =
+        migrate_baseline(baseline_state, owner, now).map_err(|error| error.to_string())?;
+
+    Ok((stable_state, Some(withheld)))
+}
+
+// the balances of the first version open the ledger and the chains it supported are registered
+// its users signed legacy proofs so they are accepted until an admin turns them off
+// the withheld amounts are left out as the order of the tickets is given by their remittances
+pub fn migrate_baseline(
+    state: BaselineState,
+    owner: Principal,
+    now: u64,
+) -> Result<(StableState, remittance::WithheldBalanceStore), CcampError> {
+    let address = |wallet: Wallet| Address::Evm(wallet);
+    let account = |account: BaselineAccount| Account {
+        balance: Nat::from(account.balance),
+    };
+
+    let available: remittance::AvailableBalanceStore = state
+        .0
+        .into_iter()
+        .map(|((token, chain, wallet, dc_canister), balance)| {
+            (
+                (address(token), chain.into(), address(wallet), dc_canister),
+                account(balance),
+            )
+        })
+        .collect();
+    let withheld: remittance::WithheldBalanceStore = state
+        .1
+        .into_iter()
+        .map(|((token, chain, wallet, dc_canister, amount), withheld)| {
+            (
+                (
+                    address(token),
+                    chain.into(),
+                    address(wallet),
+                    dc_canister,
+                    Nat::from(amount),
+                ),
+                WithheldAccount {
+                    balance: Nat::from(withheld.balance),
+                    signature: withheld.signature,
+                    nonce: withheld.nonce,
+                    key_version: lib::keys::LEGACY_KEY_VERSION,
+                },
+            )
+        })
+        .collect();
+    let canister_balances: remittance::CanisterBalanceStore = state
+        .7
+        .into_iter()
+        .map(|((token, chain, dc_canister), balance)| {
+            (
+                (address(token), chain.into(), dc_canister),
+                account(balance),
+            )
+        })
+        .collect();
+    let remittance_reciepts = state
+        .5
+        .into_iter()
+        .map(|(key, reciept)| {
+            (
+                key,
+                remittance::RemittanceReciept {
+                    token: reciept.token,
+                    chain: reciept.chain,
+                    amount: Nat::from(reciept.amount),
+                    account: reciept.account,
+                    timestamp: reciept.timestamp,
+                },
+            )
+        })
+        .collect();
+    let ledger = ledger::open(available, &withheld, canister_balances, now)?;
+
+    let stable_state = (
+        ledger,
+        tickets::WithdrawalTicketStore::default(),
+        state.3,
+        state.4,
+        remittance_reciepts,
+        state.6,
+        lib::upgrade::legacy_chains().into_iter().collect(),
+        SigningMode::default(),
+        remittance::ProofNoncesStore::default(),
+        true,
+        lib::keys::KeyRegistry::default(),
+        lib::ecdsa::PublicKeyCacheStore::default(),
+        (
+            lib::upgrade::legacy_access_control(owner),
+            lib::pause::PauseState::default(),
+            lib::audit::AuditLog::default(),
+        ),
+        (
+            BTreeMap::default(),
+            lib::dedup::ProcessedEvents::default(),
+            deposits::PendingDepositStore::default(),
+        ),
+        solvency::SolvencyState::default(),
+    );
+
+    Ok((stable_state, withheld))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::LedgerAccount;
+
+    #[test]
+    fn the_baseline_state_is_migrated_to_the_current_layout() {
+        let dc_canister = Principal::from_text("bkyz2-fmaaa-aaaaa-qaaaq-cai").unwrap();
+        let owner = Principal::from_slice(&[7]);
+        let token =
+            Wallet::try_from(String::from("0xB24a30A3971e4d9bf771BDc81435c25EA69A445c")).unwrap();
+        let account =
+            Wallet::try_from(String::from("0x9C81E8F60a9B8743678F1b6Ae893Cc72c6Bc6840")).unwrap();
+        let baseline_state: BaselineState = (
+            HashMap::from([(
+                (
+                    token.clone(),
+                    LegacyChain::Polygon137,
+                    account.clone(),
+                    dc_canister,
+                ),
+                BaselineAccount { balance: 60 },
+            )]),
+            HashMap::from([(
+                (
+                    token.clone(),
+                    LegacyChain::Polygon137,
+                    account.clone(),
+                    dc_canister,
+                    40,
+                ),
+                BaselineWithheldAccount {
+                    balance: 40,
+                    signature: String::from("0x01"),
+                    nonce: 9,
+                },
+            )]),
+            HashMap::new(),
+            HashMap::new(),
+            vec![dc_canister],
+            HashMap::new(),
+            Config::default(),
+            HashMap::from([(
+                (token.clone(), LegacyChain::Polygon137, dc_canister),
+                BaselineAccount { balance: 100 },
+            )]),
+        );
+        let mut bytes = candid::encode_args(baseline_state).unwrap();
+        // stable memory is read in whole pages
+        bytes.extend([0u8; 64]);
+
+        let (stable_state, withheld) = restore(&bytes, owner, 5).unwrap();
+        let chain: lib::Chain = String::from("polygon:137").try_into().unwrap();
+        let pool = (
+            Address::Evm(token.clone()),
+            chain.clone(),
+            LedgerAccount::Pool { dc_canister },
+        );
+        assert_eq!(stable_state.0.balance(&pool), Nat::from(100u64));
+        let available = (
+            Address::Evm(token),
+            chain.clone(),
+            LedgerAccount::Available {
+                account: Address::Evm(account),
+                dc_canister,
+            },
+        );
+        assert_eq!(stable_state.0.balance(&available), Nat::from(60u64));
+        assert!(stable_state.0.is_consistent());
+        assert_eq!(withheld.unwrap().values().next().unwrap().nonce, 9);
+        assert!(stable_state.6.contains_key(&chain));
+        let icp: lib::Chain = LegacyChain::Icp.into();
+        assert!(stable_state.6.contains_key(&icp));
+        // legacy proofs stay accepted and the caller of the upgrade owns the canister
+        assert!(stable_state.9);
+        assert_eq!(stable_state.12 .0.owner, Some(owner));
+
+        // the stores saved by the current version are restored as they are
+        let versioned = lib::upgrade::encode(STATE_VERSION, stable_state.clone()).unwrap();
+        let (restored, withheld) = restore(&versioned, owner, 6).unwrap();
+        assert_eq!(restored.0, stable_state.0);
+        assert!(withheld.is_none());
+    }
+}
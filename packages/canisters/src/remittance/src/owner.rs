@@ -1,10 +1,28 @@
 use ic_cdk::caller;
+use lib::{owner::Role, CcampError};
 
 // ------- Access control
-pub fn only_publisher() {
+pub fn only_publisher() -> Result<(), CcampError> {
     let caller_principal_id = caller();
     if !crate::DC_CANISTERS.with(|publisher| publisher.borrow().contains(&caller_principal_id)) {
-        panic!("NOT_ALLOWED");
+        return Err(CcampError::NotAllowed {
+            caller: caller_principal_id,
+        });
     }
+
+    Ok(())
 }
-// ------- Access control
\ No newline at end of file
+
+// the protocol data collection canisters forward the blocks reported by their publishers
+// operators can report them too e.g to reverse the deposits of a reorg the publishers missed
+pub fn only_block_reporter() -> Result<(), CcampError> {
+    let caller_principal_id = caller();
+    let is_pdc = crate::IS_PDC_CANISTER
+        .with(|is_pdc_canister| is_pdc_canister.borrow().contains_key(&caller_principal_id));
+    if is_pdc {
+        return Ok(());
+    }
+
+    lib::owner::ensure_role(Role::Operator)
+}
+// ------- Access control
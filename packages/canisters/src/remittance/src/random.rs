@@ -6,7 +6,7 @@ use getrandom::register_custom_getrandom;
 use rand::{rngs::StdRng, RngCore, SeedableRng};
 
 thread_local! {
-    pub static RNG: RefCell<Option<StdRng>> = RefCell::new(None);
+    pub static RNG: RefCell<Option<StdRng>> = const { RefCell::new(None) };
 }
 
 async fn set_rand() {
@@ -1,45 +1,56 @@
 // define all major types and their implementation here
 
 #![allow(dead_code)]
-use crate::utils;
-use candid::{CandidType, Principal};
+use candid::{CandidType, Int, Nat, Principal};
 use easy_hasher::easy_hasher;
 use eth_encode_packed::{
     ethabi::{ethereum_types::U256, Address},
     SolidityDataType,
 };
-use ic_cdk::api::time;
-use lib;
+use lib::{
+    self,
+    chain::SignatureScheme,
+    constants::{LOCKER_DOMAIN_NAME, LOCKER_DOMAIN_VERSION, SOLANA_WITHDRAWAL_PREFIX},
+    ethereum::{self, eip712},
+    remittance::SigningMode,
+    signer::{Ed25519Signer, Signer, Signers},
+    CcampError,
+};
 use rand::rngs::StdRng;
 use serde_derive::Deserialize;
 use std::{cell::RefCell, collections::HashMap};
 
+use crate::{
+    ledger::{self, EntryKind, LedgerAccount},
+    tickets,
+    utils::vec_u8_to_string,
+};
+
 thread_local! {
-    static RNG: RefCell<Option<StdRng>> = RefCell::new(None);
+    static RNG: RefCell<Option<StdRng>> = const { RefCell::new(None) };
 }
-#[derive(Clone, Debug, CandidType, Deserialize)]
+#[derive(Clone, Debug, CandidType, Deserialize, Default)]
 pub struct Account {
-    pub balance: u64,
-}
-impl Default for Account {
-    fn default() -> Self {
-        return Self { balance: 0 };
-    }
+    pub balance: Nat,
 }
 
+// a withheld remittance as it was kept before withdrawal tickets, only read to migrate it
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub struct WithheldAccount {
-    pub balance: u64,
+    pub balance: Nat,
     pub signature: String,
     pub nonce: u64,
+    // the version of the key which made the signature
+    pub key_version: u32,
 }
 impl Default for WithheldAccount {
     fn default() -> Self {
-        return Self {
-            balance: 0,
+        Self {
+            balance: Nat::default(),
             signature: String::from(""),
             nonce: 0,
-        };
+            key_version: lib::keys::LEGACY_KEY_VERSION,
+        }
     }
 }
 
@@ -48,216 +59,638 @@ pub struct RemittanceReply {
     pub hash: String,
     pub signature: String,
     pub nonce: u64,
-    pub amount: u64,
+    pub amount: Nat,
 }
 
-#[derive(CandidType, Deserialize, Debug, Clone)]
+#[derive(CandidType, Deserialize, Debug, Clone, Default)]
 pub struct RemittanceReciept {
     pub token: String,
     pub chain: String,
-    pub amount: u64,
+    pub amount: Nat,
     pub account: String,
     pub timestamp: u64,
 }
-impl Default for RemittanceReciept {
-    fn default() -> Self {
-        return Self {
-            amount: 0,
-            timestamp: 0,
-            token: String::from(""),
-            chain: String::from(""),
-            account: String::from(""),
-        };
-    }
+
+// the parameters of a call to `remit`
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct RemitRequest {
+    pub token: String,
+    pub chain: String,
+    pub account: String,
+    pub dc_canister: Principal,
+    pub amount: Nat,
+    // a signature of the request by the account
+    pub proof: String,
+    pub proof_parameters: Option<ProofParameters>,
 }
 
+// the replay protection of a `remit` proof
+// `nonce` has to be the next proof nonce of the account and `expiry` a unix timestamp in seconds
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct ProofParameters {
+    pub nonce: u64,
+    pub expiry: u64,
+}
+
+// the balances kept before the ledger, only read to open it
+// (token, chain, account, dc_canister) and (token, chain, dc_canister)
 pub type AvailableBalanceStore =
-    HashMap<(lib::Wallet, lib::Chain, lib::Wallet, Principal), Account>;
+    HashMap<(lib::Address, lib::Chain, lib::Address, Principal), Account>;
+pub type CanisterBalanceStore = HashMap<(lib::Address, lib::Chain, Principal), Account>;
+// (token, chain, account, dc_canister, amount), replaced by the withdrawal tickets
 pub type WithheldBalanceStore =
-    HashMap<(lib::Wallet, lib::Chain, lib::Wallet, Principal, u64), WithheldAccount>;
-pub type WithheldAmountsStore =
-    HashMap<(lib::Wallet, lib::Chain, lib::Wallet, Principal), Vec<u64>>;
+    HashMap<(lib::Address, lib::Chain, lib::Address, Principal, Nat), WithheldAccount>;
 pub type RemittanceRecieptsStore = HashMap<(Principal, u64), RemittanceReciept>;
-pub type CanisterBalanceStore = HashMap<(lib::Wallet, lib::Chain, Principal), Account>;
+// the next proof nonce of each account
+pub type ProofNoncesStore = HashMap<lib::Address, u64>;
 
 // this is equivalent to a function which produces abi.encodePacked(nonce, amount, address)
 pub fn hash_remittance_parameters(
     nonce: u64,
-    amount: u64,
+    amount: &Nat,
     address: &str,
     chain_id: &str,
     dc_canister_id: &str,
     token_address: &str,
-) -> Vec<u8> {
+) -> Result<Vec<u8>, CcampError> {
     // convert the address to bytes format
-    let address: [u8; 20] = utils::string_to_vec_u8(address).try_into().unwrap();
-    let token_address: [u8; 20] = utils::string_to_vec_u8(token_address).try_into().unwrap();
+    // only evm addresses can be withdrawn through the locker contract
+    let to_evm_address = |address: &str| -> Result<[u8; 20], CcampError> {
+        let wallet =
+            lib::Wallet::try_from(address.to_string()).map_err(|_| CcampError::InvalidAddress {
+                address: address.to_string(),
+                reason: String::from("UNSUPPORTED_ADDRESS_FORMAT"),
+            })?;
+
+        wallet
+            .address
+            .try_into()
+            .map_err(|_| CcampError::InvalidAddress {
+                address: address.to_string(),
+                reason: String::from("INVALID_ADDRESSS_LENGTH"),
+            })
+    };
+    let address = to_evm_address(address)?;
+    let token_address = to_evm_address(token_address)?;
+    // the amount has to fit into a uint256
+    let amount = lib::amount::to_u256_bytes(amount).ok_or(CcampError::InvalidAmount {
+        amount: lib::amount::to_int(amount),
+    })?;
 
     // pack the encoded bytes
     let input = vec![
         SolidityDataType::Number(U256::from(nonce)),
-        SolidityDataType::Number(U256::from(amount)),
+        SolidityDataType::Number(U256::from_big_endian(&amount)),
         SolidityDataType::Address(Address::from(address)),
         SolidityDataType::String(chain_id),
         SolidityDataType::String(dc_canister_id),
         SolidityDataType::Address(Address::from(token_address)),
     ];
-    let (_bytes, __) = eth_encode_packed::abi::encode_packed(&input);
+    let (bytes, _) = eth_encode_packed::abi::encode_packed(&input);
 
-    easy_hasher::raw_keccak256(_bytes.clone()).to_vec()
+    Ok(easy_hasher::raw_keccak256(bytes).to_vec())
 }
 
-// given some details, which are the parameters of the function
-// we want to get the balance signature generated when a remit request created by this account
-// it would return a balance of 0 and no signature if a user has not made a remit request for the specified "amount"
-pub fn get_remitted_balance(
-    token: lib::Wallet,
-    chain: lib::Chain,
-    account: lib::Wallet,
-    dc_canister: Principal,
-    amount: u64,
-) -> WithheldAccount {
-    let withheld_amount = crate::WITHHELD_REMITTANCE.with(|withheld| {
-        let existing_key = (token, chain, account.clone(), dc_canister, amount);
-        withheld
-            .borrow()
-            .get(&existing_key)
-            .cloned()
-            .unwrap_or_default()
-    });
+// verify the proof of a remittance request, withhold the amount from the available balance
+// and sign the withdrawal authorization with the signer of the signature scheme of the chain
+// `now` is the current unix timestamp in seconds which the expiry of the proof is checked against
+pub async fn remit<S: Signer, E: Ed25519Signer>(
+    signers: &Signers<S, E>,
+    now: u64,
+    request: RemitRequest,
+) -> Result<RemittanceReply, CcampError> {
+    let RemitRequest {
+        token,
+        chain,
+        account,
+        dc_canister,
+        amount,
+        proof,
+        proof_parameters,
+    } = request;
+
+    // make sure the amount being remitted is none zero
+    if amount == 0u64 {
+        return Err(CcampError::InvalidAmount {
+            amount: lib::amount::to_int(&amount),
+        });
+    }
+
+    // generate key values
+    let chain = lib::chain::resolve_chain(chain)?;
+    let token = parse_address(token)?;
+    let account_address = account.clone();
+    let account = parse_address(account)?;
+
+    // the 'proof' is a signature of the request parameters by the provided address
+    // or of only the amount when the legacy format is still allowed
+    let proof_message = match &proof_parameters {
+        Some(parameters) => {
+            if parameters.expiry <= now {
+                return Err(CcampError::ProofExpired {
+                    expiry: parameters.expiry,
+                    now,
+                });
+            }
+
+            remit_proof_message(&token, &chain, &account, &dc_canister, &amount, parameters)
+        }
+        None => {
+            if !crate::ALLOW_LEGACY_PROOFS.with(|allow| *allow.borrow()) {
+                return Err(CcampError::LegacyProofDisabled);
+            }
+
+            lib::amount::to_decimal_string(&amount)
+        }
+    };
+    verify_proof(&account, account_address, proof, proof_message)?;
+    // the nonce of the proof is only checked here and used once the request can't fail anymore
+    if let Some(parameters) = &proof_parameters {
+        let expected = get_proof_nonce(&account);
+        if parameters.nonce != expected {
+            return Err(CcampError::InvalidProofNonce {
+                expected,
+                received: parameters.nonce,
+            });
+        }
+    }
+
+    // a legacy proof only signs the amount so it can be replayed
+    // it is rejected while a ticket of the same amount is outstanding instead of withholding the amount again
+    if proof_parameters.is_none()
+        && tickets::is_outstanding(&token, &chain, &account, &dc_canister, &amount)
+    {
+        return Err(CcampError::RemittanceInProgress { amount });
+    }
+
+    // the nonce identifies the ticket of the remittance so it can't be in use already
+    let nonce = loop {
+        let nonce = crate::random::get_random_number();
+        if !tickets::contains(nonce) {
+            break nonce;
+        }
+    };
+    let signing_mode = crate::SIGNING_MODE.with(|mode| mode.borrow().clone());
+    let message_hash = hash_withdrawal(
+        &signing_mode,
+        nonce,
+        &amount,
+        &account.to_string(),
+        &chain,
+        &dc_canister.to_string(),
+        &token.to_string(),
+    )?;
+
+    // sign with the current key of the chain and data collection canister
+    let key_id = lib::keys::key_id(
+        lib::keys::current_version(),
+        Some(&chain),
+        Some(&dc_canister),
+    )?;
+
+    // reserve the amount before the call to sign so concurrent remittances can't spend it twice
+    // the journal keeps timestamps in nanoseconds
+    let timestamp = now.saturating_mul(1_000_000_000);
+    tickets::open(tickets::WithdrawalTicket {
+        nonce,
+        token: token.clone(),
+        chain: chain.clone(),
+        account: account.clone(),
+        dc_canister,
+        amount: amount.clone(),
+        signature: None,
+        hash: format!("0x{}", vec_u8_to_string(&message_hash)),
+        key_version: key_id.version,
+        created_at: timestamp,
+    })?;
 
-    withheld_amount
+    // use the nonce before the call to sign so the proof can't be replayed in the meantime
+    if let Some(parameters) = &proof_parameters {
+        if let Err(error) = use_proof_nonce(&account, parameters.nonce) {
+            tickets::release(nonce, timestamp);
+            return Err(error);
+        }
+    }
+
+    // generate a signature for these parameters
+    let signature_hex = match (chain.signature_scheme(), &signing_mode) {
+        (SignatureScheme::Ed25519, _) => signers
+            .ed25519
+            .derive(key_id.derivation_path())
+            .sign(&message_hash)
+            .await
+            .map(|signature| vec_u8_to_string(&signature)),
+        (SignatureScheme::EcdsaSecp256k1, SigningMode::PersonalSign) => {
+            let signer = signers.ecdsa.derive(key_id.derivation_path());
+            ethereum::sign_message(&message_hash, &signer)
+                .await
+                .map(|reply| reply.signature_hex)
+        }
+        // the typed data digest is signed without the personal message prefix
+        (SignatureScheme::EcdsaSecp256k1, SigningMode::Eip712) => {
+            let signer = signers.ecdsa.derive(key_id.derivation_path());
+            ethereum::sign_digest(message_hash.clone(), &signer)
+                .await
+                .map(|reply| reply.signature_hex)
+        }
+    };
+    // the reserved amount goes back to the available balance if the signature couldn't be made
+    let signature_hex = match signature_hex {
+        Ok(signature_hex) => signature_hex,
+        Err(reason) => {
+            tickets::release(nonce, timestamp);
+            return Err(CcampError::SigningFailed { reason });
+        }
+    };
+    let signature_string = format!("0x{}", signature_hex);
+    tickets::set_signature(nonce, signature_string.clone());
+
+    // create response object
+    let response = RemittanceReply {
+        hash: format!("0x{}", vec_u8_to_string(&message_hash)),
+        signature: signature_string,
+        nonce,
+        amount,
+    };
+
+    Ok(response)
+}
+
+// the message a user signs with personal_sign to request a remittance
+// it binds the proof to every parameter of the request so it can't be replayed for another one
+pub fn remit_proof_message(
+    token: &lib::Address,
+    chain: &lib::Chain,
+    account: &lib::Address,
+    dc_canister: &Principal,
+    amount: &Nat,
+    parameters: &ProofParameters,
+) -> String {
+    format!(
+        "CCAMP remit\ntoken:{}\nchain:{}\naccount:{}\ndc_canister:{}\namount:{}\nnonce:{}\nexpiry:{}",
+        token,
+        chain,
+        account,
+        dc_canister,
+        lib::amount::to_decimal_string(amount),
+        parameters.nonce,
+        parameters.expiry
+    )
+}
+
+// an address of any format supported by the protocol, e.g given by a user
+pub fn parse_address(address: String) -> Result<lib::Address, CcampError> {
+    address
+        .clone()
+        .try_into()
+        .map_err(|reason| CcampError::InvalidAddress { address, reason })
+}
+
+pub fn get_proof_nonce(account: &lib::Address) -> u64 {
+    crate::PROOF_NONCES.with(|nonces| nonces.borrow().get(account).cloned().unwrap_or_default())
+}
+
+// make sure the nonce of a proof is the next nonce of the account and mark it as used
+pub fn use_proof_nonce(account: &lib::Address, nonce: u64) -> Result<(), CcampError> {
+    let expected = get_proof_nonce(account);
+    if nonce != expected {
+        return Err(CcampError::InvalidProofNonce {
+            expected,
+            received: nonce,
+        });
+    }
+
+    crate::PROOF_NONCES.with(|nonces| nonces.borrow_mut().insert(account.clone(), expected + 1));
+
+    Ok(())
+}
+
+// the EIP-712 type of a withdrawal authorization, its fields mirror the packed parameters
+pub const WITHDRAWAL_TYPE: &str = "Withdrawal(uint256 nonce,uint256 amount,address account,string chain,string canisterId,address token)";
+
+// the EIP-712 digest of a withdrawal which is bound to the locker contract registered for the chain
+pub fn hash_withdrawal_typed_data(
+    nonce: u64,
+    amount: &Nat,
+    address: &str,
+    chain: &lib::Chain,
+    dc_canister_id: &str,
+    token_address: &str,
+) -> Result<Vec<u8>, CcampError> {
+    let locker_not_registered = || CcampError::LockerNotRegistered {
+        chain: chain.to_string(),
+    };
+    let locker_address = lib::chain::get_chain_metadata(chain)
+        .and_then(|metadata| metadata.locker_address)
+        .ok_or_else(locker_not_registered)?;
+    let domain = eip712::Domain {
+        name: LOCKER_DOMAIN_NAME.to_string(),
+        version: LOCKER_DOMAIN_VERSION.to_string(),
+        chain_id: eip712::chain_id(chain).map_err(|_| CcampError::InvalidChain {
+            chain: chain.to_string(),
+        })?,
+        verifying_contract: lib::Wallet::try_from(locker_address)
+            .map_err(|_| locker_not_registered())?,
+    };
+
+    let to_wallet = |address: &str| -> Result<lib::Wallet, CcampError> {
+        lib::Wallet::try_from(address.to_string()).map_err(|_| CcampError::InvalidAddress {
+            address: address.to_string(),
+            reason: String::from("UNSUPPORTED_ADDRESS_FORMAT"),
+        })
+    };
+    let amount = lib::amount::to_u256_bytes(amount).ok_or(CcampError::InvalidAmount {
+        amount: lib::amount::to_int(amount),
+    })?;
+
+    let struct_hash = eip712::hash_struct(
+        WITHDRAWAL_TYPE,
+        &[
+            eip712::encode_u64(nonce),
+            amount,
+            eip712::encode_address(&to_wallet(address)?),
+            eip712::encode_string(&chain.to_string()),
+            eip712::encode_string(dc_canister_id),
+            eip712::encode_address(&to_wallet(token_address)?),
+        ],
+    );
+
+    Ok(eip712::hash_typed_data(&domain, &struct_hash).to_vec())
+}
+
+// make sure the proof of a remit request was signed by the account
+// solana accounts sign with ed25519 and every other account with an ethereum wallet
+fn verify_proof(
+    account: &lib::Address,
+    account_address: String,
+    proof: String,
+    message: String,
+) -> Result<(), CcampError> {
+    if let lib::Address::Solana(public_key) = account {
+        return lib::schnorr::verify_ed25519(public_key, message.as_bytes(), &proof)
+            .map_err(|reason| CcampError::InvalidSignature { reason });
+    }
+
+    let derived_address = ethereum::recover_address_from_eth_signature(proof, message)?;
+    if derived_address != account_address.to_lowercase() {
+        return Err(CcampError::SignatureMismatch {
+            recovered: derived_address,
+            expected: account_address,
+        });
+    }
+
+    Ok(())
+}
+
+// the message an ed25519 withdrawal authorization signs
+// it is laid out like the borsh encoding of the withdrawal so solana programs can rebuild it:
+// "ccamp:withdrawal" || nonce (u64 LE) || amount (u64 LE) || account (32 bytes) || token (32 bytes)
+// || chain (u32 LE length || utf8) || canister id (u32 LE length || utf8)
+pub fn solana_withdrawal_message(
+    nonce: u64,
+    amount: &Nat,
+    address: &str,
+    chain: &lib::Chain,
+    dc_canister_id: &str,
+    token_address: &str,
+) -> Result<Vec<u8>, CcampError> {
+    let to_solana_address = |address: &str| -> Result<Vec<u8>, CcampError> {
+        match lib::Address::parse_solana(address) {
+            Ok(lib::Address::Solana(public_key)) => Ok(public_key),
+            _ => Err(CcampError::InvalidAddress {
+                address: address.to_string(),
+                reason: String::from("UNSUPPORTED_ADDRESS_FORMAT"),
+            }),
+        }
+    };
+    let account = to_solana_address(address)?;
+    let token = to_solana_address(token_address)?;
+    // spl token amounts are u64
+    let amount = lib::amount::to_u64(amount).ok_or(CcampError::InvalidAmount {
+        amount: lib::amount::to_int(amount),
+    })?;
+    let encode_string = |value: &str| -> Vec<u8> {
+        [&(value.len() as u32).to_le_bytes()[..], value.as_bytes()].concat()
+    };
+
+    Ok([
+        SOLANA_WITHDRAWAL_PREFIX.as_bytes(),
+        &nonce.to_le_bytes(),
+        &amount.to_le_bytes(),
+        &account,
+        &token,
+        &encode_string(&chain.to_string()),
+        &encode_string(dc_canister_id),
+    ]
+    .concat())
+}
+
+// hash the parameters of a withdrawal according to the signing mode of the canister
+// withdrawals on ed25519 chains sign the solana message itself whatever the signing mode
+pub fn hash_withdrawal(
+    signing_mode: &SigningMode,
+    nonce: u64,
+    amount: &Nat,
+    address: &str,
+    chain: &lib::Chain,
+    dc_canister_id: &str,
+    token_address: &str,
+) -> Result<Vec<u8>, CcampError> {
+    if chain.signature_scheme() == SignatureScheme::Ed25519 {
+        return solana_withdrawal_message(
+            nonce,
+            amount,
+            address,
+            chain,
+            dc_canister_id,
+            token_address,
+        );
+    }
+
+    match signing_mode {
+        SigningMode::PersonalSign => hash_remittance_parameters(
+            nonce,
+            amount,
+            address,
+            &chain.to_string(),
+            dc_canister_id,
+            token_address,
+        ),
+        SigningMode::Eip712 => {
+            hash_withdrawal_typed_data(nonce, amount, address, chain, dc_canister_id, token_address)
+        }
+    }
 }
 
 // get the total unspent available-to-use balance for the user
 pub fn get_available_balance(
-    token: lib::Wallet,
+    token: lib::Address,
     chain: lib::Chain,
-    account: lib::Wallet,
+    account: lib::Address,
     dc_canister: Principal,
 ) -> Account {
-    let available_amount = crate::REMITTANCE.with(|remittance| {
-        let existing_key = (token, chain, account, dc_canister);
-        remittance
-            .borrow()
-            .get(&existing_key)
-            .cloned()
-            .unwrap_or_default()
-    });
+    let balance = ledger::balance(
+        &token,
+        &chain,
+        LedgerAccount::Available {
+            account,
+            dc_canister,
+        },
+    );
 
-    available_amount
+    Account { balance }
 }
 
-pub fn get_canister_balance(
-    token: lib::Wallet,
+// get the balance which was remitted but not withdrawn or cancelled yet
+pub fn get_withheld_balance(
+    token: lib::Address,
     chain: lib::Chain,
+    account: lib::Address,
     dc_canister: Principal,
 ) -> Account {
-    let canister_balance = crate::CANISTER_BALANCE.with(|cb| {
-        let existing_key = (token, chain, dc_canister);
-        cb.borrow().get(&existing_key).cloned().unwrap_or_default()
-    });
+    let balance = ledger::balance(
+        &token,
+        &chain,
+        LedgerAccount::Withheld {
+            account,
+            dc_canister,
+        },
+    );
 
-    canister_balance
+    Account { balance }
 }
 
-// it essentially uses the mapping (ticker, chain, recipientaddress) => {DataModel}
-// so if an entry exists for a particular combination of (ticker, chain, recipientaddress)
-// then the price is updated, otherwise the entry is created
-pub fn update_balance(new_remittance: &lib::DataModel, dc_canister: Principal) {
-    crate::REMITTANCE.with(|remittance| {
-        let mut remittance_store = remittance.borrow_mut();
+pub fn get_canister_balance(
+    token: lib::Address,
+    chain: lib::Chain,
+    dc_canister: Principal,
+) -> Account {
+    let balance = ledger::balance(&token, &chain, LedgerAccount::Pool { dc_canister });
 
-        let hash_key = (
-            new_remittance.token.clone(),
-            new_remittance.chain.clone(),
-            new_remittance.account.clone(),
-            dc_canister.clone(),
-        );
+    Account { balance }
+}
 
-        if let Some(existing_data) = remittance_store.get_mut(&hash_key) {
-            existing_data.balance =
-                (existing_data.balance as i64 + new_remittance.amount as i64) as u64;
-        } else {
-            remittance_store.insert(
-                hash_key,
-                Account {
-                    balance: new_remittance.amount as u64,
-                },
-            );
-        }
-    });
+// a deposit grows the pool of the data collection canister along with the balance of the user
+pub fn record_deposit(
+    deposit: &lib::DataModel,
+    dc_canister: Principal,
+    now: u64,
+) -> Result<u64, CcampError> {
+    let amount = lib::amount::abs(&deposit.amount);
+    let postings = vec![
+        ledger::debit(
+            &deposit.token,
+            &deposit.chain,
+            LedgerAccount::Pool { dc_canister },
+            amount.clone(),
+        ),
+        ledger::credit(
+            &deposit.token,
+            &deposit.chain,
+            LedgerAccount::Available {
+                account: deposit.account.clone(),
+                dc_canister,
+            },
+            amount,
+        ),
+    ];
+    let reference = deposit
+        .identity
+        .as_ref()
+        .map(|identity| identity.to_string());
+
+    ledger::post(EntryKind::Deposit, reference, postings, now)
 }
 
-pub fn update_canister_balance(
-    token: lib::Wallet,
-    chain: lib::Chain,
+// the adjustments of a batch move balances between users in a single entry
+// the negative amounts are debited from their account and the positive ones credited to it
+pub fn record_adjustments(
+    adjustments: &[lib::DataModel],
     dc_canister: Principal,
-    amount: i64,
-) {
-    crate::CANISTER_BALANCE.with(|canister_balance| {
-        let mut canister_balance_store = canister_balance.borrow_mut();
-
-        let hash_key = (token.clone(), chain.clone(), dc_canister.clone());
-
-        if let Some(existing_data) = canister_balance_store.get_mut(&hash_key) {
-            existing_data.balance = (existing_data.balance as i64 + amount as i64) as u64;
-        } else {
-            canister_balance_store.insert(
-                hash_key,
-                Account {
-                    balance: amount as u64,
-                },
-            );
-        }
-    });
+    now: u64,
+) -> Result<u64, CcampError> {
+    let postings = adjustments
+        .iter()
+        .map(|adjustment| {
+            let account = LedgerAccount::Available {
+                account: adjustment.account.clone(),
+                dc_canister,
+            };
+            let amount = lib::amount::abs(&adjustment.amount);
+            match lib::amount::is_negative(&adjustment.amount) {
+                true => ledger::debit(&adjustment.token, &adjustment.chain, account, amount),
+                false => ledger::credit(&adjustment.token, &adjustment.chain, account, amount),
+            }
+        })
+        .collect();
+
+    ledger::post(EntryKind::Adjustment, None, postings, now)
+}
+
+// retire the previous key versions once every signature they made has been confirmed or cancelled
+pub fn retire_unused_keys() -> Vec<u32> {
+    lib::keys::retire_unused(&tickets::versions_in_use())
+}
+
+// the token, chain and account of a withdrawal or cancellation seen on chain
+fn parse_withdrawal_event(
+    token: String,
+    chain: String,
+    account: String,
+) -> Result<(lib::Address, lib::Chain, lib::Address), CcampError> {
+    let chain: lib::Chain = chain
+        .clone()
+        .try_into()
+        .map_err(|_| CcampError::InvalidChain { chain })?;
+
+    Ok((parse_address(token)?, chain, parse_address(account)?))
 }
 
 pub fn confirm_withdrawal(
+    nonce: u64,
     token: String,
     chain: String,
     account: String,
-    amount_withdrawn: u64,
+    amount_withdrawn: Nat,
     dc_canister: Principal,
-) -> bool {
-    let chain: lib::Chain = chain.try_into().unwrap();
-    let token: lib::Wallet = token.try_into().unwrap();
-    let account: lib::Wallet = account.try_into().unwrap();
-
-    let hash_key = (
-        token.clone(),
-        chain.clone(),
-        account.clone(),
-        dc_canister.clone(),
-    );
+    now: u64,
+) -> Result<(), CcampError> {
+    let (token, chain, account) = parse_withdrawal_event(token, chain, account)?;
 
-    // go through the witheld amounts and remove this amount from it
-    crate::WITHHELD_AMOUNTS.with(|witheld_amounts| {
-        let mut mut_witheld_amounts = witheld_amounts.borrow_mut();
-        let unwithdrawn_amounts = mut_witheld_amounts
-            .get(&hash_key)
-            .expect("WITHDRAWAL_CONFIRMATION_ERROR:AMOUNT_NOT_WITHELD")
-            .into_iter()
-            .filter(|&amount_to_withdraw| *amount_to_withdraw != amount_withdrawn)
-            .cloned()
-            .collect();
-        mut_witheld_amounts.insert(hash_key, unwithdrawn_amounts);
-    });
+    // resolve the ticket the withdrawal was signed for
+    let withdrawn_details = tickets::resolve(
+        nonce,
+        &token,
+        &chain,
+        &account,
+        &dc_canister,
+        &amount_withdrawn,
+    )?;
 
-    // go through the witheld balance store and remove this amount from it
-    let withdrawn_details = crate::WITHHELD_REMITTANCE.with(|withheld_remittance| {
-        let key = (
-            token.clone(),
-            chain.clone(),
-            account.clone(),
-            dc_canister.clone(),
-            amount_withdrawn,
-        );
-        let withdrawn_balance = withheld_remittance.borrow().get(&key).unwrap().clone();
-        withheld_remittance.borrow_mut().remove(&key);
-
-        withdrawn_balance
-    });
+    // the withdrawn amount leaves the pool along with the withheld balance of the user
+    let postings = vec![
+        ledger::debit(
+            &token,
+            &chain,
+            LedgerAccount::Withheld {
+                account: account.clone(),
+                dc_canister,
+            },
+            amount_withdrawn.clone(),
+        ),
+        ledger::credit(
+            &token,
+            &chain,
+            LedgerAccount::Pool { dc_canister },
+            amount_withdrawn.clone(),
+        ),
+    ];
+    ledger::post(
+        EntryKind::Withdrawal,
+        Some(withdrawn_details.nonce.to_string()),
+        postings,
+        now,
+    )?;
 
     // create a reciept entry here for a succcessfull withdrawal
     crate::REMITTANCE_RECIEPTS.with(|remittance_reciepts| {
@@ -268,71 +701,98 @@ pub fn confirm_withdrawal(
                 chain: chain.to_string(),
                 amount: amount_withdrawn,
                 account: account.to_string(),
-                timestamp: time(),
+                timestamp: now,
             },
         );
     });
-    return true;
+
+    Ok(())
 }
 
 pub fn cancel_withdrawal(
+    nonce: u64,
     token: String,
     chain: String,
     account: String,
-    amount_canceled: u64,
+    amount_canceled: Nat,
     dc_canister: Principal,
-) -> bool {
-    let chain: lib::Chain = chain.try_into().unwrap();
-    let token: lib::Wallet = token.try_into().unwrap();
-    let account: lib::Wallet = account.try_into().unwrap();
-
-    let hash_key = (
-        token.clone(),
-        chain.clone(),
-        account.clone(),
-        dc_canister.clone(),
-    );
+    now: u64,
+) -> Result<(), CcampError> {
+    let (token, chain, account) = parse_withdrawal_event(token, chain, account)?;
 
-    // go through the witheld amounts and remove this amount from it
-    crate::WITHHELD_AMOUNTS.with(|witheld_amounts| {
-        let mut mut_witheld_amounts = witheld_amounts.borrow_mut();
-        let unwithdrawn_amounts = mut_witheld_amounts
-            .get(&hash_key)
-            .expect("CANCEL_WITHDRAW_ERROR:AMOUNT_NOT_WITHELD")
-            .into_iter()
-            .filter(|&amount_to_withdraw| *amount_to_withdraw != amount_canceled)
-            .cloned()
-            .collect();
-        mut_witheld_amounts.insert(hash_key.clone(), unwithdrawn_amounts);
-    });
+    // resolve the ticket the cancelled withdrawal was signed for
+    let canceled_details = tickets::resolve(
+        nonce,
+        &token,
+        &chain,
+        &account,
+        &dc_canister,
+        &amount_canceled,
+    )?;
 
-    // go through the witheld balance store and remove this amount from it
-    crate::WITHHELD_REMITTANCE.with(|withheld_remittance| {
-        withheld_remittance.borrow_mut().remove(&(
-            token.clone(),
-            chain.clone(),
-            account.clone(),
-            dc_canister.clone(),
+    // add the withheld amount back to the available balance
+    let postings = vec![
+        ledger::debit(
+            &token,
+            &chain,
+            LedgerAccount::Withheld {
+                account: account.clone(),
+                dc_canister,
+            },
+            amount_canceled.clone(),
+        ),
+        ledger::credit(
+            &token,
+            &chain,
+            LedgerAccount::Available {
+                account,
+                dc_canister,
+            },
             amount_canceled,
-        ));
-    });
-
-    // add the withheld total back to the available balance
-    crate::REMITTANCE.with(|remittance| {
-        if let Some(existing_data) = remittance.borrow_mut().get_mut(&hash_key) {
-            existing_data.balance = existing_data.balance + amount_canceled;
-        }
-    });
+        ),
+    ];
+    ledger::post(
+        EntryKind::Cancellation,
+        Some(canceled_details.nonce.to_string()),
+        postings,
+        now,
+    )?;
 
-    return true;
+    Ok(())
 }
 
 // use the right validator depending on if the caller is a pdc or not
 pub fn validate_remittance_data(
     is_pdc: bool,
-    new_remittances: &Vec<lib::DataModel>,
+    new_remittances: &[lib::DataModel],
     dc_canister: Principal,
-) -> Result<(), String> {
+) -> Result<(), CcampError> {
+    // make sure every chain referenced has been registered
+    if let Some(unregistered) = new_remittances
+        .iter()
+        .find(|item| !lib::chain::is_registered(&item.chain))
+    {
+        return Err(CcampError::ChainNotRegistered {
+            chain: unregistered.chain.to_string(),
+        });
+    }
+    // a balance which can't be withdrawn through a locker of its chain would be stuck in the canister
+    if let Some(address) = new_remittances
+        .iter()
+        .flat_map(|item| {
+            let signature_scheme = item.chain.signature_scheme();
+            [&item.account, &item.token]
+                .into_iter()
+                .filter(move |address| !address.is_withdrawable(&signature_scheme))
+        })
+        .next()
+    {
+        return Err(CcampError::InvalidAddress {
+            address: address.to_string(),
+            reason: String::from("UNSUPPORTED_ADDRESS_FORMAT"),
+        });
+    }
+
     match is_pdc {
         true => validate_pdc_remittance_data(new_remittances, dc_canister),
         false => validate_dc_remittance_data(new_remittances, dc_canister),
@@ -340,92 +800,792 @@ pub fn validate_remittance_data(
 }
 
 pub fn validate_pdc_remittance_data(
-    new_remittances: &Vec<lib::DataModel>,
+    new_remittances: &[lib::DataModel],
     dc_canister: Principal,
-) -> Result<(), String> {
+) -> Result<(), CcampError> {
     // validate that all adjust operations lead to a sum of zero
     let adjust_operations: Vec<lib::DataModel> = new_remittances
-        .into_iter()
+        .iter()
         .filter(|&single_remittance| single_remittance.action == lib::Action::Adjust)
         .cloned()
         .collect();
     // apply the same validation of dc canisters to the adjust operations of a pdc canister
-    if let Err(err_message) = validate_dc_remittance_data(&adjust_operations, dc_canister) {
-        return Err(err_message);
-    };
+    validate_dc_remittance_data(&adjust_operations, dc_canister)?;
 
     // validate that all operations that are not "adjust" operations are positive amounts
     // other than adjusts we currently have no use for negative amounts operations
     // this can be later changed
-    let non_adjust_operations_gt_0: Vec<&lib::DataModel> = new_remittances
-        .into_iter()
-        .filter(|single_remittance| {
-            single_remittance.action != lib::Action::Adjust && single_remittance.amount < 0
-        })
-        .collect();
-    if non_adjust_operations_gt_0.len() > 0 {
-        return Err("NON_ADJUST_AMOUNT_MUST_BE_GT_0".to_string());
+    let non_adjust_operation_lt_0 = new_remittances.iter().find(|single_remittance| {
+        single_remittance.action != lib::Action::Adjust
+            && lib::amount::is_negative(&single_remittance.amount)
+    });
+    if let Some(operation) = non_adjust_operation_lt_0 {
+        return Err(CcampError::InvalidAmount {
+            amount: operation.amount.clone(),
+        });
     };
 
+    // the events which happened on chain have to carry their identity so they are only credited once
+    if let Some(operation) = new_remittances.iter().find(|single_remittance| {
+        single_remittance.action != lib::Action::Adjust && single_remittance.identity.is_none()
+    }) {
+        return Err(CcampError::InvalidEvent {
+            reason: format!("MISSING_EVENT_IDENTITY:{}", operation),
+        });
+    };
+
+    // a withdrawal or cancellation resolves the signed ticket of its nonce
+    for operation in new_remittances.iter().filter(|single_remittance| {
+        matches!(
+            single_remittance.action,
+            lib::Action::Withdraw | lib::Action::CancelWithdraw
+        )
+    }) {
+        let nonce = operation.nonce.ok_or_else(|| CcampError::InvalidEvent {
+            reason: format!("MISSING_WITHDRAWAL_NONCE:{}", operation),
+        })?;
+        tickets::find_signed(
+            nonce,
+            &operation.token,
+            &operation.chain,
+            &operation.account,
+            &dc_canister,
+            &lib::amount::abs(&operation.amount),
+        )?;
+    }
+
     Ok(())
 }
 
+// sum up the amounts of the items sharing the same key, in the order the keys first appear
+fn net_amounts<K: PartialEq>(
+    new_remittances: &[lib::DataModel],
+    key: impl Fn(&lib::DataModel) -> K,
+) -> Vec<(K, Int)> {
+    let mut net_amounts: Vec<(K, Int)> = Vec::new();
+    for item in new_remittances {
+        let item_key = key(item);
+        match net_amounts.iter_mut().find(|(other, _)| *other == item_key) {
+            Some((_, net_amount)) => *net_amount = net_amount.clone() + item.amount.clone(),
+            None => net_amounts.push((item_key, item.amount.clone())),
+        }
+    }
+
+    net_amounts
+}
+
 // validate data for an ordinary dc canister
+// a batch can settle several assets at once e.g a swap, but the amounts of every asset have to sum up to zero
 pub fn validate_dc_remittance_data(
-    new_remittances: &Vec<lib::DataModel>,
+    new_remittances: &[lib::DataModel],
     dc_canister: Principal,
-) -> Result<(), String> {
-    // validate that all operations are adjust and the resultant of amounts is zero
-    let amount_delta = new_remittances
-        .iter()
-        .fold(0, |acc, account| acc + account.amount);
-
-    if amount_delta != 0 {
-        return Err("SUM_ADJUST_AMOUNTS != 0".to_string());
+) -> Result<(), CcampError> {
+    // validate that all operations are adjust and the resultant of amounts is zero for each asset
+    if let Some((asset, delta)) = net_amounts(new_remittances, lib::DataModel::asset)
+        .into_iter()
+        .find(|(_, delta)| *delta != 0)
+    {
+        return Err(CcampError::UnbalancedAdjustment { asset, delta });
     }
 
     // validate it is only adjust action provided
-    let is_action_valid = new_remittances
+    let invalid_action = new_remittances
         .iter()
-        .all(|item| item.action == lib::Action::Adjust);
+        .find(|item| item.action != lib::Action::Adjust);
 
-    if !is_action_valid {
-        return Err("INVALID_ACTION_FOUND".to_string());
+    if let Some(item) = invalid_action {
+        return Err(CcampError::InvalidAction {
+            action: format!("{:?}", item.action),
+        });
     }
 
-    // check for all the negative deductions and confirm that the owners have at least that much balance
-    let mut sufficient_balance_error: Result<(), String> = Ok(());
-    new_remittances
-        .iter()
-        .filter(|&item| item.amount < 0)
-        .for_each(|item| {
-            let existing_balance = get_available_balance(
-                item.token.clone(),
-                item.chain.clone(),
-                item.account.clone(),
-                dc_canister.clone(),
-            );
+    // check for all the net deductions of an account and confirm that the owner has at least that much balance
+    let account_deltas = net_amounts(new_remittances, |item| (item.asset(), item.account.clone()));
+    for ((asset, account), delta) in account_deltas {
+        if !lib::amount::is_negative(&delta) {
+            continue;
+        }
 
-            if existing_balance.balance < item.amount.abs() as u64 {
-                sufficient_balance_error = Err("INSUFFICIENT_USER_BALANCE".to_string());
-            };
-        });
-    if let Err(_) = sufficient_balance_error {
-        return sufficient_balance_error;
+        let existing_balance =
+            get_available_balance(asset.token, asset.chain, account, dc_canister);
+        if existing_balance.balance < lib::amount::abs(&delta) {
+            return Err(CcampError::InsufficientBalance {
+                requested: lib::amount::abs(&delta),
+                available: existing_balance.balance,
+            });
+        }
     }
     // check for all positive additions that the canister has enough balance to cover it
-    let mut insufficient_canister_balance: Result<(), String> = Ok(());
+    let mut insufficient_canister_balance: Result<(), CcampError> = Ok(());
     new_remittances
         .iter()
-        .filter(|&item| item.amount > 0)
+        .filter(|&item| lib::amount::is_positive(&item.amount))
         .for_each(|item| {
             let existing_balance =
-                get_canister_balance(item.token.clone(), item.chain.clone(), dc_canister.clone());
+                get_canister_balance(item.token.clone(), item.chain.clone(), dc_canister);
 
-            if existing_balance.balance < item.amount as u64 {
-                insufficient_canister_balance = Err("INSUFFICIENT_CANISTER_BALANCE".to_string());
+            if existing_balance.balance < lib::amount::abs(&item.amount) {
+                insufficient_canister_balance = Err(CcampError::InsufficientCanisterBalance {
+                    requested: lib::amount::abs(&item.amount),
+                    available: existing_balance.balance,
+                });
             };
         });
 
     insufficient_canister_balance
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{executor::block_on, future::join};
+    use lib::signer::{LocalEd25519Signer, LocalSigner};
+    use rand::SeedableRng;
+    use std::task::Poll;
+
+    const CHAIN: &str = "ethereum:5";
+    const TOKEN: &str = "0xB24a30A3971e4d9bf771BDc81435c25EA69A445c";
+    const DC_CANISTER: &str = "bkyz2-fmaaa-aaaaa-qaaaq-cai";
+    const LOCKER: &str = "0xadBBdcdbF9B77720775103dd126346686C1d9011";
+
+    // the canister signers and the wallet of the user requesting the remittance
+    fn setup(balance: u64) -> (Signers<LocalSigner, LocalEd25519Signer>, LocalSigner) {
+        crate::random::RNG.with(|rng| *rng.borrow_mut() = Some(StdRng::seed_from_u64(0)));
+        // most tests remit with proofs which only sign the amount
+        crate::ALLOW_LEGACY_PROOFS.with(|allow| *allow.borrow_mut() = true);
+        lib::chain::register_chain(
+            CHAIN.to_string().try_into().unwrap(),
+            lib::chain::ChainMetadata {
+                name: String::from("Ethereum Goerli"),
+                finality_depth: 64,
+                locker_address: Some(LOCKER.to_string()),
+            },
+        );
+
+        let user = LocalSigner::from_seed(b"user");
+        let (token, chain, account, dc_canister) = balance_key(&user);
+        deposit(token, chain, account, dc_canister, balance);
+
+        (signers(), user)
+    }
+
+    fn deposit(
+        token: lib::Address,
+        chain: lib::Chain,
+        account: lib::Address,
+        dc_canister: Principal,
+        amount: u64,
+    ) {
+        let deposit = lib::DataModel {
+            token,
+            chain,
+            amount: Int::from(amount),
+            account,
+            action: lib::Action::Deposit,
+            identity: None,
+            nonce: None,
+        };
+        record_deposit(&deposit, dc_canister, 0).unwrap();
+    }
+
+    fn signers() -> Signers<LocalSigner, LocalEd25519Signer> {
+        Signers {
+            ecdsa: LocalSigner::from_seed(b"remittance"),
+            ed25519: LocalEd25519Signer::from_seed(b"remittance"),
+        }
+    }
+
+    // suspends every signature once so other calls run in the meantime
+    // like they do while the canister awaits the management canister
+    #[derive(Clone)]
+    struct SuspendingSigner {
+        signer: LocalSigner,
+        fails: bool,
+    }
+    impl Signer for SuspendingSigner {
+        fn derive(&self, derivation_path: Vec<Vec<u8>>) -> Self {
+            Self {
+                signer: self.signer.derive(derivation_path),
+                fails: self.fails,
+            }
+        }
+
+        async fn public_key(&self) -> Result<Vec<u8>, String> {
+            self.signer.public_key().await
+        }
+
+        async fn sign_digest(&self, message_hash: &[u8]) -> Result<Vec<u8>, String> {
+            let mut suspended = false;
+            futures::future::poll_fn(|context| match suspended {
+                true => Poll::Ready(()),
+                false => {
+                    suspended = true;
+                    context.waker().wake_by_ref();
+                    Poll::Pending
+                }
+            })
+            .await;
+
+            match self.fails {
+                true => Err(String::from("SIGNING_UNAVAILABLE")),
+                false => self.signer.sign_digest(message_hash).await,
+            }
+        }
+    }
+
+    fn suspending_signers(fails: bool) -> Signers<SuspendingSigner, LocalEd25519Signer> {
+        let signers = signers();
+
+        Signers {
+            ecdsa: SuspendingSigner {
+                signer: signers.ecdsa,
+                fails,
+            },
+            ed25519: signers.ed25519,
+        }
+    }
+
+    fn address_of(signer: &LocalSigner) -> String {
+        let public_key = block_on(signer.public_key()).unwrap();
+
+        ethereum::get_address_from_public_key(public_key).unwrap()
+    }
+
+    fn balance_key(user: &LocalSigner) -> (lib::Address, lib::Chain, lib::Address, Principal) {
+        (
+            TOKEN.to_string().try_into().unwrap(),
+            CHAIN.to_string().try_into().unwrap(),
+            address_of(user).try_into().unwrap(),
+            Principal::from_text(DC_CANISTER).unwrap(),
+        )
+    }
+
+    fn available_balance(user: &LocalSigner) -> Nat {
+        let (token, chain, account, dc_canister) = balance_key(user);
+
+        get_available_balance(token, chain, account, dc_canister).balance
+    }
+
+    fn request(
+        user: &LocalSigner,
+        amount: u64,
+        proof_parameters: Option<ProofParameters>,
+    ) -> RemitRequest {
+        let (token, chain, account, dc_canister) = balance_key(user);
+        let amount = Nat::from(amount);
+        let message = match &proof_parameters {
+            Some(parameters) => {
+                remit_proof_message(&token, &chain, &account, &dc_canister, &amount, parameters)
+            }
+            None => lib::amount::to_decimal_string(&amount),
+        };
+        let proof = block_on(ethereum::sign_message(message.as_bytes(), user)).unwrap();
+
+        RemitRequest {
+            token: TOKEN.to_string(),
+            chain: CHAIN.to_string(),
+            account: address_of(user),
+            dc_canister: Principal::from_text(DC_CANISTER).unwrap(),
+            amount,
+            proof: format!("0x{}", proof.signature_hex),
+            proof_parameters,
+        }
+    }
+
+    fn recover_signer(digest: &[u8], signature: &str) -> String {
+        let (signature, recovery_id) = ethereum::parse_eth_signature(signature).unwrap();
+        let message = libsecp256k1::Message::parse(&digest.try_into().unwrap());
+        let public_key = libsecp256k1::recover(&message, &signature, &recovery_id).unwrap();
+
+        ethereum::get_address_from_public_key(public_key.serialize_compressed().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn remit_signature_is_accepted_by_locker() {
+        let (signers, user) = setup(1_000);
+
+        let reply = block_on(remit(&signers, 0, request(&user, 400, None))).unwrap();
+
+        // keccak256(abi.encodePacked(_nonce, _amount, msg.sender, chainId, _canisterId, _token))
+        let mut packed = vec![0u8; 24];
+        packed.extend_from_slice(&reply.nonce.to_be_bytes());
+        packed.extend_from_slice(&[0u8; 30]);
+        packed.extend_from_slice(&400u16.to_be_bytes());
+        packed.extend_from_slice(&hex::decode(&address_of(&user)[2..]).unwrap());
+        packed.extend_from_slice(CHAIN.as_bytes());
+        packed.extend_from_slice(DC_CANISTER.as_bytes());
+        packed.extend_from_slice(&hex::decode(&TOKEN[2..]).unwrap());
+        let data_hash = easy_hasher::raw_keccak256(packed).to_vec();
+
+        assert_eq!(reply.hash, format!("0x{}", hex::encode(&data_hash)));
+        assert_eq!(
+            recover_signer(&ethereum::hash_eth_message(&data_hash), &reply.signature),
+            address_of(&signers.ecdsa)
+        );
+        assert_eq!(available_balance(&user), Nat::from(600u64));
+    }
+
+    #[test]
+    fn remit_eip712_signature_recovers_to_signer() {
+        let (signers, user) = setup(1_000);
+        crate::SIGNING_MODE.with(|mode| *mode.borrow_mut() = SigningMode::Eip712);
+
+        let reply = block_on(remit(&signers, 0, request(&user, 400, None))).unwrap();
+        let digest = hash_withdrawal_typed_data(
+            reply.nonce,
+            &Nat::from(400u64),
+            &address_of(&user),
+            &CHAIN.to_string().try_into().unwrap(),
+            DC_CANISTER,
+            TOKEN,
+        )
+        .unwrap();
+
+        assert_eq!(
+            recover_signer(&digest, &reply.signature),
+            address_of(&signers.ecdsa)
+        );
+    }
+
+    #[test]
+    fn remit_rejects_a_replayed_legacy_proof_of_an_outstanding_amount() {
+        let (signers, user) = setup(1_000);
+
+        block_on(remit(&signers, 0, request(&user, 400, None))).unwrap();
+        let error = block_on(remit(&signers, 0, request(&user, 400, None))).unwrap_err();
+
+        assert_eq!(
+            error,
+            CcampError::RemittanceInProgress {
+                amount: Nat::from(400u64)
+            }
+        );
+        assert_eq!(available_balance(&user), Nat::from(600u64));
+    }
+
+    #[test]
+    fn remit_rejects_insufficient_balance() {
+        let (signers, user) = setup(100);
+
+        let error = block_on(remit(&signers, 0, request(&user, 400, None))).unwrap_err();
+
+        assert_eq!(
+            error,
+            CcampError::InsufficientBalance {
+                requested: Nat::from(400u64),
+                available: Nat::from(100u64),
+            }
+        );
+    }
+
+    #[test]
+    fn concurrent_remits_cannot_spend_the_same_balance() {
+        let (_, user) = setup(1_000);
+        let signers = suspending_signers(false);
+
+        // the second call checks the balance while the first one awaits its signature
+        let (first, second) = block_on(join(
+            remit(&signers, 0, request(&user, 600, None)),
+            remit(&signers, 0, request(&user, 700, None)),
+        ));
+        assert!(first.is_ok());
+        assert_eq!(
+            second.unwrap_err(),
+            CcampError::InsufficientBalance {
+                requested: Nat::from(700u64),
+                available: Nat::from(400u64),
+            }
+        );
+        assert_eq!(available_balance(&user), Nat::from(400u64));
+
+        // a legacy proof of the same amount is rejected while its ticket is outstanding, signed or not
+        let (first, second) = block_on(join(
+            remit(&signers, 0, request(&user, 300, None)),
+            remit(&signers, 0, request(&user, 300, None)),
+        ));
+        let in_progress = CcampError::RemittanceInProgress {
+            amount: Nat::from(300u64),
+        };
+        assert!(first.is_ok());
+        assert_eq!(second.unwrap_err(), in_progress);
+        let again = block_on(remit(&signers, 0, request(&user, 300, None)));
+        assert_eq!(again.unwrap_err(), in_progress);
+        assert_eq!(available_balance(&user), Nat::from(100u64));
+        assert!(crate::LEDGER.with(|ledger| ledger.borrow().is_consistent()));
+    }
+
+    #[test]
+    fn failed_signatures_release_the_reserved_amount() {
+        let (_, user) = setup(1_000);
+        let signers = suspending_signers(true);
+
+        // the amount is reserved while the signature is awaited
+        let (first, second) = block_on(join(
+            remit(&signers, 0, request(&user, 600, None)),
+            remit(&signers, 0, request(&user, 700, None)),
+        ));
+        assert!(matches!(first, Err(CcampError::SigningFailed { .. })));
+        assert!(matches!(
+            second,
+            Err(CcampError::InsufficientBalance { .. })
+        ));
+
+        // and given back once the signature failed
+        let (token, chain, account, dc_canister) = balance_key(&user);
+        assert_eq!(available_balance(&user), Nat::from(1_000u64));
+        assert_eq!(
+            get_withheld_balance(token, chain, account, dc_canister).balance,
+            Nat::from(0u64)
+        );
+        assert_eq!(crate::solvency::reconcile(0).discrepancies, vec![]);
+    }
+
+    #[test]
+    fn remit_rejects_proof_of_another_account() {
+        let (signers, user) = setup(1_000);
+        let mut remit_request = request(&user, 400, None);
+        remit_request.proof = request(&signers.ecdsa, 400, None).proof;
+
+        let error = block_on(remit(&signers, 0, remit_request)).unwrap_err();
+
+        assert!(matches!(error, CcampError::SignatureMismatch { .. }));
+    }
+
+    #[test]
+    fn remit_rejects_replayed_and_expired_proofs() {
+        let (signers, user) = setup(1_000);
+        let parameters = ProofParameters {
+            nonce: 0,
+            expiry: 100,
+        };
+
+        block_on(remit(
+            &signers,
+            50,
+            request(&user, 400, Some(parameters.clone())),
+        ))
+        .unwrap();
+        let replayed = block_on(remit(&signers, 50, request(&user, 400, Some(parameters))));
+        let expired = block_on(remit(
+            &signers,
+            100,
+            request(
+                &user,
+                400,
+                Some(ProofParameters {
+                    nonce: 1,
+                    expiry: 100,
+                }),
+            ),
+        ));
+
+        assert_eq!(
+            replayed.unwrap_err(),
+            CcampError::InvalidProofNonce {
+                expected: 1,
+                received: 0,
+            }
+        );
+        assert_eq!(
+            expired.unwrap_err(),
+            CcampError::ProofExpired {
+                expiry: 100,
+                now: 100,
+            }
+        );
+    }
+
+    #[test]
+    fn withdrawals_resolve_the_ticket_of_their_nonce() {
+        let (signers, user) = setup(1_000);
+        let (token, chain, account, dc_canister) = balance_key(&user);
+        let first = block_on(remit(&signers, 0, request(&user, 400, None))).unwrap();
+        let second = block_on(remit(&signers, 0, request(&user, 300, None))).unwrap();
+        let withdrawal = |nonce: Option<u64>, amount: u64| lib::DataModel {
+            token: token.clone(),
+            chain: chain.clone(),
+            amount: Int::from(amount),
+            account: account.clone(),
+            action: lib::Action::Withdraw,
+            identity: Some(lib::EventIdentity {
+                chain: chain.clone(),
+                transaction_hash: format!("0x{}", "22".repeat(32)),
+                log_index: 0,
+                block_hash: format!("0x{}", "ff".repeat(32)),
+            }),
+            nonce,
+        };
+
+        // the event has to carry the nonce of a signed ticket it matches
+        assert!(matches!(
+            validate_pdc_remittance_data(&[withdrawal(None, 300)], dc_canister),
+            Err(CcampError::InvalidEvent { .. })
+        ));
+        assert_eq!(
+            validate_pdc_remittance_data(&[withdrawal(Some(second.nonce), 400)], dc_canister),
+            Err(CcampError::UnmatchedWithdrawalTicket {
+                nonce: second.nonce
+            })
+        );
+        assert_eq!(
+            validate_pdc_remittance_data(&[withdrawal(Some(second.nonce), 300)], dc_canister),
+            Ok(())
+        );
+
+        let confirm = || {
+            confirm_withdrawal(
+                second.nonce,
+                TOKEN.to_string(),
+                CHAIN.to_string(),
+                account.to_string(),
+                Nat::from(300u64),
+                dc_canister,
+                0,
+            )
+        };
+        confirm().unwrap();
+        assert!(tickets::contains(first.nonce));
+        assert!(!tickets::contains(second.nonce));
+        // a ticket is only resolved once
+        assert_eq!(
+            confirm(),
+            Err(CcampError::UnmatchedWithdrawalTicket {
+                nonce: second.nonce
+            })
+        );
+    }
+
+    #[test]
+    fn remit_keeps_previous_keys_until_their_signatures_settle() {
+        let (signers, user) = setup(1_000);
+        let (_, chain, account, dc_canister) = balance_key(&user);
+
+        let legacy = block_on(remit(&signers, 0, request(&user, 400, None))).unwrap();
+        let version = lib::keys::rotate(true, 0).unwrap();
+
+        // nothing is signed with the rotated key until its addresses are registered and it is activated
+        let pending = block_on(remit(&signers, 0, request(&user, 100, None))).unwrap();
+        let data_hash = hex::decode(&pending.hash[2..]).unwrap();
+        assert_eq!(
+            recover_signer(&ethereum::hash_eth_message(&data_hash), &pending.signature),
+            address_of(&signers.ecdsa)
+        );
+        cancel_withdrawal(
+            pending.nonce,
+            TOKEN.to_string(),
+            CHAIN.to_string(),
+            account.to_string(),
+            Nat::from(100u64),
+            dc_canister,
+            0,
+        )
+        .unwrap();
+
+        lib::keys::activate(version).unwrap();
+        let reply = block_on(remit(&signers, 0, request(&user, 200, None))).unwrap();
+
+        // the new withdrawal is signed by the key of the chain and data collection canister
+        let key_id = lib::keys::key_id(version, Some(&chain), Some(&dc_canister)).unwrap();
+        let rotated_signer = signers.ecdsa.derive(key_id.derivation_path());
+        let data_hash = hex::decode(&reply.hash[2..]).unwrap();
+        assert_ne!(address_of(&rotated_signer), address_of(&signers.ecdsa));
+        assert_eq!(
+            recover_signer(&ethereum::hash_eth_message(&data_hash), &reply.signature),
+            address_of(&rotated_signer)
+        );
+
+        // the legacy key is retired once the withdrawal it signed is cancelled
+        assert!(retire_unused_keys().is_empty());
+        cancel_withdrawal(
+            legacy.nonce,
+            TOKEN.to_string(),
+            CHAIN.to_string(),
+            account.to_string(),
+            Nat::from(400u64),
+            dc_canister,
+            0,
+        )
+        .unwrap();
+        assert_eq!(retire_unused_keys(), vec![lib::keys::LEGACY_KEY_VERSION]);
+        assert_eq!(
+            lib::keys::key_id(lib::keys::LEGACY_KEY_VERSION, None, None),
+            Err(CcampError::UnknownKeyVersion {
+                version: lib::keys::LEGACY_KEY_VERSION
+            })
+        );
+    }
+
+    #[test]
+    fn remit_signs_solana_withdrawals_with_ed25519() {
+        let (signers, _) = setup(0);
+        let chain = "solana:5eykt4UsFv8P8NJdTREpY1vzqKqZKvdp";
+        let token = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+        lib::chain::register_chain(
+            chain.to_string().try_into().unwrap(),
+            lib::chain::ChainMetadata {
+                name: String::from("Solana Mainnet"),
+                finality_depth: 32,
+                locker_address: None,
+            },
+        );
+        let user = LocalEd25519Signer::from_seed(b"user");
+        let user_public_key = block_on(user.public_key()).unwrap();
+        let account = lib::Address::Solana(user_public_key.clone());
+        let dc_canister = Principal::from_text(DC_CANISTER).unwrap();
+        deposit(
+            lib::Address::parse_solana(token).unwrap(),
+            chain.to_string().try_into().unwrap(),
+            account.clone(),
+            dc_canister,
+            1_000,
+        );
+
+        let proof = block_on(user.sign(b"400")).unwrap();
+        let reply = block_on(remit(
+            &signers,
+            0,
+            RemitRequest {
+                token: token.to_string(),
+                chain: chain.to_string(),
+                account: account.to_string(),
+                dc_canister,
+                amount: Nat::from(400u64),
+                proof: hex::encode(proof),
+                proof_parameters: None,
+            },
+        ))
+        .unwrap();
+
+        let message = hex::decode(&reply.hash[2..]).unwrap();
+        assert!(message.starts_with(SOLANA_WITHDRAWAL_PREFIX.as_bytes()));
+        assert_eq!(&message[16..24], &reply.nonce.to_le_bytes());
+        assert_eq!(&message[24..32], &400u64.to_le_bytes());
+        assert_eq!(&message[32..64], &user_public_key[..]);
+        let canister_public_key = block_on(signers.ed25519.public_key()).unwrap();
+        assert_eq!(
+            lib::schnorr::verify_ed25519(&canister_public_key, &message, &reply.signature),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn adjustments_balance_per_asset() {
+        let chain: lib::Chain = CHAIN.to_string().try_into().unwrap();
+        let dc_canister = Principal::from_text(DC_CANISTER).unwrap();
+        let token: lib::Address = TOKEN.to_string().try_into().unwrap();
+        let other_token: lib::Address = String::from("0x1c7D4B196Cb0C7B01d743Fbc6116a902379C7238")
+            .try_into()
+            .unwrap();
+        let alice: lib::Address = String::from("0x9C81E8F60a9B8743678F1b6Ae893Cc72c6Bc6840")
+            .try_into()
+            .unwrap();
+        let bob: lib::Address = String::from("0x1a7f0c9f8d0e1a7b4a7d1b6c7ed3e3a9a3c5b9d1")
+            .try_into()
+            .unwrap();
+        deposit(
+            token.clone(),
+            chain.clone(),
+            alice.clone(),
+            dc_canister,
+            100,
+        );
+        deposit(
+            other_token.clone(),
+            chain.clone(),
+            bob.clone(),
+            dc_canister,
+            50,
+        );
+        let adjust = |token: &lib::Address, account: &lib::Address, amount: i64| lib::DataModel {
+            token: token.clone(),
+            chain: chain.clone(),
+            amount: Int::from(amount),
+            account: account.clone(),
+            action: lib::Action::Adjust,
+            identity: None,
+            nonce: None,
+        };
+
+        // a swap settles both assets in the same batch, each of them balancing on its own
+        let swap = vec![
+            adjust(&token, &alice, -100),
+            adjust(&token, &bob, 100),
+            adjust(&other_token, &bob, -50),
+            adjust(&other_token, &alice, 50),
+        ];
+        assert_eq!(validate_dc_remittance_data(&swap, dc_canister), Ok(()));
+        record_adjustments(&swap, dc_canister, 0).unwrap();
+        let balance = |token: &lib::Address, account: &lib::Address| {
+            get_available_balance(token.clone(), chain.clone(), account.clone(), dc_canister)
+                .balance
+        };
+        assert_eq!(balance(&token, &bob), Nat::from(100u64));
+        assert_eq!(balance(&other_token, &alice), Nat::from(50u64));
+
+        // the amounts of a token can't make up for the amounts of another one
+        let mismatched = vec![adjust(&token, &bob, -50), adjust(&other_token, &bob, 50)];
+        assert_eq!(
+            validate_dc_remittance_data(&mismatched, dc_canister),
+            Err(CcampError::UnbalancedAdjustment {
+                asset: lib::Asset {
+                    token: token.clone(),
+                    chain: chain.clone()
+                },
+                delta: Int::from(-50)
+            })
+        );
+
+        // the deductions of an account are checked against its balance together
+        let overdraft = vec![
+            adjust(&token, &bob, -60),
+            adjust(&token, &bob, -60),
+            adjust(&token, &alice, 120),
+        ];
+        assert_eq!(
+            validate_dc_remittance_data(&overdraft, dc_canister),
+            Err(CcampError::InsufficientBalance {
+                requested: Nat::from(120u64),
+                available: Nat::from(100u64)
+            })
+        );
+    }
+
+    #[test]
+    fn balances_which_cant_be_withdrawn_are_rejected() {
+        setup(0);
+        let dc_canister = Principal::from_text(DC_CANISTER).unwrap();
+        let deposit_of = |account: &str| lib::DataModel {
+            token: TOKEN.to_string().try_into().unwrap(),
+            chain: CHAIN.to_string().try_into().unwrap(),
+            amount: Int::from(100),
+            account: account.to_string().try_into().unwrap(),
+            action: lib::Action::Deposit,
+            identity: Some(lib::EventIdentity {
+                chain: CHAIN.to_string().try_into().unwrap(),
+                transaction_hash: format!("0x{}", "11".repeat(32)),
+                log_index: 0,
+                block_hash: format!("0x{}", "ff".repeat(32)),
+            }),
+            nonce: None,
+        };
+
+        // neither a tron nor an icp account can be paid out by the locker of an evm chain
+        for account in ["TR7NHqjeKQxGTCi8q8ZY4pL8otSzgjLj6t", DC_CANISTER] {
+            assert_eq!(
+                validate_remittance_data(true, &[deposit_of(account)], dc_canister),
+                Err(CcampError::InvalidAddress {
+                    address: account.to_string(),
+                    reason: String::from("UNSUPPORTED_ADDRESS_FORMAT")
+                })
+            );
+        }
+        assert_eq!(
+            validate_remittance_data(
+                true,
+                &[deposit_of("0x9C81E8F60a9B8743678F1b6Ae893Cc72c6Bc6840")],
+                dc_canister
+            ),
+            Ok(())
+        );
+    }
+}
@@ -0,0 +1,335 @@
+// the funds held by a data collection canister have to cover everything owed to its users
+// the reconciliation checks it for every asset against the balances of the Locker and can stop `remit` when it doesn't hold
+use candid::{CandidType, Nat, Principal};
+use lib::{pause::Operation, Asset};
+use serde_derive::Deserialize;
+use std::{collections::HashMap, time::Duration};
+
+use crate::ledger::LedgerAccount;
+
+// how often the timer reconciles the balances
+pub const RECONCILIATION_INTERVAL: Duration = Duration::from_secs(3_600);
+
+// the balances of an asset of a data collection canister
+#[derive(Clone, Debug, Default, CandidType, Deserialize, PartialEq)]
+pub struct AssetBalances {
+    // the funds locked in the contracts
+    pub pool: Nat,
+    // the sum of the balances the users can remit
+    pub available: Nat,
+    // the sum of the balances remitted but not withdrawn or cancelled yet
+    pub withheld: Nat,
+    pub fees: Nat,
+    // the funds the pool held beyond or lacked to cover the balances of its users when the ledger was opened
+    pub opening_surplus: Nat,
+    pub opening_shortfall: Nat,
+    // the sum of the signed remittances waiting to be withdrawn or cancelled
+    pub withheld_remittances: Nat,
+    // the sum of the remittances whose signature is being made
+    pub reserved: Nat,
+    // the sum of the deposits held in the Locker whose block isn't final yet
+    pub pending: Nat,
+    // the balance of the data collection canister last reported from the Locker, none until one is reported
+    pub locker: Option<Nat>,
+}
+impl AssetBalances {
+    // the pool has to hold what is owed to the users and the protocol, along with the surplus it was opened with
+    // and the withheld balances have to match the remittances which were signed or are being signed
+    // the Locker has to hold the pool along with the deposits which aren't final yet
+    // a pool which lacked funds when it was opened doesn't hold what is owed to its users
+    pub fn is_solvent(&self) -> bool {
+        self.opening_shortfall == 0u64
+            && self.pool
+                == self.available.clone()
+                    + self.withheld.clone()
+                    + self.fees.clone()
+                    + self.opening_surplus.clone()
+            && self.withheld == self.withheld_remittances.clone() + self.reserved.clone()
+            && self
+                .locker
+                .as_ref()
+                .is_none_or(|locker| *locker == self.pool.clone() + self.pending.clone())
+    }
+}
+
+// the balance of a data collection canister read from the Locker of a chain i.e `getBalance(canisterId, token)`
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub struct LockerBalance {
+    pub balance: Nat,
+    // timestamp in nanoseconds
+    pub reported_at: u64,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub struct Discrepancy {
+    pub asset: Asset,
+    pub dc_canister: Principal,
+    pub balances: AssetBalances,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub struct ReconciliationReport {
+    // timestamp in nanoseconds
+    pub timestamp: u64,
+    // the number of (token, chain, dc_canister) combinations which were checked
+    pub assets_checked: u64,
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+#[derive(Clone, Debug, Default, CandidType, Deserialize, PartialEq)]
+pub struct SolvencyState {
+    // whether `remit` is paused once the timer finds a discrepancy
+    pub auto_pause: bool,
+    // the report of the last reconciliation made by the timer
+    pub last_report: Option<ReconciliationReport>,
+    // the balances last reported from the Lockers
+    pub locker_balances: HashMap<(Asset, Principal), LockerBalance>,
+}
+
+// compare the pool of every asset with the balances owed to its users and the balance reported from its Locker
+pub fn reconcile(now: u64) -> ReconciliationReport {
+    let mut assets: HashMap<(Asset, Principal), AssetBalances> = HashMap::new();
+
+    crate::LEDGER.with(|ledger| {
+        for ((token, chain, account), balance) in &ledger.borrow().balances {
+            let asset = Asset {
+                token: token.clone(),
+                chain: chain.clone(),
+            };
+            let balances = assets.entry((asset, account.dc_canister())).or_default();
+            let sum = match account {
+                LedgerAccount::Pool { .. } => &mut balances.pool,
+                LedgerAccount::Available { .. } => &mut balances.available,
+                LedgerAccount::Withheld { .. } => &mut balances.withheld,
+                LedgerAccount::Fees { .. } => &mut balances.fees,
+                LedgerAccount::OpeningSurplus { .. } => &mut balances.opening_surplus,
+                LedgerAccount::OpeningShortfall { .. } => &mut balances.opening_shortfall,
+            };
+            *sum += balance.clone();
+        }
+    });
+    crate::TICKETS.with(|tickets| {
+        for ticket in tickets.borrow().values() {
+            let asset = Asset {
+                token: ticket.token.clone(),
+                chain: ticket.chain.clone(),
+            };
+            let balances = assets.entry((asset, ticket.dc_canister)).or_default();
+            let sum = match ticket.signature {
+                Some(_) => &mut balances.withheld_remittances,
+                None => &mut balances.reserved,
+            };
+            *sum += ticket.amount.clone();
+        }
+    });
+    crate::PENDING_DEPOSITS.with(|pending| {
+        for pending_deposit in pending.borrow().deposits.values() {
+            let balances = assets
+                .entry((pending_deposit.deposit.asset(), pending_deposit.dc_canister))
+                .or_default();
+            balances.pending += lib::amount::abs(&pending_deposit.deposit.amount);
+        }
+    });
+    crate::SOLVENCY.with(|solvency| {
+        for (key, locker_balance) in &solvency.borrow().locker_balances {
+            let balances = assets.entry(key.clone()).or_default();
+            balances.locker = Some(locker_balance.balance.clone());
+        }
+    });
+
+    let assets_checked = assets.len() as u64;
+    let mut discrepancies: Vec<Discrepancy> = assets
+        .into_iter()
+        .filter(|(_, balances)| !balances.is_solvent())
+        .map(|((asset, dc_canister), balances)| Discrepancy {
+            asset,
+            dc_canister,
+            balances,
+        })
+        .collect();
+    discrepancies.sort_by_cached_key(|discrepancy| {
+        (
+            discrepancy.dc_canister,
+            discrepancy.asset.chain.clone(),
+            discrepancy.asset.token.to_string(),
+        )
+    });
+
+    ReconciliationReport {
+        timestamp: now,
+        assets_checked,
+        discrepancies,
+    }
+}
+
+// reconcile the balances, keep the report and pause `remit` if it found a discrepancy and auto pause is on
+// `canister_id` is recorded as the one which paused it
+pub fn run(canister_id: Principal, now: u64) -> ReconciliationReport {
+    let report = reconcile(now);
+
+    let auto_pause = crate::SOLVENCY.with(|solvency| {
+        let mut solvency = solvency.borrow_mut();
+        solvency.last_report = Some(report.clone());
+
+        solvency.auto_pause
+    });
+    if auto_pause && !report.discrepancies.is_empty() && !lib::pause::is_paused(Operation::Remit) {
+        lib::pause::set_paused(
+            Operation::Remit,
+            true,
+            format!(
+                "SOLVENCY_DISCREPANCY:{} of {} assets",
+                report.discrepancies.len(),
+                report.assets_checked
+            ),
+            canister_id,
+            now,
+        );
+    }
+
+    report
+}
+
+// has to be started again after every upgrade as timers aren't kept
+pub fn start_reconciliation_timer() {
+    ic_cdk_timers::set_timer_interval(RECONCILIATION_INTERVAL, || {
+        run(ic_cdk::id(), ic_cdk::api::time());
+    });
+}
+
+// record the balance of a data collection canister read from the Locker of a chain
+// it should be read at the block of the latest event published, so the events it reflects were all received
+pub fn report_locker_balance(asset: Asset, dc_canister: Principal, balance: Nat, now: u64) {
+    crate::SOLVENCY.with(|solvency| {
+        solvency.borrow_mut().locker_balances.insert(
+            (asset, dc_canister),
+            LockerBalance {
+                balance,
+                reported_at: now,
+            },
+        )
+    });
+}
+
+pub fn get_locker_balances() -> Vec<(Asset, Principal, LockerBalance)> {
+    crate::SOLVENCY.with(|solvency| {
+        solvency
+            .borrow()
+            .locker_balances
+            .iter()
+            .map(|((asset, dc_canister), locker_balance)| {
+                (asset.clone(), *dc_canister, locker_balance.clone())
+            })
+            .collect()
+    })
+}
+
+pub fn set_auto_pause(auto_pause: bool) {
+    crate::SOLVENCY.with(|solvency| solvency.borrow_mut().auto_pause = auto_pause);
+}
+
+pub fn get_auto_pause() -> bool {
+    crate::SOLVENCY.with(|solvency| solvency.borrow().auto_pause)
+}
+
+pub fn get_last_report() -> Option<ReconciliationReport> {
+    crate::SOLVENCY.with(|solvency| solvency.borrow().last_report.clone())
+}
+
+pub fn get_state() -> SolvencyState {
+    crate::SOLVENCY.with(|solvency| solvency.borrow().clone())
+}
+
+pub fn restore_state(state: SolvencyState) {
+    crate::SOLVENCY.with(|solvency| *solvency.borrow_mut() = state);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deposit(transaction_hash: &str, block_hash: &str) -> lib::DataModel {
+        lib::DataModel::try_from(lib::Event {
+            event_name: String::from("FundsDeposited"),
+            canister_id: String::from("bkyz2-fmaaa-aaaaa-qaaaq-cai"),
+            account: String::from("0x9C81E8F60a9B8743678F1b6Ae893Cc72c6Bc6840"),
+            amount: candid::Int::from(100),
+            chain: String::from("ethereum:5"),
+            token: String::from("0xB24a30A3971e4d9bf771BDc81435c25EA69A445c"),
+            transaction_hash: Some(transaction_hash.to_string()),
+            block_hash: Some(block_hash.to_string()),
+            log_index: Some(0),
+            nonce: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn discrepancies_pause_remit_when_auto_pause_is_on() {
+        let dc_canister = Principal::from_text("bkyz2-fmaaa-aaaaa-qaaaq-cai").unwrap();
+        let chain: lib::Chain = String::from("ethereum:5").try_into().unwrap();
+        lib::chain::register_chain(
+            chain.clone(),
+            lib::chain::ChainMetadata {
+                name: String::from("Ethereum Goerli"),
+                finality_depth: 2,
+                locker_address: None,
+            },
+        );
+        let credited = deposit("0x01", "0xb1");
+        crate::deposits::receive(credited.clone(), dc_canister, 0).unwrap();
+        crate::deposits::confirm_block(chain, String::from("0xb1"), 2, 0).unwrap();
+
+        let report = run(dc_canister, 1);
+        assert_eq!(report.assets_checked, 1);
+        assert_eq!(report.discrepancies, vec![]);
+
+        // the Locker holds the pool along with the deposits which aren't final yet
+        crate::deposits::receive(deposit("0x02", "0xb2"), dc_canister, 2).unwrap();
+        report_locker_balance(credited.asset(), dc_canister, Nat::from(200u64), 2);
+        assert_eq!(run(dc_canister, 3).discrepancies, vec![]);
+
+        // funds which left the Locker without the canister seeing them
+        report_locker_balance(credited.asset(), dc_canister, Nat::from(160u64), 4);
+        assert_eq!(run(dc_canister, 5).discrepancies.len(), 1);
+        assert!(!lib::pause::is_paused(Operation::Remit));
+
+        set_auto_pause(true);
+        let report = run(dc_canister, 6);
+        assert_eq!(
+            report.discrepancies,
+            vec![Discrepancy {
+                asset: credited.asset(),
+                dc_canister,
+                balances: AssetBalances {
+                    pool: Nat::from(100u64),
+                    available: Nat::from(100u64),
+                    pending: Nat::from(100u64),
+                    locker: Some(Nat::from(160u64)),
+                    ..Default::default()
+                },
+            }]
+        );
+        assert!(lib::pause::is_paused(Operation::Remit));
+        assert_eq!(get_last_report(), Some(report));
+    }
+
+    #[test]
+    fn a_pool_opened_with_a_shortfall_is_a_discrepancy() {
+        let balances = AssetBalances {
+            pool: Nat::from(100u64),
+            available: Nat::from(90u64),
+            opening_surplus: Nat::from(10u64),
+            ..Default::default()
+        };
+        assert!(balances.is_solvent());
+
+        let balances = AssetBalances {
+            pool: Nat::from(90u64),
+            available: Nat::from(100u64),
+            opening_shortfall: Nat::from(10u64),
+            ..Default::default()
+        };
+        assert!(!balances.is_solvent());
+    }
+}
@@ -0,0 +1,354 @@
+// every remittance is a withdrawal ticket identified by the nonce it is signed with
+// the withdrawal or cancellation of a remittance on chain resolves exactly one ticket
+use candid::{CandidType, Nat, Principal};
+use lib::{remittance::SigningMode, CcampError};
+use serde_derive::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+
+use crate::{
+    ledger::{self, EntryKind, LedgerAccount},
+    remittance::WithheldBalanceStore,
+    utils::vec_u8_to_string,
+};
+
+#[derive(Clone, Debug, CandidType, Deserialize, PartialEq)]
+pub struct WithdrawalTicket {
+    pub nonce: u64,
+    pub token: lib::Address,
+    pub chain: lib::Chain,
+    pub account: lib::Address,
+    pub dc_canister: Principal,
+    pub amount: Nat,
+    // none while the signature is being made, the amount is reserved in the meantime
+    pub signature: Option<String>,
+    // the hash of the withdrawal message which is signed
+    pub hash: String,
+    // the version of the key which made the signature
+    pub key_version: u32,
+    // timestamp in nanoseconds
+    pub created_at: u64,
+}
+impl WithdrawalTicket {
+    fn matches(
+        &self,
+        token: &lib::Address,
+        chain: &lib::Chain,
+        account: &lib::Address,
+        dc_canister: &Principal,
+        amount: &Nat,
+    ) -> bool {
+        self.token == *token
+            && self.chain == *chain
+            && self.account == *account
+            && self.dc_canister == *dc_canister
+            && self.amount == *amount
+    }
+
+    fn available(&self) -> LedgerAccount {
+        LedgerAccount::Available {
+            account: self.account.clone(),
+            dc_canister: self.dc_canister,
+        }
+    }
+
+    fn withheld(&self) -> LedgerAccount {
+        LedgerAccount::Withheld {
+            account: self.account.clone(),
+            dc_canister: self.dc_canister,
+        }
+    }
+
+    // the postings moving the amount of the ticket between two accounts
+    fn postings(&self, from: LedgerAccount, to: LedgerAccount) -> Vec<ledger::Posting> {
+        vec![
+            ledger::debit(&self.token, &self.chain, from, self.amount.clone()),
+            ledger::credit(&self.token, &self.chain, to, self.amount.clone()),
+        ]
+    }
+}
+
+pub type WithdrawalTicketStore = BTreeMap<u64, WithdrawalTicket>;
+
+pub fn get(nonce: u64) -> Option<WithdrawalTicket> {
+    crate::TICKETS.with(|tickets| tickets.borrow().get(&nonce).cloned())
+}
+
+pub fn contains(nonce: u64) -> bool {
+    crate::TICKETS.with(|tickets| tickets.borrow().contains_key(&nonce))
+}
+
+// whether a ticket of the amount is outstanding, signed or not
+pub fn is_outstanding(
+    token: &lib::Address,
+    chain: &lib::Chain,
+    account: &lib::Address,
+    dc_canister: &Principal,
+    amount: &Nat,
+) -> bool {
+    crate::TICKETS.with(|tickets| {
+        tickets
+            .borrow()
+            .values()
+            .any(|ticket| ticket.matches(token, chain, account, dc_canister, amount))
+    })
+}
+
+// the tickets of an account which weren't withdrawn or cancelled yet
+pub fn get_tickets(account: &lib::Address) -> Vec<WithdrawalTicket> {
+    crate::TICKETS.with(|tickets| {
+        tickets
+            .borrow()
+            .values()
+            .filter(|ticket| ticket.account == *account)
+            .cloned()
+            .collect()
+    })
+}
+
+// reserve the amount of a new ticket by moving it from the available to the withheld balance
+// nothing changes if the available balance doesn't cover it
+pub fn open(ticket: WithdrawalTicket) -> Result<(), CcampError> {
+    ledger::post(
+        EntryKind::Remittance,
+        Some(ticket.nonce.to_string()),
+        ticket.postings(ticket.available(), ticket.withheld()),
+        ticket.created_at,
+    )?;
+    crate::TICKETS.with(|tickets| tickets.borrow_mut().insert(ticket.nonce, ticket));
+
+    Ok(())
+}
+
+// the amount stays withheld along with the signature until the ticket is withdrawn or cancelled
+pub fn set_signature(nonce: u64, signature: String) {
+    crate::TICKETS.with(|tickets| {
+        if let Some(ticket) = tickets.borrow_mut().get_mut(&nonce) {
+            ticket.signature = Some(signature);
+        }
+    })
+}
+
+// give the reserved amount back to the available balance e.g when the signature couldn't be made
+pub fn release(nonce: u64, now: u64) {
+    let ticket = crate::TICKETS
+        .with(|tickets| tickets.borrow_mut().remove(&nonce))
+        .expect("TICKET_NOT_FOUND");
+
+    // the withheld balance holds at least the amount of every ticket so this can't fail
+    if let Err(error) = ledger::post(
+        EntryKind::Release,
+        Some(nonce.to_string()),
+        ticket.postings(ticket.withheld(), ticket.available()),
+        now,
+    ) {
+        panic!("{}", error);
+    }
+}
+
+// the signed ticket a withdrawal or cancellation seen on chain refers to by its nonce
+// the rest of the event has to match the ticket as well
+pub fn find_signed(
+    nonce: u64,
+    token: &lib::Address,
+    chain: &lib::Chain,
+    account: &lib::Address,
+    dc_canister: &Principal,
+    amount: &Nat,
+) -> Result<WithdrawalTicket, CcampError> {
+    get(nonce)
+        .filter(|ticket| ticket.signature.is_some())
+        .filter(|ticket| ticket.matches(token, chain, account, dc_canister, amount))
+        .ok_or(CcampError::UnmatchedWithdrawalTicket { nonce })
+}
+
+// remove the signed ticket a withdrawal or cancellation seen on chain refers to
+pub fn resolve(
+    nonce: u64,
+    token: &lib::Address,
+    chain: &lib::Chain,
+    account: &lib::Address,
+    dc_canister: &Principal,
+    amount: &Nat,
+) -> Result<WithdrawalTicket, CcampError> {
+    let ticket = find_signed(nonce, token, chain, account, dc_canister, amount)?;
+    crate::TICKETS.with(|tickets| tickets.borrow_mut().remove(&nonce));
+
+    Ok(ticket)
+}
+
+// the keys which made the signatures of the outstanding tickets
+pub fn versions_in_use() -> HashSet<u32> {
+    crate::TICKETS.with(|tickets| {
+        tickets
+            .borrow()
+            .values()
+            .map(|ticket| ticket.key_version)
+            .collect()
+    })
+}
+
+// turn the withheld entries kept before tickets into tickets
+// their signatures were made over the personal-sign hash whichever signing mode is in use now
+// an entry whose hash can't be made fails the migration
+// `created_at` gives the time an entry was withheld at so the tickets keep their order, it defaults to `now`
+pub fn migrate(
+    withheld: WithheldBalanceStore,
+    created_at: impl Fn(u64) -> Option<u64>,
+    now: u64,
+) -> Result<WithdrawalTicketStore, CcampError> {
+    withheld
+        .into_iter()
+        .map(
+            |((token, chain, account, dc_canister, amount), withheld_account)| {
+                let hash = crate::remittance::hash_withdrawal(
+                    &SigningMode::PersonalSign,
+                    withheld_account.nonce,
+                    &amount,
+                    &account.to_string(),
+                    &chain,
+                    &dc_canister.to_string(),
+                    &token.to_string(),
+                )?;
+
+                let ticket = WithdrawalTicket {
+                    nonce: withheld_account.nonce,
+                    token,
+                    chain,
+                    account,
+                    dc_canister,
+                    amount,
+                    signature: Some(withheld_account.signature),
+                    hash: format!("0x{}", vec_u8_to_string(&hash)),
+                    key_version: withheld_account.key_version,
+                    created_at: created_at(withheld_account.nonce).unwrap_or(now),
+                };
+                Ok((ticket.nonce, ticket))
+            },
+        )
+        .collect()
+}
+
+pub fn get_state() -> WithdrawalTicketStore {
+    crate::TICKETS.with(|tickets| tickets.borrow().clone())
+}
+
+pub fn restore_state(state: WithdrawalTicketStore) {
+    crate::TICKETS.with(|tickets| *tickets.borrow_mut() = state);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remittance::WithheldAccount;
+
+    fn ticket(nonce: u64, created_at: u64, signature: Option<&str>) -> WithdrawalTicket {
+        WithdrawalTicket {
+            nonce,
+            token: String::from("0xB24a30A3971e4d9bf771BDc81435c25EA69A445c")
+                .try_into()
+                .unwrap(),
+            chain: String::from("ethereum:5").try_into().unwrap(),
+            account: String::from("0x9C81E8F60a9B8743678F1b6Ae893Cc72c6Bc6840")
+                .try_into()
+                .unwrap(),
+            dc_canister: Principal::from_text("bkyz2-fmaaa-aaaaa-qaaaq-cai").unwrap(),
+            amount: Nat::from(100u64),
+            signature: signature.map(String::from),
+            hash: String::new(),
+            key_version: 1,
+            created_at,
+        }
+    }
+
+    #[test]
+    fn withdrawals_resolve_the_signed_ticket_of_their_nonce() {
+        // two remittances of the same amount along with one whose signature is being made
+        let tickets = [
+            ticket(7, 2, Some("0x02")),
+            ticket(9, 1, Some("0x01")),
+            ticket(3, 0, None),
+        ];
+        restore_state(
+            tickets
+                .iter()
+                .map(|ticket| (ticket.nonce, ticket.clone()))
+                .collect(),
+        );
+        let resolve_nonce = |nonce: u64, amount: u64| {
+            let t = &tickets[0];
+            resolve(
+                nonce,
+                &t.token,
+                &t.chain,
+                &t.account,
+                &t.dc_canister,
+                &Nat::from(amount),
+            )
+            .map(|ticket| ticket.nonce)
+        };
+
+        // the ticket is only resolved by an event which matches it
+        assert_eq!(
+            resolve_nonce(7, 50),
+            Err(CcampError::UnmatchedWithdrawalTicket { nonce: 7 })
+        );
+        assert_eq!(resolve_nonce(7, 100), Ok(7));
+        assert_eq!(
+            resolve_nonce(7, 100),
+            Err(CcampError::UnmatchedWithdrawalTicket { nonce: 7 })
+        );
+        assert_eq!(
+            resolve_nonce(3, 100),
+            Err(CcampError::UnmatchedWithdrawalTicket { nonce: 3 })
+        );
+        assert!(contains(9));
+        assert!(contains(3));
+    }
+
+    #[test]
+    fn withheld_entries_are_migrated_into_tickets() {
+        let tickets = [ticket(7, 3, Some("0x07")), ticket(9, 1, Some("0x09"))];
+        let withheld: WithheldBalanceStore = tickets
+            .iter()
+            .map(|ticket| {
+                (
+                    (
+                        ticket.token.clone(),
+                        ticket.chain.clone(),
+                        ticket.account.clone(),
+                        ticket.dc_canister,
+                        // entries of the same account were keyed by their amount
+                        Nat::from(ticket.nonce),
+                    ),
+                    WithheldAccount {
+                        balance: Nat::from(ticket.nonce),
+                        signature: ticket.signature.clone().unwrap(),
+                        nonce: ticket.nonce,
+                        key_version: 1,
+                    },
+                )
+            })
+            .collect();
+        // the time each entry was withheld at, the entries which aren't found are created at the upgrade
+        let created_at = |nonce: u64| (nonce == 9).then_some(1);
+
+        // the entries were signed over the personal-sign hash, which needs no locker
+        let migrated = migrate(withheld, created_at, 5).unwrap();
+        for (nonce, created_at) in [(7, 5), (9, 1)] {
+            let ticket = &migrated[&nonce];
+            let hash = crate::remittance::hash_withdrawal(
+                &SigningMode::PersonalSign,
+                nonce,
+                &ticket.amount,
+                &ticket.account.to_string(),
+                &ticket.chain,
+                &ticket.dc_canister.to_string(),
+                &ticket.token.to_string(),
+            )
+            .unwrap();
+            assert_eq!(ticket.hash, format!("0x{}", vec_u8_to_string(&hash)));
+            assert_eq!(ticket.created_at, created_at);
+            assert_eq!(ticket.amount, Nat::from(nonce));
+        }
+    }
+}
@@ -1,29 +1,9 @@
 //! deprecated use the one from lib package instead
 
-pub fn string_to_vec_u8(str: &str) -> Vec<u8> {
-    let starts_from: usize;
-    if str.starts_with("0x") {
-        starts_from = 2;
-    } else {
-        starts_from = 0;
-    }
-
-    (starts_from..str.len())
-        .step_by(2)
-        .map(|i| u8::from_str_radix(&str[i..i + 2], 16).unwrap())
-        .collect::<Vec<u8>>()
-}
-
-pub fn vec_u8_to_string(vec: &Vec<u8>) -> String {
+pub fn vec_u8_to_string(vec: &[u8]) -> String {
     vec.iter()
         .map(|r| format!("{:02x}", r))
         .collect::<Vec<String>>()
         .join("")
         .to_string()
 }
-
-pub fn remove_leading(vec: &Vec<u8>, element: u8) -> Vec<u8> {
-    let start = vec.iter().position(|&x| x != element).unwrap();
-    let result = &vec[start..];
-    result.to_vec()
-}
@@ -18,13 +18,26 @@ contract Locker is Initializable, UUPSUpgradeable, OwnableUpgradeable, Reentranc
     address public remittanceCanister;
     address ZER0_ADDRESS = 0x0000000000000000000000000000000000000000;
 
+    // the EIP-712 domain and type of the withdrawals signed by a remittance canister in its Eip712 signing mode
+    // they match LOCKER_DOMAIN_NAME, LOCKER_DOMAIN_VERSION and WITHDRAWAL_TYPE of the canisters
+    bytes32 constant DOMAIN_TYPEHASH = keccak256("EIP712Domain(string name,string version,uint256 chainId,address verifyingContract)");
+    bytes32 constant DOMAIN_NAME_HASH = keccak256("CCAMP Locker");
+    bytes32 constant DOMAIN_VERSION_HASH = keccak256("1");
+    bytes32 constant WITHDRAWAL_TYPEHASH = keccak256("Withdrawal(uint256 nonce,uint256 amount,address account,string chain,string canisterId,address token)");
+
     mapping(bytes => bool) usedSignatures;
     mapping(bytes32 => mapping(address => uint256)) public canisters; //keccak256(principal) => tokenAddress => amountDeposited
+    // the addresses of the rotated keys of the remittance canister, which sign for a single data collection canister
+    // a rotated address is added before the canister signs with it and removed once its withdrawals are settled
+    mapping(bytes32 => mapping(address => bool)) public canisterSigners; //keccak256(principal) => signer => isSigner
 
     event FundsDeposited(string canisterId, address indexed account, uint amount, string chain, address token);
-    event FundsWithdrawn(string canisterId, address indexed account, uint amount, string chain, address token);
-    event WithdrawCanceled(string canisterId, address indexed account, uint amount, string chain, address token);
+    // the nonce identifies the withdrawal ticket of the remittance canister which is resolved
+    event FundsWithdrawn(string canisterId, address indexed account, uint amount, string chain, address token, uint nonce);
+    event WithdrawCanceled(string canisterId, address indexed account, uint amount, string chain, address token, uint nonce);
     event UpdateRemittanceCanister(address remittanceCanister);
+    event SignerAdded(string canisterId, address signer);
+    event SignerRemoved(string canisterId, address signer);
 
     function depositTokens(string calldata _canisterId) public nonReentrant payable {
         uint256 _amount = msg.value;
@@ -65,8 +78,66 @@ contract Locker is Initializable, UUPSUpgradeable, OwnableUpgradeable, Reentranc
         emit UpdateRemittanceCanister(_remittanceCanister);
     }
 
-    function validateSignature(bytes32 dataHash, bytes calldata signature) internal view returns (bool isValid) {
-        isValid = VerifySignature.verify(remittanceCanister, dataHash, signature);
+    function addSigner(string calldata _canisterId, address _signer) public onlyOwner {
+        require(_signer != ZER0_ADDRESS, "INVALID_SIGNER");
+
+        canisterSigners[keccak256(bytes(_canisterId))][_signer] = true;
+        emit SignerAdded(_canisterId, _signer);
+    }
+
+    function removeSigner(string calldata _canisterId, address _signer) public onlyOwner {
+        canisterSigners[keccak256(bytes(_canisterId))][_signer] = false;
+        emit SignerRemoved(_canisterId, _signer);
+    }
+
+    // the remittance canister address signs for every data collection canister
+    function isSigner(bytes32 _canisterIdHash, address _signer) public view returns (bool) {
+        if (_signer == ZER0_ADDRESS) {
+            return false;
+        }
+
+        return _signer == remittanceCanister || canisterSigners[_canisterIdHash][_signer];
+    }
+
+    function domainSeparator() public view returns (bytes32) {
+        return keccak256(abi.encode(DOMAIN_TYPEHASH, DOMAIN_NAME_HASH, DOMAIN_VERSION_HASH, block.chainid, address(this)));
+    }
+
+    function withdrawalTypedDataHash(
+        uint _nonce,
+        uint _amount,
+        address _account,
+        bytes32 _canisterIdHash,
+        address _token
+    ) public view returns (bytes32) {
+        bytes32 structHash = keccak256(
+            abi.encode(WITHDRAWAL_TYPEHASH, _nonce, _amount, _account, keccak256(bytes(chainId)), _canisterIdHash, _token)
+        );
+        return keccak256(abi.encodePacked("\x19\x01", domainSeparator(), structHash));
+    }
+
+    // a withdrawal is signed by the remittance canister either as a personal message of the packed parameters
+    // or as EIP-712 typed data, depending on the signing mode of the canister
+    function validateSignature(
+        uint _nonce,
+        uint _amount,
+        address _account,
+        string calldata _canisterId,
+        address _token,
+        bytes calldata _signature
+    ) internal view returns (bool isValid) {
+        bytes32 canisterIdHash = keccak256(bytes(_canisterId));
+        address signer = VerifySignature.recoverSigner(
+            keccak256(abi.encodePacked(_nonce, _amount, _account, chainId, _canisterId, _token)),
+            _signature
+        );
+        if (isSigner(canisterIdHash, signer)) {
+            return true;
+        }
+
+        bytes32 typedDataHash = withdrawalTypedDataHash(_nonce, _amount, _account, canisterIdHash, _token);
+        signer = VerifySignature.recoverDigestSigner(typedDataHash, _signature);
+        isValid = isSigner(canisterIdHash, signer);
     }
 
     function getBalance(string calldata _canisterId, address _token) public view returns (uint256 balance) {
@@ -96,12 +167,11 @@ contract Locker is Initializable, UUPSUpgradeable, OwnableUpgradeable, Reentranc
         require(getBalance(_canisterId, _token) >= _amount, "WITHDRAW_AMOUNT > CANISTER_TOKEN_BALANCE");
         require(!usedSignatures[_signature], "USED_SIGNATURE");
 
-        bytes32 dataHash = keccak256(abi.encodePacked(_nonce, _amount, msg.sender, chainId, _canisterId, _token));
-        require(validateSignature(dataHash, _signature), "INVALID_SIGNATURE");
+        require(validateSignature(_nonce, _amount, msg.sender, _canisterId, _token, _signature), "INVALID_SIGNATURE");
 
         usedSignatures[_signature] = true;
 
-        emit FundsWithdrawn(_canisterId, msg.sender, _amount, chainId, _token);
+        emit FundsWithdrawn(_canisterId, msg.sender, _amount, chainId, _token, _nonce);
         bool success = IERC20Upgradeable(_token).transfer(_recipient, _amount);
         return success;
     }
@@ -120,12 +190,11 @@ contract Locker is Initializable, UUPSUpgradeable, OwnableUpgradeable, Reentranc
         require(getBalance(_canisterId, _token) >= _amount, "WITHDRAW_AMOUNT > CANISTER_TOKEN_BALANCE");
         require(!usedSignatures[_signature], "USED_SIGNATURE");
 
-        bytes32 dataHash = keccak256(abi.encodePacked(_nonce, _amount, msg.sender, chainId, _canisterId, _token));
-        require(validateSignature(dataHash, _signature), "INVALID_SIGNATURE");
+        require(validateSignature(_nonce, _amount, msg.sender, _canisterId, _token, _signature), "INVALID_SIGNATURE");
 
         usedSignatures[_signature] = true;
 
-        emit FundsWithdrawn(_canisterId, msg.sender, _amount, chainId, _token);
+        emit FundsWithdrawn(_canisterId, msg.sender, _amount, chainId, _token, _nonce);
         (bool success, bytes memory data) = payable(_recipient).call{value: _amount}("");
     
         return success;
@@ -164,12 +233,11 @@ contract Locker is Initializable, UUPSUpgradeable, OwnableUpgradeable, Reentranc
         require(!usedSignatures[_signature], "USED_SIGNATURE");
 
         // validate the signature
-        bytes32 dataHash = keccak256(abi.encodePacked(_nonce, _amount, msg.sender, chainId, _canisterId, _token));
-        require(validateSignature(dataHash, _signature), "INVALID_SIGNATURE");
+        require(validateSignature(_nonce, _amount, msg.sender, _canisterId, _token, _signature), "INVALID_SIGNATURE");
 
         // mark signature as used
         usedSignatures[_signature] = true;
-        emit WithdrawCanceled(_canisterId, msg.sender, _amount,chainId, _token);
+        emit WithdrawCanceled(_canisterId, msg.sender, _amount, chainId, _token, _nonce);
     }
 
     /// @dev required by the OZ UUPS module
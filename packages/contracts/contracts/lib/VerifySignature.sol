@@ -25,6 +25,20 @@ library VerifySignature {
         return signer == recovered;
     }
 
+    // recover the signer of a personal message
+    function recoverSigner(bytes32 message, bytes memory signature) public pure returns (address) {
+        (bytes32 r, bytes32 s, uint8 v) = splitSignature(signature);
+
+        return ecrecover(getEthSignedMessageHash(message), v, r, s);
+    }
+
+    // recover the signer of a digest which is signed as is e.g an EIP-712 typed data hash
+    function recoverDigestSigner(bytes32 digest, bytes memory signature) public pure returns (address) {
+        (bytes32 r, bytes32 s, uint8 v) = splitSignature(signature);
+
+        return ecrecover(digest, v, r, s);
+    }
+
     function splitSignature(bytes memory sig) public pure returns (bytes32 r, bytes32 s, uint8 v) {
         require(sig.length == 65, "invalid signature length");
 
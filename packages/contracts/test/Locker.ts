@@ -1,3 +1,4 @@
+import { SignerWithAddress } from '@nomiclabs/hardhat-ethers/signers';
 import assert from 'assert';
 import { expect } from 'chai';
 import { BigNumberish, Contract, ethers, Signer } from 'ethers';
@@ -13,6 +14,7 @@ import {
 import {
 	fetchEventArgsFromTx,
 	generateHashAndSignature,
+	generateTypedDataSignature,
 	getERC20Token,
 	loadLockerContract,
 	mintTokenAndApproveLocker,
@@ -111,6 +113,8 @@ describe('Locker', function () {
 		expect(canisterId).to.equal(remittanceCanisterPrincipal);
 		expect(account).to.equal(recipient).equal(fundsRecipient);
 		expect(amount.toString()).to.equal(depositedAmount.toString());
+		// the nonce identifies the ticket of the remittance canister
+		expect(withdrawEvent?.nonce.toString()).to.equal(`${nonce}`);
 
 		// validate funds were sent to recipient
 		expect(recipientPostBalance.toString()).to.equal(
@@ -118,6 +122,85 @@ describe('Locker', function () {
 		);
 	});
 
+	it('should unlock funds with a valid typed data signature', async () => {
+		const depositedAmount = ethers.utils.parseEther('0.5');
+		const recipient = await adminSigner.getAddress();
+
+		await lockerContract.depositFunds(
+			remittanceCanisterPrincipal,
+			depositedAmount,
+			testTokenAddress
+		);
+
+		const recipientPreBalance = await tokenContract.balanceOf(recipient);
+
+		// sign the withdrawal as eip712 typed data
+		const { hash, signature } = await generateTypedDataSignature(
+			nonce,
+			depositedAmount,
+			recipient,
+			chainId,
+			remittanceCanisterPrincipal,
+			testTokenAddress,
+			lockerContract.address,
+			canisterSigner as SignerWithAddress
+		);
+		const contractHash = await lockerContract.withdrawalTypedDataHash(
+			nonce,
+			depositedAmount,
+			recipient,
+			hEthers.utils.keccak256(encoder.encode(remittanceCanisterPrincipal)),
+			testTokenAddress
+		);
+		expect(contractHash).to.equal(hash);
+
+		await lockerContract.withdraw(
+			remittanceCanisterPrincipal,
+			testTokenAddress,
+			nonce,
+			depositedAmount,
+			signature
+		);
+		const recipientPostBalance = await tokenContract.balanceOf(recipient);
+
+		expect(recipientPostBalance.toString()).to.equal(
+			(+recipientPreBalance + +depositedAmount).toString()
+		);
+	});
+
+	it('should revert when a typed data signature is made for another locker', async () => {
+		const depositedAmount = ethers.utils.parseEther('0.5');
+		const recipient = await adminSigner.getAddress();
+
+		await lockerContract.depositFunds(
+			remittanceCanisterPrincipal,
+			depositedAmount,
+			testTokenAddress
+		);
+
+		// the domain of the signature names another verifying contract
+		const { signature } = await generateTypedDataSignature(
+			nonce,
+			depositedAmount,
+			recipient,
+			chainId,
+			remittanceCanisterPrincipal,
+			testTokenAddress,
+			testTokenAddress,
+			canisterSigner as SignerWithAddress
+		);
+
+		await expect(
+			lockerContract.withdraw(
+				remittanceCanisterPrincipal,
+				testTokenAddress,
+				nonce,
+				depositedAmount,
+				signature
+			)
+		).to.be.revertedWith(ERROR_MESSAGES.INVALID_SIGNATURE);
+	});
+
 	it('should revert when unlocking funds with invalid signature from wrong amount', async () => {
 		const recipient = await adminSigner.getAddress();
 
@@ -298,6 +381,7 @@ describe('Locker', function () {
 		expect(account).to.equal(adminAccount);
 		expect(amountCanceled).to.equal(depositedAmount);
 		expect(signatureHash).to.equal(hEthers.utils.keccak256(signature));
+		expect(onCancelEvent?.nonce.toString()).to.equal(`${nonce}`);
 
 		// try to withdraw using this signature again and get an error
 			await expect(
@@ -325,4 +409,104 @@ describe('Locker', function () {
 
 		expect(setCanisterEvent).to.equal(newCanisterAddress);
 	});
+
+	it('should accept withdrawals signed by a rotated signer of the canister until it is removed', async () => {
+		const depositedAmount = ethers.utils.parseEther('0.5');
+		const recipient = await adminSigner.getAddress();
+		const rotatedSigner = allSigners[4];
+		const otherCanisterPrincipal = 'bkyz2-fmaaa-aaaaa-qaaaq-cai';
+
+		await lockerContract.depositFunds(
+			remittanceCanisterPrincipal,
+			depositedAmount,
+			testTokenAddress
+		);
+		await lockerContract.depositFunds(
+			otherCanisterPrincipal,
+			depositedAmount,
+			testTokenAddress
+		);
+		await lockerContract.addSigner(
+			remittanceCanisterPrincipal,
+			await rotatedSigner.getAddress()
+		);
+
+		// the rotated signer only signs for the canister it was added for
+		const { signature: otherSignature } = await generateHashAndSignature(
+			nonce,
+			depositedAmount,
+			recipient,
+			chainId,
+			otherCanisterPrincipal,
+			testTokenAddress,
+			rotatedSigner
+		);
+		await expect(
+			lockerContract.withdraw(
+				otherCanisterPrincipal,
+				testTokenAddress,
+				nonce,
+				depositedAmount,
+				otherSignature
+			)
+		).to.revertedWith(ERROR_MESSAGES.INVALID_SIGNATURE);
+
+		const { signature } = await generateHashAndSignature(
+			nonce,
+			ethers.utils.parseEther('0.2'),
+			recipient,
+			chainId,
+			remittanceCanisterPrincipal,
+			testTokenAddress,
+			rotatedSigner
+		);
+		await lockerContract.withdraw(
+			remittanceCanisterPrincipal,
+			testTokenAddress,
+			nonce,
+			ethers.utils.parseEther('0.2'),
+			signature
+		);
+
+		// the previous address of the canister keeps signing alongside it
+		const { signature: previousSignature } = await generateHashAndSignature(
+			nonce + 1,
+			ethers.utils.parseEther('0.1'),
+			recipient,
+			chainId,
+			remittanceCanisterPrincipal,
+			testTokenAddress,
+			canisterSigner
+		);
+		await lockerContract.withdraw(
+			remittanceCanisterPrincipal,
+			testTokenAddress,
+			nonce + 1,
+			ethers.utils.parseEther('0.1'),
+			previousSignature
+		);
+
+		await lockerContract.removeSigner(
+			remittanceCanisterPrincipal,
+			await rotatedSigner.getAddress()
+		);
+		const { signature: removedSignature } = await generateHashAndSignature(
+			nonce + 2,
+			ethers.utils.parseEther('0.1'),
+			recipient,
+			chainId,
+			remittanceCanisterPrincipal,
+			testTokenAddress,
+			rotatedSigner
+		);
+		await expect(
+			lockerContract.withdraw(
+				remittanceCanisterPrincipal,
+				testTokenAddress,
+				nonce + 2,
+				ethers.utils.parseEther('0.1'),
+				removedSignature
+			)
+		).to.revertedWith(ERROR_MESSAGES.INVALID_SIGNATURE);
+	});
 });
@@ -30,6 +30,47 @@ export async function generateHashAndSignature(
 	return { hash: dataHash, signature };
 }
 
+export async function generateTypedDataSignature(
+	nonce: number,
+	amount: BigNumberish,
+	account: string,
+	chainId: string,
+	canisterId: string,
+	tokenAddress: string,
+	lockerAddress: string,
+	signer: SignerWithAddress
+) {
+	// the domain and type signed by a remittance canister in its eip712 signing mode
+	const domain = {
+		name: 'CCAMP Locker',
+		version: '1',
+		chainId: await getChainId(),
+		verifyingContract: lockerAddress,
+	};
+	const types = {
+		Withdrawal: [
+			{ name: 'nonce', type: 'uint256' },
+			{ name: 'amount', type: 'uint256' },
+			{ name: 'account', type: 'address' },
+			{ name: 'chain', type: 'string' },
+			{ name: 'canisterId', type: 'string' },
+			{ name: 'token', type: 'address' },
+		],
+	};
+	const value = {
+		nonce,
+		amount,
+		account,
+		chain: chainId,
+		canisterId,
+		token: tokenAddress,
+	};
+
+	const hash = hEthers.utils._TypedDataEncoder.hash(domain, types, value);
+	const signature = await signer._signTypedData(domain, types, value);
+	return { hash, signature };
+}
+
 export const getChainId = async () =>
 	await hEthers.provider
 		.getNetwork()
@@ -34,6 +34,7 @@ export function handleFundsWithdrawn(event: FundsWithdrawnEvent): void {
   entity.amount = event.params.amount;
   entity.chain = event.params.chain;
   entity.token = event.params.token;
+  entity.nonce = event.params.nonce;
 
   entity.save();
 }
@@ -47,6 +48,7 @@ export function handleWithdrawCanceled(event: WithdrawCanceledEvent): void {
   entity.amount = event.params.amount;
   entity.chain = event.params.chain;
   entity.token = event.params.token;
+  entity.nonce = event.params.nonce;
 
   entity.save();
 }